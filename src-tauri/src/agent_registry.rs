@@ -72,7 +72,8 @@ pub async fn initialize_registry(_app_handle: AppHandle) {
         agent_type: "claude_code".to_string(),
         config: serde_json::json!({
             "executable_path": "claude-code",
-            "default_args": ["--no-update-check"]
+            "default_args": ["--no-update-check"],
+            "max_concurrent_tasks": 2
         }),
         permissions: AgentPermissions {
             file_read: true,
@@ -89,7 +90,8 @@ pub async fn initialize_registry(_app_handle: AppHandle) {
         agent_type: "gemini_cli".to_string(),
         config: serde_json::json!({
             "executable_path": "gemini",
-            "default_args": []
+            "default_args": [],
+            "max_concurrent_tasks": 2
         }),
         permissions: AgentPermissions {
             file_read: true,
@@ -107,7 +109,8 @@ pub async fn initialize_registry(_app_handle: AppHandle) {
         config: serde_json::json!({
             "openrouter_api_key": "",
             "default_model": "anthropic/claude-3-sonnet",
-            "task_decomposition_enabled": true
+            "task_decomposition_enabled": true,
+            "max_concurrent_tasks": 4
         }),
         permissions: AgentPermissions {
             file_read: true,
@@ -133,6 +136,36 @@ pub fn get_registry() -> &'static AgentRegistry {
     }
 }
 
+fn try_get_registry() -> Option<&'static AgentRegistry> {
+    unsafe {
+        REGISTRY.as_ref()
+    }
+}
+
+/// How many tasks each agent may run at once, from its `max_concurrent_tasks` setting.
+pub async fn get_agent_capacities() -> HashMap<String, usize> {
+    let Some(registry) = try_get_registry() else {
+        return HashMap::new();
+    };
+    let agents = registry.agents.read().await;
+
+    agents
+        .values()
+        .filter_map(|config| {
+            config.config["max_concurrent_tasks"]
+                .as_u64()
+                .map(|capacity| (config.id.clone(), capacity as usize))
+        })
+        .collect()
+}
+
+/// Permissions configured for an agent, if the registry knows it.
+pub async fn get_agent_permissions(agent_id: &str) -> Option<AgentPermissions> {
+    let registry = try_get_registry()?;
+    let agents = registry.agents.read().await;
+    agents.get(agent_id).map(|config| config.permissions.clone())
+}
+
 // Additional agent registry functions for commands.rs
 pub async fn get_agent_status(agent_id: &str) -> Option<AgentStatus> {
     let registry = get_registry();
@@ -176,3 +209,161 @@ pub async fn list_all_agents() -> Result<Vec<AgentStatus>, Box<dyn std::error::E
     
     Ok(agent_statuses)
 }
+
+pub async fn get_agent_stats(agent_id: Option<&str>) -> Result<Vec<AgentStats>, Box<dyn std::error::Error + Send + Sync>> {
+    let tasks = crate::database::get_database().get_tasks(agent_id)?;
+    Ok(compute_agent_stats(&tasks))
+}
+
+/// Group stored tasks by agent and summarise their outcomes overall, per model and per category.
+pub fn compute_agent_stats(tasks: &[TaskResult]) -> Vec<AgentStats> {
+    let mut by_agent: HashMap<String, Vec<&TaskResult>> = HashMap::new();
+    for task in tasks {
+        by_agent.entry(task.agent_type.clone()).or_default().push(task);
+    }
+
+    let mut stats: Vec<AgentStats> = by_agent
+        .into_iter()
+        .map(|(agent_type, agent_tasks)| {
+            let mut by_model: HashMap<String, Vec<&TaskResult>> = HashMap::new();
+            let mut by_category: HashMap<String, Vec<&TaskResult>> = HashMap::new();
+            for task in &agent_tasks {
+                let model = task.metadata.model.clone().unwrap_or_else(|| "unknown".to_string());
+                let category = task.metadata.category.clone().unwrap_or_else(|| "general".to_string());
+                by_model.entry(model).or_default().push(task);
+                by_category.entry(category).or_default().push(task);
+            }
+
+            AgentStats {
+                agent_type,
+                overall: summarise_outcomes(&agent_tasks),
+                by_model: by_model.into_iter().map(|(k, v)| (k, summarise_outcomes(&v))).collect(),
+                by_category: by_category.into_iter().map(|(k, v)| (k, summarise_outcomes(&v))).collect(),
+            }
+        })
+        .collect();
+
+    stats.sort_by(|a, b| a.agent_type.cmp(&b.agent_type));
+    stats
+}
+
+fn summarise_outcomes(tasks: &[&TaskResult]) -> OutcomeStats {
+    let mut stats = OutcomeStats {
+        total_tasks: tasks.len() as u64,
+        ..Default::default()
+    };
+    let mut durations: Vec<f64> = Vec::new();
+
+    for task in tasks {
+        match task.status {
+            TaskStatus::Completed => stats.succeeded += 1,
+            TaskStatus::Failed => stats.failed += 1,
+            _ => {}
+        }
+        if let Some(completed_at) = task.completed_at {
+            durations.push((completed_at - task.created_at).num_milliseconds().max(0) as f64);
+        }
+        stats.total_retries += task.metadata.retries as u64;
+        if let Some(usage) = &task.metadata.usage {
+            stats.prompt_tokens += usage.prompt_tokens;
+            stats.completion_tokens += usage.completion_tokens;
+            stats.cost_usd += usage.cost_usd.unwrap_or(0.0);
+        }
+    }
+
+    let finished = stats.succeeded + stats.failed;
+    if finished > 0 {
+        stats.success_rate = stats.succeeded as f64 / finished as f64;
+    }
+
+    if !durations.is_empty() {
+        durations.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        stats.mean_duration_ms = Some(durations.iter().sum::<f64>() / durations.len() as f64);
+        // Nearest-rank percentile
+        let rank = ((durations.len() as f64) * 0.95).ceil() as usize;
+        stats.p95_duration_ms = Some(durations[rank.saturating_sub(1).min(durations.len() - 1)]);
+    }
+
+    stats
+}
+
+/// Short track-record summary the middle manager can weigh when routing subtasks.
+pub fn format_stats_for_planner(stats: &[AgentStats]) -> String {
+    stats
+        .iter()
+        .filter(|s| s.overall.total_tasks > 0)
+        .map(|s| {
+            let mut categories: Vec<String> = s
+                .by_category
+                .iter()
+                .map(|(category, c)| format!("{} {:.0}% of {}", category, c.success_rate * 100.0, c.total_tasks))
+                .collect();
+            categories.sort();
+            format!(
+                "- {}: {:.0}% success over {} tasks ({})",
+                s.agent_type,
+                s.overall.success_rate * 100.0,
+                s.overall.total_tasks,
+                categories.join(", ")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+
+    fn task(agent: &str, model: &str, status: TaskStatus, duration_ms: i64) -> TaskResult {
+        let created_at = Utc::now();
+        TaskResult {
+            id: uuid::Uuid::new_v4().to_string(),
+            session_id: "session".to_string(),
+            task_description: "task".to_string(),
+            agent_type: agent.to_string(),
+            status,
+            result: None,
+            error: None,
+            created_at,
+            completed_at: Some(created_at + Duration::milliseconds(duration_ms)),
+            metadata: TaskMetadata {
+                model: Some(model.to_string()),
+                category: Some("testing".to_string()),
+                retries: 1,
+                usage: Some(TokenUsage {
+                    prompt_tokens: 10,
+                    completion_tokens: 5,
+                    cost_usd: Some(0.01),
+                }),
+                prompt_templates: vec![],
+                planner_model: None,
+                rule_matches: vec![],
+            },
+        }
+    }
+
+    #[test]
+    fn test_compute_agent_stats() {
+        let mut tasks: Vec<TaskResult> = (1..=19)
+            .map(|i| task("claude_code", "sonnet", TaskStatus::Completed, i * 100))
+            .collect();
+        tasks.push(task("claude_code", "opus", TaskStatus::Failed, 10_000));
+        tasks.push(task("gemini_cli", "gemini", TaskStatus::Completed, 50));
+
+        let stats = compute_agent_stats(&tasks);
+        assert_eq!(stats.len(), 2);
+
+        let claude = &stats[0];
+        assert_eq!(claude.agent_type, "claude_code");
+        assert_eq!(claude.overall.total_tasks, 20);
+        assert_eq!(claude.overall.failed, 1);
+        assert!((claude.overall.success_rate - 0.95).abs() < 1e-9);
+        assert_eq!(claude.overall.p95_duration_ms, Some(1900.0));
+        assert_eq!(claude.overall.total_retries, 20);
+        assert_eq!(claude.overall.prompt_tokens, 200);
+        assert_eq!(claude.by_model["opus"].success_rate, 0.0);
+        assert_eq!(claude.by_category["testing"].total_tasks, 20);
+    }
+}
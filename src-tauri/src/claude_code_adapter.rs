@@ -109,6 +109,7 @@ impl ClaudeCodeAdapter {
                 error: Some("Task not allowed by current permissions".to_string()),
                 created_at: chrono::Utc::now(),
                 completed_at: Some(chrono::Utc::now()),
+                metadata: TaskMetadata::default(),
             });
         }
 
@@ -125,6 +126,7 @@ impl ClaudeCodeAdapter {
             error: None,
             created_at: chrono::Utc::now(),
             completed_at: Some(chrono::Utc::now()),
+            metadata: TaskMetadata::default(),
         })
     }
 
@@ -233,5 +235,6 @@ pub async fn execute_task(request: crate::commands::ExecuteTaskRequest) -> Resul
         error: None,
         created_at: chrono::Utc::now(),
         completed_at: Some(chrono::Utc::now()),
+        metadata: TaskMetadata::default(),
     })
 }
@@ -1,13 +1,29 @@
 use serde::{Deserialize, Serialize};
 use tauri::State;
 use crate::models::*;
-use crate::session_manager::{SessionManager, ConversationMessage};
+use crate::session_manager::{SessionManager, ConversationMessage, SessionDeletion};
+use crate::dag_executor::NodeState;
+use crate::prompt_templates::{self, TemplateInfo};
+use crate::plan_approval::{PendingPlan, PlanEdit, SubtaskEdit};
+use crate::context_window::ConversationSummary;
+use crate::routing_rules::{self, RoutingRuleInfo};
+use crate::plan_preview::PlanPreview;
+use crate::ensemble::EnsembleCandidate;
+use crate::scheduler::QueueSnapshot;
+use crate::session_state::StatusChange;
+use crate::request_runs::{RequestRun, RunProgress, RunResults};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateSessionRequest {
     pub name: String,
     pub project_path: String,
     pub description: Option<String>,
+    #[serde(default)]
+    pub require_plan_approval: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub priority: SessionPriority,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -15,12 +31,27 @@ pub struct ExecuteTaskRequest {
     pub session_id: String,
     pub task_description: String,
     pub agent_type: String, // "claude_code", "gemini_cli", or "middle_manager"
+    /// Plan and route the task without running it.
+    #[serde(default)]
+    pub dry_run: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SendMessageRequest {
     pub session_id: String,
     pub message: String,
+    /// Plan and route the message without running it or adding it to the conversation.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// What running something returned (its result, or the run it was started as), or a
+/// preview of it for dry runs.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum RunResponse<T> {
+    Ran(T),
+    Preview(Box<PlanPreview>),
 }
 
 #[tauri::command]
@@ -34,7 +65,14 @@ pub async fn create_session(
     session_manager: State<'_, SessionManager>,
 ) -> Result<Session, String> {
     session_manager
-        .create_session(request.name, request.project_path, request.description)
+        .create_session(
+            request.name,
+            request.project_path,
+            request.description,
+            request.require_plan_approval,
+            request.tags,
+            request.priority,
+        )
         .await
         .map_err(|e| e.to_string())
 }
@@ -50,7 +88,23 @@ pub async fn get_sessions(
 }
 
 #[tauri::command]
-pub async fn execute_task(request: ExecuteTaskRequest) -> Result<TaskResult, String> {
+pub async fn execute_task(
+    request: ExecuteTaskRequest,
+    session_manager: State<'_, SessionManager>,
+) -> Result<RunResponse<TaskResult>, String> {
+    if request.dry_run {
+        let preview = if request.agent_type == "middle_manager" {
+            session_manager
+                .preview_user_request(&request.session_id, request.task_description)
+                .await
+        } else {
+            session_manager
+                .preview_direct_task(&request.session_id, &request.task_description, &request.agent_type)
+                .await
+        };
+        return preview.map(|preview| RunResponse::Preview(Box::new(preview))).map_err(|e| e.to_string());
+    }
+
     // Route to appropriate agent based on agent_type
     let result = match request.agent_type.as_str() {
         "claude_code" => {
@@ -67,7 +121,7 @@ pub async fn execute_task(request: ExecuteTaskRequest) -> Result<TaskResult, Str
         }
     };
     
-    result
+    result.map(RunResponse::Ran)
 }
 
 #[tauri::command]
@@ -98,14 +152,29 @@ pub async fn list_agents() -> Result<Vec<AgentStatus>, String> {
         .map_err(|e| format!("Failed to list agents: {}", e))
 }
 
+#[tauri::command]
+pub async fn get_agent_stats(agent_id: Option<String>) -> Result<Vec<AgentStats>, String> {
+    crate::agent_registry::get_agent_stats(agent_id.as_deref()).await
+        .map_err(|e| format!("Failed to compute agent statistics: {}", e))
+}
+
 #[tauri::command]
 pub async fn send_message(
     request: SendMessageRequest,
     session_manager: State<'_, SessionManager>,
-) -> Result<Vec<ConversationMessage>, String> {
+) -> Result<RunResponse<RequestRun>, String> {
+    if request.dry_run {
+        return session_manager
+            .preview_user_request(&request.session_id, request.message)
+            .await
+            .map(|preview| RunResponse::Preview(Box::new(preview)))
+            .map_err(|e| e.to_string());
+    }
+
     session_manager
-        .execute_user_request(&request.session_id, request.message)
+        .start_user_request(&request.session_id, request.message)
         .await
+        .map(RunResponse::Ran)
         .map_err(|e| e.to_string())
 }
 
@@ -117,6 +186,287 @@ pub async fn get_conversation_history(
     Ok(session_manager.get_conversation_history(&session_id).await)
 }
 
+#[tauri::command]
+pub async fn pin_message(
+    session_id: String,
+    message_id: String,
+    pinned: bool,
+    session_manager: State<'_, SessionManager>,
+) -> Result<(), String> {
+    session_manager
+        .pin_message(&session_id, &message_id, pinned)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_conversation_summary(
+    session_id: String,
+    session_manager: State<'_, SessionManager>,
+) -> Result<Option<ConversationSummary>, String> {
+    Ok(session_manager.get_conversation_summary(&session_id).await)
+}
+
+#[tauri::command]
+pub async fn get_ensemble_candidates(
+    session_id: String,
+    session_manager: State<'_, SessionManager>,
+) -> Result<Vec<EnsembleCandidate>, String> {
+    session_manager
+        .get_ensemble_candidates(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_plan_status(
+    session_id: String,
+    session_manager: State<'_, SessionManager>,
+) -> Result<Vec<NodeState>, String> {
+    Ok(session_manager.get_plan_status(&session_id).await)
+}
+
+#[tauri::command]
+pub async fn set_plan_approval(
+    session_id: String,
+    required: bool,
+    session_manager: State<'_, SessionManager>,
+) -> Result<(), String> {
+    session_manager
+        .set_plan_approval(&session_id, required)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn set_session_tags(
+    session_id: String,
+    tags: Vec<String>,
+    session_manager: State<'_, SessionManager>,
+) -> Result<(), String> {
+    session_manager
+        .set_session_tags(&session_id, tags)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn set_session_priority(
+    session_id: String,
+    priority: SessionPriority,
+    session_manager: State<'_, SessionManager>,
+) -> Result<(), String> {
+    session_manager
+        .set_session_priority(&session_id, priority)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_scheduler_queue(
+    session_manager: State<'_, SessionManager>,
+) -> Result<QueueSnapshot, String> {
+    Ok(session_manager.get_scheduler_queue().await)
+}
+
+#[tauri::command]
+pub async fn get_pending_plan(
+    session_id: String,
+    session_manager: State<'_, SessionManager>,
+) -> Result<Option<PendingPlan>, String> {
+    Ok(session_manager.get_pending_plan(&session_id).await)
+}
+
+#[tauri::command]
+pub async fn update_pending_subtask(
+    session_id: String,
+    subtask_id: String,
+    edit: SubtaskEdit,
+    session_manager: State<'_, SessionManager>,
+) -> Result<PendingPlan, String> {
+    session_manager
+        .edit_pending_plan(&session_id, PlanEdit::Update { subtask_id, edit })
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn reassign_pending_subtask(
+    session_id: String,
+    subtask_id: String,
+    agent: String,
+    session_manager: State<'_, SessionManager>,
+) -> Result<PendingPlan, String> {
+    session_manager
+        .edit_pending_plan(&session_id, PlanEdit::Reassign { subtask_id, agent })
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn set_pending_subtask_dependencies(
+    session_id: String,
+    subtask_id: String,
+    dependencies: Vec<String>,
+    session_manager: State<'_, SessionManager>,
+) -> Result<PendingPlan, String> {
+    session_manager
+        .edit_pending_plan(&session_id, PlanEdit::SetDependencies { subtask_id, dependencies })
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn approve_plan(
+    session_id: String,
+    session_manager: State<'_, SessionManager>,
+) -> Result<RequestRun, String> {
+    session_manager
+        .start_plan_approval(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn reject_plan(
+    session_id: String,
+    reason: Option<String>,
+    session_manager: State<'_, SessionManager>,
+) -> Result<(), String> {
+    session_manager
+        .reject_plan(&session_id, reason)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_usage_summary(
+    session_id: String,
+    session_manager: State<'_, SessionManager>,
+) -> Result<UsageSummary, String> {
+    session_manager
+        .get_usage_summary(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn set_session_budget(
+    session_id: String,
+    limit_usd: f64,
+    warn_threshold: Option<f64>,
+) -> Result<(), String> {
+    crate::database::get_database()
+        .set_budget("session", &session_id, &budget_from(limit_usd, warn_threshold)?)
+        .map_err(|e| format!("Failed to store session budget: {}", e))
+}
+
+#[tauri::command]
+pub async fn set_project_budget(
+    project_path: String,
+    limit_usd: f64,
+    warn_threshold: Option<f64>,
+) -> Result<(), String> {
+    crate::database::get_database()
+        .set_budget("project", &project_path, &budget_from(limit_usd, warn_threshold)?)
+        .map_err(|e| format!("Failed to store project budget: {}", e))
+}
+
+fn budget_from(limit_usd: f64, warn_threshold: Option<f64>) -> Result<Budget, String> {
+    let warn_threshold = warn_threshold.unwrap_or(0.8);
+    if limit_usd < 0.0 || !(0.0..=1.0).contains(&warn_threshold) {
+        return Err("Budget limit must be non-negative and the warning threshold between 0 and 1".to_string());
+    }
+    Ok(Budget { limit_usd, warn_threshold })
+}
+
+#[tauri::command]
+pub async fn list_prompt_templates(project_path: Option<String>) -> Result<Vec<TemplateInfo>, String> {
+    Ok(prompt_templates::list_templates(project_path.as_deref().map(std::path::Path::new)))
+}
+
+#[tauri::command]
+pub async fn list_routing_rules(project_path: Option<String>) -> Result<Vec<RoutingRuleInfo>, String> {
+    Ok(routing_rules::list_rules(project_path.as_deref().map(std::path::Path::new)))
+}
+
+#[tauri::command]
+pub async fn get_session_status_history(
+    session_id: String,
+    session_manager: State<'_, SessionManager>,
+) -> Result<Vec<StatusChange>, String> {
+    session_manager
+        .get_status_history(&session_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_run_status(
+    run_id: String,
+    session_manager: State<'_, SessionManager>,
+) -> Result<RunProgress, String> {
+    session_manager
+        .get_run_progress(&run_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_run_results(
+    run_id: String,
+    session_manager: State<'_, SessionManager>,
+) -> Result<RunResults, String> {
+    session_manager
+        .get_run_results(&run_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn list_runs(
+    session_id: String,
+    session_manager: State<'_, SessionManager>,
+) -> Result<Vec<RequestRun>, String> {
+    Ok(session_manager.list_runs(&session_id).await)
+}
+
+#[tauri::command]
+pub async fn complete_session(
+    session_id: String,
+    keep_branch: bool,
+    session_manager: State<'_, SessionManager>,
+) -> Result<Session, String> {
+    session_manager
+        .complete_session(&session_id, keep_branch)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn archive_session(
+    session_id: String,
+    keep_branch: bool,
+    session_manager: State<'_, SessionManager>,
+) -> Result<Session, String> {
+    session_manager
+        .archive_session(&session_id, keep_branch)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn delete_session(
+    session_id: String,
+    confirmed: Option<bool>,
+    session_manager: State<'_, SessionManager>,
+) -> Result<SessionDeletion, String> {
+    session_manager
+        .delete_session(&session_id, confirmed.unwrap_or(false))
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub async fn pause_session(
     session_id: String,
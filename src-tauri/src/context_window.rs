@@ -0,0 +1,259 @@
+use crate::session_manager::{ConversationMessage, MessageRole};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Share of the context budget set aside for the rolling summary once older turns are dropped.
+const SUMMARY_SHARE: usize = 4;
+/// Allowance for the section headings added around the messages.
+const HEADING_TOKENS: usize = 20;
+
+/// Number of tokens in `text`, using the cl100k tokenizer bundled with tiktoken-rs. Models
+/// tokenize differently, but it is close enough to budget against and needs no network.
+pub fn count_tokens(text: &str) -> usize {
+    tiktoken_rs::cl100k_base_singleton().lock().encode_ordinary(text).len()
+}
+
+/// Cut `text` down to about `max_tokens`, keeping its beginning and end, where agent output
+/// tends to state what was attempted and how it went.
+pub fn truncate_to_tokens(text: &str, max_tokens: usize) -> String {
+    let bpe = tiktoken_rs::cl100k_base_singleton();
+    let bpe = bpe.lock();
+    let tokens = bpe.encode_ordinary(text);
+    if tokens.len() <= max_tokens {
+        return text.to_string();
+    }
+
+    let keep = max_tokens.saturating_sub(10) / 2;
+    let head = bpe.decode(tokens[..keep].to_vec()).unwrap_or_default();
+    let tail = bpe.decode(tokens[tokens.len() - keep..].to_vec()).unwrap_or_default();
+    format!("{}\n[... {} tokens omitted ...]\n{}", head, tokens.len() - 2 * keep, tail)
+}
+
+/// LLM-written summary of the turns that no longer fit in the planner's context.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversationSummary {
+    pub session_id: String,
+    pub summary: String,
+    /// Messages before this index in the conversation are covered by the summary, apart from
+    /// the goal and pinned messages, which are always sent verbatim.
+    pub covered_messages: usize,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Most tokens the rolling summary may take up in a context of `budget` tokens.
+pub fn summary_budget(budget: usize) -> usize {
+    budget / SUMMARY_SHARE
+}
+
+/// Which messages go into the context and how.
+struct Layout {
+    goal: Option<usize>,
+    pinned: Vec<usize>,
+    /// Index of the oldest message sent verbatim as part of the recent conversation.
+    recent_start: usize,
+    /// Token limit for the newest message when it alone overflows the budget.
+    newest_limit: Option<usize>,
+}
+
+fn layout(history: &[ConversationMessage], summary: Option<&ConversationSummary>, budget: usize) -> Layout {
+    let tokens: Vec<usize> = history.iter().map(|msg| count_tokens(&format_message(msg))).collect();
+    // The first thing the user asked for frames everything after it
+    let goal = history.iter().position(|msg| matches!(msg.role, MessageRole::User));
+    let pinned: Vec<usize> = history
+        .iter()
+        .enumerate()
+        .filter(|(index, msg)| msg.pinned && Some(*index) != goal)
+        .map(|(index, _)| index)
+        .collect();
+
+    if tokens.iter().sum::<usize>() <= budget {
+        return Layout { goal, pinned, recent_start: 0, newest_limit: None };
+    }
+
+    let fixed: usize = goal.iter().chain(&pinned).map(|index| tokens[*index]).sum();
+    let summary_tokens = summary
+        .map(|summary| count_tokens(&summary.summary))
+        .unwrap_or(0)
+        .max(summary_budget(budget));
+    let mut remaining = budget.saturating_sub(fixed + summary_tokens + HEADING_TOKENS);
+
+    let mut recent_start = history.len();
+    let mut newest_limit = None;
+    for index in (0..history.len()).rev() {
+        if Some(index) == goal || pinned.contains(&index) {
+            recent_start = index;
+            continue;
+        }
+        if tokens[index] <= remaining {
+            remaining -= tokens[index];
+        } else if index == history.len() - 1 {
+            // A single huge message still goes in, cut down to what is left
+            newest_limit = Some(remaining);
+            remaining = 0;
+        } else {
+            break;
+        }
+        recent_start = index;
+    }
+
+    Layout { goal, pinned, recent_start, newest_limit }
+}
+
+/// Older messages that have fallen out of the recent window and are not in the summary yet.
+/// Empty when nothing needs summarizing.
+pub fn messages_to_summarize(
+    history: &[ConversationMessage],
+    summary: Option<&ConversationSummary>,
+    budget: usize,
+) -> Range<usize> {
+    let covered = summary.map(|summary| summary.covered_messages).unwrap_or(0);
+    let recent_start = layout(history, summary, budget).recent_start;
+    covered.min(recent_start)..recent_start
+}
+
+/// Messages in `range` as they should be handed to the summarizer, leaving out the ones that
+/// are kept verbatim anyway.
+pub fn summary_transcript(history: &[ConversationMessage], range: Range<usize>) -> String {
+    let goal = history.iter().position(|msg| matches!(msg.role, MessageRole::User));
+    history[range.clone()]
+        .iter()
+        .zip(range)
+        .filter(|(msg, index)| !msg.pinned && Some(*index) != goal)
+        .map(|(msg, _)| format_message(msg))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Build the planner's conversation context within `budget` tokens: the original goal and
+/// pinned messages verbatim, the summary of older turns, then as many recent messages as fit.
+pub fn build_context(
+    history: &[ConversationMessage],
+    summary: Option<&ConversationSummary>,
+    budget: usize,
+) -> String {
+    let layout = layout(history, summary, budget);
+    let mut sections = Vec::new();
+
+    if layout.recent_start > 0 {
+        if let Some(goal) = layout.goal.filter(|goal| *goal < layout.recent_start) {
+            sections.push(format!("Original goal:\n{}", format_message(&history[goal])));
+        }
+        let pinned: Vec<String> = layout
+            .pinned
+            .iter()
+            .filter(|index| **index < layout.recent_start)
+            .map(|index| format_message(&history[*index]))
+            .collect();
+        if !pinned.is_empty() {
+            sections.push(format!("Pinned messages:\n{}", pinned.join("\n")));
+        }
+        if let Some(summary) = summary.filter(|summary| !summary.summary.is_empty()) {
+            sections.push(format!("Summary of earlier conversation:\n{}", summary.summary));
+        }
+    }
+
+    let recent: Vec<String> = history[layout.recent_start..]
+        .iter()
+        .enumerate()
+        .map(|(offset, msg)| {
+            let line = format_message(msg);
+            match layout.newest_limit {
+                Some(limit) if layout.recent_start + offset == history.len() - 1 => truncate_to_tokens(&line, limit),
+                _ => line,
+            }
+        })
+        .collect();
+    if !recent.is_empty() {
+        if sections.is_empty() {
+            sections.push(recent.join("\n"));
+        } else {
+            sections.push(format!("Recent conversation:\n{}", recent.join("\n")));
+        }
+    }
+
+    sections.join("\n\n")
+}
+
+fn format_message(msg: &ConversationMessage) -> String {
+    let role = match msg.role {
+        MessageRole::User => "User",
+        MessageRole::Assistant => "Assistant",
+        MessageRole::System => "System",
+    };
+    let agent_info = msg.agent_type
+        .as_ref()
+        .map(|a| format!(" ({})", a))
+        .unwrap_or_default();
+    format!("{}{}: {}", role, agent_info, msg.content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: MessageRole, content: &str) -> ConversationMessage {
+        ConversationMessage {
+            id: uuid::Uuid::new_v4().to_string(),
+            session_id: "session".to_string(),
+            role,
+            content: content.to_string(),
+            agent_type: None,
+            pinned: false,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_context_keeps_goal_and_pinned_messages() {
+        let mut history = vec![
+            message(MessageRole::User, "Port the CLI to async"),
+            message(MessageRole::Assistant, "Planning the port"),
+            message(MessageRole::User, "Never touch the legacy module"),
+        ];
+        history[2].pinned = true;
+        for turn in 0..20 {
+            history.push(message(MessageRole::Assistant, &format!("Progress report {} ", turn).repeat(20)));
+        }
+        history.push(message(MessageRole::User, "What is left?"));
+
+        // Everything fits in a large budget, so nothing is summarized
+        assert!(messages_to_summarize(&history, None, 100_000).is_empty());
+        assert!(build_context(&history, None, 100_000).starts_with("User: Port the CLI"));
+
+        let budget = 400;
+        let range = messages_to_summarize(&history, None, budget);
+        assert_eq!(range.start, 0);
+        assert!(range.end > 3 && range.end < history.len());
+        let transcript = summary_transcript(&history, range.clone());
+        assert!(transcript.starts_with("Assistant: Planning the port"));
+        assert!(!transcript.contains("legacy module"));
+
+        let summary = ConversationSummary {
+            session_id: "session".to_string(),
+            summary: "The agents reported steady progress.".to_string(),
+            covered_messages: range.end,
+            updated_at: Utc::now(),
+        };
+        let context = build_context(&history, Some(&summary), budget);
+        assert!(count_tokens(&context) <= budget);
+        assert!(context.starts_with("Original goal:\nUser: Port the CLI to async"));
+        assert!(context.contains("Pinned messages:\nUser: Never touch the legacy module"));
+        assert!(context.contains("Summary of earlier conversation:\nThe agents reported steady progress."));
+        assert!(context.ends_with("User: What is left?"));
+        assert!(messages_to_summarize(&history, Some(&summary), budget).is_empty());
+    }
+
+    #[test]
+    fn test_huge_message_is_truncated() {
+        let history = vec![
+            message(MessageRole::User, "Run the tests"),
+            message(MessageRole::Assistant, &"error: build failed\n".repeat(2000)),
+        ];
+
+        let context = build_context(&history, None, 500);
+        assert!(count_tokens(&context) <= 500);
+        assert!(context.contains("tokens omitted"));
+        assert!(context.starts_with("User: Run the tests\nAssistant: error: build failed"));
+    }
+}
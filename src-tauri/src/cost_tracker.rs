@@ -0,0 +1,147 @@
+use crate::models::*;
+use crate::context_window::count_tokens;
+
+/// Published per-million-token prices (prompt, completion) in USD, matched against model
+/// names by substring. Used when a provider doesn't report cost itself.
+const MODEL_PRICES: &[(&str, f64, f64)] = &[
+    ("claude-3-opus", 15.0, 75.0),
+    ("claude-3-sonnet", 3.0, 15.0),
+    ("claude-3.5-sonnet", 3.0, 15.0),
+    ("claude-3-5-sonnet", 3.0, 15.0),
+    ("claude-3-haiku", 0.25, 1.25),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4o", 2.5, 10.0),
+    ("gemini-1.5-pro", 1.25, 5.0),
+    ("gemini-1.5-flash", 0.075, 0.3),
+];
+
+pub fn estimate_cost_usd(model: &str, prompt_tokens: u64, completion_tokens: u64) -> Option<f64> {
+    let model = model.to_lowercase();
+    MODEL_PRICES
+        .iter()
+        .find(|(name, _, _)| model.contains(name))
+        .map(|(_, prompt_price, completion_price)| {
+            (prompt_tokens as f64 * prompt_price + completion_tokens as f64 * completion_price) / 1_000_000.0
+        })
+}
+
+/// Fill in an estimated cost when the provider reported tokens but no cost.
+pub fn with_estimated_cost(model: &str, mut usage: TokenUsage) -> TokenUsage {
+    if usage.cost_usd.is_none() {
+        usage.cost_usd = estimate_cost_usd(model, usage.prompt_tokens, usage.completion_tokens);
+    }
+    usage
+}
+
+/// Pull token and cost figures out of an agent CLI's output. Claude Code's JSON output
+/// reports `total_cost_usd` and a `usage` block; other CLIs may print similar objects.
+pub fn parse_agent_usage(output: &str) -> Option<TokenUsage> {
+    output
+        .lines()
+        .rev()
+        .filter_map(|line| {
+            let line = line.trim();
+            if line.starts_with('{') { serde_json::from_str::<serde_json::Value>(line).ok() } else { None }
+        })
+        .chain(serde_json::from_str::<serde_json::Value>(output.trim()).ok())
+        .find_map(|report| {
+            let usage = &report["usage"];
+            let prompt_tokens = usage["input_tokens"].as_u64().or_else(|| usage["prompt_tokens"].as_u64());
+            let completion_tokens = usage["output_tokens"].as_u64().or_else(|| usage["completion_tokens"].as_u64());
+            let cost_usd = report["total_cost_usd"].as_f64().or_else(|| report["cost_usd"].as_f64());
+
+            if prompt_tokens.is_none() && completion_tokens.is_none() && cost_usd.is_none() {
+                return None;
+            }
+            Some(TokenUsage {
+                prompt_tokens: prompt_tokens.unwrap_or(0),
+                completion_tokens: completion_tokens.unwrap_or(0),
+                cost_usd,
+            })
+        })
+}
+
+/// Expected usage of one more task like those summarised in `history`. Without a history of
+/// recorded usage only the task description itself can be counted.
+pub fn estimate_task_usage(history: Option<&OutcomeStats>, description: &str) -> TokenUsage {
+    match history.filter(|stats| stats.total_tasks > 0 && stats.prompt_tokens + stats.completion_tokens > 0) {
+        Some(stats) => TokenUsage {
+            prompt_tokens: stats.prompt_tokens / stats.total_tasks,
+            completion_tokens: stats.completion_tokens / stats.total_tasks,
+            cost_usd: (stats.cost_usd > 0.0).then(|| stats.cost_usd / stats.total_tasks as f64),
+        },
+        None => TokenUsage {
+            prompt_tokens: count_tokens(description) as u64,
+            completion_tokens: 0,
+            cost_usd: None,
+        },
+    }
+}
+
+/// Total of several usage figures. The cost is known if any part of it is.
+pub fn sum_usage<'a>(usages: impl IntoIterator<Item = &'a TokenUsage>) -> TokenUsage {
+    usages.into_iter().fold(TokenUsage::default(), |total, usage| TokenUsage {
+        prompt_tokens: total.prompt_tokens + usage.prompt_tokens,
+        completion_tokens: total.completion_tokens + usage.completion_tokens,
+        cost_usd: match (total.cost_usd, usage.cost_usd) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(0.0) + b.unwrap_or(0.0)),
+        },
+    })
+}
+
+/// Compare spending against a budget.
+pub fn evaluate_budget(spent_usd: f64, budget: &Budget) -> BudgetStatus {
+    if spent_usd >= budget.limit_usd {
+        BudgetStatus::Exceeded
+    } else if spent_usd >= budget.limit_usd * budget.warn_threshold {
+        BudgetStatus::Warning
+    } else {
+        BudgetStatus::Ok
+    }
+}
+
+/// Combine the session and project budget states, the stricter one winning.
+pub fn overall_budget_status(summary: &UsageSummary) -> BudgetStatus {
+    let session = summary
+        .session_budget
+        .as_ref()
+        .map(|budget| evaluate_budget(summary.session_usage.cost_usd.unwrap_or(0.0), budget))
+        .unwrap_or(BudgetStatus::Ok);
+    let project = summary
+        .project_budget
+        .as_ref()
+        .map(|budget| evaluate_budget(summary.project_usage.cost_usd.unwrap_or(0.0), budget))
+        .unwrap_or(BudgetStatus::Ok);
+    session.max(project)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_agent_usage() {
+        let output = "Working...\n{\"type\":\"result\",\"total_cost_usd\":0.042,\"usage\":{\"input_tokens\":1200,\"output_tokens\":300}}";
+        let usage = parse_agent_usage(output).unwrap();
+        assert_eq!(usage.prompt_tokens, 1200);
+        assert_eq!(usage.completion_tokens, 300);
+        assert_eq!(usage.cost_usd, Some(0.042));
+
+        assert!(parse_agent_usage("no usage here").is_none());
+    }
+
+    #[test]
+    fn test_budget_evaluation() {
+        let budget = Budget {
+            limit_usd: 10.0,
+            warn_threshold: 0.8,
+        };
+        assert_eq!(evaluate_budget(5.0, &budget), BudgetStatus::Ok);
+        assert_eq!(evaluate_budget(8.5, &budget), BudgetStatus::Warning);
+        assert_eq!(evaluate_budget(10.0, &budget), BudgetStatus::Exceeded);
+
+        let cost = estimate_cost_usd("anthropic/claude-3-sonnet", 1_000_000, 100_000).unwrap();
+        assert!((cost - 4.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,377 @@
+use crate::middle_manager::SubTask;
+use crate::models::*;
+use crate::scheduler::{QueuedSubtask, Scheduler, SchedulerPermit};
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum NodeStatus {
+    Pending,
+    /// Started, but waiting for a slot on its agent.
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Skipped,
+}
+
+/// Execution state of one subtask in a plan.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct NodeState {
+    pub subtask_id: String,
+    pub description: String,
+    pub agent: String,
+    pub status: NodeStatus,
+    pub task_id: Option<String>,
+    pub error: Option<String>,
+}
+
+pub struct DagOutcome {
+    pub nodes: Vec<NodeState>,
+    /// Results of the subtasks that ran, keyed by subtask id.
+    pub results: HashMap<String, TaskResult>,
+}
+
+pub struct DagExecutor {
+    scheduler: Arc<Scheduler>,
+    session_id: String,
+}
+
+impl DagExecutor {
+    pub fn new(scheduler: Arc<Scheduler>, session_id: &str) -> Self {
+        Self {
+            scheduler,
+            session_id: session_id.to_string(),
+        }
+    }
+
+    /// Run subtasks as soon as their dependencies complete and the scheduler gives their
+    /// agent a free slot. Dependants of a failed or skipped subtask are skipped.
+    ///
+    /// `run_subtask` receives the subtask together with the results of its dependencies.
+    /// `on_status` is awaited every time a node changes state; a node is `Queued` until the
+    /// scheduler gives it a slot and `Running` from then on.
+    pub async fn run<F, Fut, S, SFut>(
+        &self,
+        subtasks: Vec<SubTask>,
+        run_subtask: F,
+        on_status: S,
+    ) -> Result<DagOutcome>
+    where
+        F: Fn(SubTask, Vec<TaskResult>) -> Fut,
+        Fut: Future<Output = TaskResult> + Send + 'static,
+        S: Fn(NodeState) -> SFut,
+        SFut: Future<Output = ()>,
+    {
+        let order = topological_order(&subtasks)?;
+        let mut subtasks: HashMap<String, SubTask> = subtasks
+            .into_iter()
+            .map(|subtask| (subtask.id.clone(), subtask))
+            .collect();
+
+        let mut nodes: HashMap<String, NodeState> = order
+            .iter()
+            .map(|id| {
+                let subtask = &subtasks[id];
+                (id.clone(), NodeState {
+                    subtask_id: id.clone(),
+                    description: subtask.description.clone(),
+                    agent: subtask.agent.clone(),
+                    status: NodeStatus::Pending,
+                    task_id: None,
+                    error: None,
+                })
+            })
+            .collect();
+        let dependencies: HashMap<String, Vec<String>> = subtasks
+            .values()
+            .map(|subtask| (subtask.id.clone(), subtask.dependencies.clone()))
+            .collect();
+
+        let mut results: HashMap<String, TaskResult> = HashMap::new();
+        let mut running: JoinSet<(String, TaskResult, Option<SchedulerPermit>)> = JoinSet::new();
+        let (started_tx, mut started) = tokio::sync::mpsc::unbounded_channel::<String>();
+
+        loop {
+            // Start or skip every pending node whose dependencies have settled
+            for id in &order {
+                if nodes[id].status != NodeStatus::Pending {
+                    continue;
+                }
+
+                let dependency_states: Vec<&NodeStatus> = dependencies[id]
+                    .iter()
+                    .map(|dep| &nodes[dep].status)
+                    .collect();
+
+                if dependency_states.iter().any(|s| matches!(s, NodeStatus::Failed | NodeStatus::Skipped)) {
+                    let node = nodes.get_mut(id).unwrap();
+                    node.status = NodeStatus::Skipped;
+                    node.error = Some("Skipped because a dependency did not complete".to_string());
+                    on_status(node.clone()).await;
+                    continue;
+                }
+
+                if !dependency_states.iter().all(|s| **s == NodeStatus::Completed) {
+                    continue;
+                }
+
+                let subtask = subtasks.remove(id).unwrap();
+                let dependency_results: Vec<TaskResult> = dependencies[id]
+                    .iter()
+                    .filter_map(|dep| results.get(dep).cloned())
+                    .collect();
+                // Ensemble subtasks take a slot for each of their agents as they run them
+                let queued = (subtask.ensemble.len() <= 1)
+                    .then(|| QueuedSubtask::new(&self.session_id, &subtask, &subtask.agent));
+                let scheduler = self.scheduler.clone();
+                let future = run_subtask(subtask, dependency_results);
+                let node_id = id.clone();
+                let started_tx = started_tx.clone();
+                running.spawn(async move {
+                    let permit = match queued {
+                        Some(queued) => Some(scheduler.acquire(queued).await),
+                        None => None,
+                    };
+                    let _ = started_tx.send(node_id.clone());
+                    (node_id, future.await, permit)
+                });
+
+                let node = nodes.get_mut(id).unwrap();
+                node.status = NodeStatus::Queued;
+                on_status(node.clone()).await;
+            }
+
+            // A node always reports getting its slot before it reports finishing
+            let joined = tokio::select! {
+                biased;
+                Some(id) = started.recv() => {
+                    let node = nodes.get_mut(&id).unwrap();
+                    if node.status == NodeStatus::Queued {
+                        node.status = NodeStatus::Running;
+                        on_status(node.clone()).await;
+                    }
+                    continue;
+                }
+                joined = running.join_next() => joined,
+            };
+            let Some(joined) = joined else {
+                break;
+            };
+            // The slot is held until the node is reported finished, so whoever gets it next
+            // can't be reported running first
+            let (id, result, permit) = joined.map_err(|e| anyhow::anyhow!("Subtask execution panicked: {}", e))?;
+
+            let node = nodes.get_mut(&id).unwrap();
+            node.task_id = Some(result.id.clone());
+            node.status = match result.status {
+                TaskStatus::Failed | TaskStatus::Cancelled => NodeStatus::Failed,
+                _ => NodeStatus::Completed,
+            };
+            node.error = result.error.clone();
+            on_status(node.clone()).await;
+            drop(permit);
+
+            results.insert(id, result);
+        }
+
+        Ok(DagOutcome {
+            nodes: order.iter().map(|id| nodes[id].clone()).collect(),
+            results,
+        })
+    }
+}
+
+/// Order subtasks so that every subtask comes after its dependencies, preferring higher
+/// priority and then the planner's original order among subtasks that are ready together.
+pub fn topological_order(subtasks: &[SubTask]) -> Result<Vec<String>> {
+    let ids: HashSet<&str> = subtasks.iter().map(|s| s.id.as_str()).collect();
+    if ids.len() != subtasks.len() {
+        return Err(anyhow::anyhow!("Task plan contains duplicate subtask ids"));
+    }
+
+    let mut remaining_deps: HashMap<&str, usize> = HashMap::new();
+    let mut dependants: HashMap<&str, Vec<&str>> = HashMap::new();
+    for subtask in subtasks {
+        for dep in &subtask.dependencies {
+            if !ids.contains(dep.as_str()) {
+                return Err(anyhow::anyhow!(
+                    "Subtask {} depends on unknown subtask {}",
+                    subtask.id,
+                    dep
+                ));
+            }
+            dependants.entry(dep.as_str()).or_default().push(subtask.id.as_str());
+        }
+        remaining_deps.insert(subtask.id.as_str(), subtask.dependencies.len());
+    }
+
+    let rank: HashMap<&str, (u8, usize)> = subtasks
+        .iter()
+        .enumerate()
+        .map(|(index, s)| (s.id.as_str(), (priority_rank(&s.priority), index)))
+        .collect();
+
+    let mut ready: Vec<&str> = subtasks
+        .iter()
+        .filter(|s| s.dependencies.is_empty())
+        .map(|s| s.id.as_str())
+        .collect();
+    let mut order = Vec::with_capacity(subtasks.len());
+
+    while !ready.is_empty() {
+        ready.sort_by_key(|id| rank[id]);
+        let id = ready.remove(0);
+        order.push(id.to_string());
+
+        for dependant in dependants.get(id).into_iter().flatten() {
+            let count = remaining_deps.get_mut(dependant).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                ready.push(dependant);
+            }
+        }
+    }
+
+    if order.len() != subtasks.len() {
+        return Err(anyhow::anyhow!("Task plan contains a dependency cycle"));
+    }
+
+    Ok(order)
+}
+
+pub fn priority_rank(priority: &str) -> u8 {
+    match priority.to_lowercase().as_str() {
+        "high" => 0,
+        "medium" => 1,
+        "low" => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn subtask(id: &str, priority: &str, dependencies: &[&str]) -> SubTask {
+        SubTask {
+            id: id.to_string(),
+            description: format!("task {}", id),
+            agent: "claude_code".to_string(),
+            priority: priority.to_string(),
+            category: None,
+            dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+            acceptance_criteria: vec![],
+            ensemble: vec![],
+        }
+    }
+
+    fn result_for(subtask: &SubTask, status: TaskStatus) -> TaskResult {
+        TaskResult {
+            id: uuid::Uuid::new_v4().to_string(),
+            session_id: "session".to_string(),
+            task_description: subtask.description.clone(),
+            agent_type: subtask.agent.clone(),
+            status,
+            result: None,
+            error: None,
+            created_at: chrono::Utc::now(),
+            completed_at: Some(chrono::Utc::now()),
+            metadata: TaskMetadata::default(),
+        }
+    }
+
+    #[test]
+    fn test_topological_order() {
+        let subtasks = vec![
+            subtask("c", "high", &["a", "b"]),
+            subtask("a", "low", &[]),
+            subtask("b", "high", &[]),
+        ];
+        assert_eq!(topological_order(&subtasks).unwrap(), vec!["b", "a", "c"]);
+
+        let cyclic = vec![subtask("a", "high", &["b"]), subtask("b", "high", &["a"])];
+        assert!(topological_order(&cyclic).is_err());
+
+        let dangling = vec![subtask("a", "high", &["missing"])];
+        assert!(topological_order(&dangling).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failed_subtask_skips_dependants() {
+        let subtasks = vec![
+            subtask("a", "high", &[]),
+            subtask("b", "high", &["a"]),
+            subtask("c", "high", &[]),
+            subtask("d", "high", &["c"]),
+        ];
+        let seen = Mutex::new(Vec::new());
+
+        let scheduler = Arc::new(Scheduler::new(std::time::Duration::from_secs(60)));
+        scheduler.set_capacities(HashMap::from([("claude_code".to_string(), 2)]));
+        let outcome = DagExecutor::new(scheduler, "session")
+            .run(
+                subtasks,
+                |subtask, _deps| async move {
+                    let status = if subtask.id == "a" { TaskStatus::Failed } else { TaskStatus::Completed };
+                    result_for(&subtask, status)
+                },
+                |node| {
+                    seen.lock().unwrap().push((node.subtask_id.clone(), node.status.clone()));
+                    async {}
+                },
+            )
+            .await
+            .unwrap();
+
+        let statuses: HashMap<String, NodeStatus> = outcome
+            .nodes
+            .iter()
+            .map(|node| (node.subtask_id.clone(), node.status.clone()))
+            .collect();
+        assert_eq!(statuses["a"], NodeStatus::Failed);
+        assert_eq!(statuses["b"], NodeStatus::Skipped);
+        assert_eq!(statuses["c"], NodeStatus::Completed);
+        assert_eq!(statuses["d"], NodeStatus::Completed);
+        assert_eq!(outcome.results.len(), 3);
+        let seen = seen.lock().unwrap();
+        let d_statuses: Vec<&NodeStatus> = seen.iter().filter(|(id, _)| id == "d").map(|(_, status)| status).collect();
+        assert_eq!(d_statuses, vec![&NodeStatus::Queued, &NodeStatus::Running, &NodeStatus::Completed]);
+    }
+
+    #[tokio::test]
+    async fn test_nodes_waiting_for_a_slot_are_not_running() {
+        let subtasks = vec![subtask("a", "high", &[]), subtask("b", "high", &[]), subtask("c", "high", &[])];
+        let running = Mutex::new(HashSet::new());
+        let most_running = Mutex::new(0);
+
+        let scheduler = Arc::new(Scheduler::new(std::time::Duration::from_secs(60)));
+        scheduler.set_capacities(HashMap::from([("claude_code".to_string(), 1)]));
+        DagExecutor::new(scheduler, "session")
+            .run(
+                subtasks,
+                |subtask, _deps| async move {
+                    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                    result_for(&subtask, TaskStatus::Completed)
+                },
+                |node| {
+                    let mut running = running.lock().unwrap();
+                    match node.status {
+                        NodeStatus::Running => running.insert(node.subtask_id.clone()),
+                        _ => running.remove(&node.subtask_id),
+                    };
+                    let mut most = most_running.lock().unwrap();
+                    *most = (*most).max(running.len());
+                    async {}
+                },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(*most_running.lock().unwrap(), 1);
+    }
+}
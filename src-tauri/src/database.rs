@@ -2,7 +2,13 @@ use rusqlite::Connection;
 use anyhow::Result;
 use std::sync::{Arc, Mutex};
 use crate::models::*;
-use crate::session_manager::ConversationMessage;
+use crate::session_manager::{ConversationMessage, MessageRole};
+use crate::plan_approval::PendingPlan;
+use crate::context_window::ConversationSummary;
+use crate::plan_preview::PlanPreview;
+use crate::planner_tools::ToolCallRecord;
+use crate::ensemble::EnsembleCandidate;
+use crate::session_state::StatusChange;
 
 pub struct Database {
     conn: Arc<Mutex<Connection>>,
@@ -58,6 +64,98 @@ impl Database {
                 FOREIGN KEY (session_id) REFERENCES sessions(id)
             );
 
+            CREATE TABLE IF NOT EXISTS llm_calls (
+                id TEXT PRIMARY KEY,
+                session_id TEXT NOT NULL,
+                purpose TEXT NOT NULL,
+                model TEXT NOT NULL,
+                prompt_tokens INTEGER NOT NULL,
+                completion_tokens INTEGER NOT NULL,
+                cost_usd REAL,
+                created_at TEXT NOT NULL,
+                FOREIGN KEY (session_id) REFERENCES sessions(id)
+            );
+
+            CREATE TABLE IF NOT EXISTS budgets (
+                scope TEXT NOT NULL,
+                scope_id TEXT NOT NULL,
+                limit_usd REAL NOT NULL,
+                warn_threshold REAL NOT NULL,
+                updated_at TEXT NOT NULL,
+                PRIMARY KEY (scope, scope_id)
+            );
+
+            CREATE TABLE IF NOT EXISTS pending_plans (
+                session_id TEXT PRIMARY KEY,
+                plan TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                FOREIGN KEY (session_id) REFERENCES sessions(id)
+            );
+
+            CREATE TABLE IF NOT EXISTS conversation_summaries (
+                session_id TEXT PRIMARY KEY,
+                summary TEXT NOT NULL,
+                covered_messages INTEGER NOT NULL,
+                updated_at TEXT NOT NULL,
+                FOREIGN KEY (session_id) REFERENCES sessions(id)
+            );
+
+            CREATE TABLE IF NOT EXISTS plan_previews (
+                id TEXT PRIMARY KEY,
+                session_id TEXT NOT NULL,
+                request TEXT NOT NULL,
+                preview TEXT NOT NULL,
+                prompt_tokens INTEGER NOT NULL DEFAULT 0,
+                completion_tokens INTEGER NOT NULL DEFAULT 0,
+                cost_usd REAL,
+                tool_calls TEXT NOT NULL DEFAULT '[]',
+                created_at TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS conversation_messages (
+                id TEXT PRIMARY KEY,
+                session_id TEXT NOT NULL,
+                role TEXT NOT NULL,
+                agent_type TEXT,
+                content TEXT NOT NULL,
+                pinned INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL,
+                updated_at TEXT NOT NULL,
+                FOREIGN KEY (session_id) REFERENCES sessions(id)
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_conversation_messages_session
+                ON conversation_messages (session_id, created_at);
+
+            CREATE TABLE IF NOT EXISTS session_status_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                session_id TEXT NOT NULL,
+                from_status TEXT,
+                to_status TEXT NOT NULL,
+                reason TEXT,
+                created_at TEXT NOT NULL,
+                FOREIGN KEY (session_id) REFERENCES sessions(id)
+            );
+
+            CREATE TABLE IF NOT EXISTS ensemble_candidates (
+                id TEXT PRIMARY KEY,
+                session_id TEXT NOT NULL,
+                candidate TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                FOREIGN KEY (session_id) REFERENCES sessions(id)
+            );
+
+            CREATE TABLE IF NOT EXISTS planner_tool_calls (
+                id TEXT PRIMARY KEY,
+                session_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                arguments TEXT NOT NULL,
+                output TEXT NOT NULL,
+                error TEXT,
+                created_at TEXT NOT NULL,
+                FOREIGN KEY (session_id) REFERENCES sessions(id)
+            );
+
             CREATE TABLE IF NOT EXISTS agent_messages (
                 id TEXT PRIMARY KEY,
                 from_agent TEXT NOT NULL,
@@ -69,6 +167,39 @@ impl Database {
             "#
         )?;
 
+        // Columns added after the initial schema shipped
+        Self::ensure_column(conn, "tasks", "model", "TEXT")?;
+        Self::ensure_column(conn, "tasks", "category", "TEXT")?;
+        Self::ensure_column(conn, "tasks", "retries", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(conn, "tasks", "prompt_tokens", "INTEGER")?;
+        Self::ensure_column(conn, "tasks", "completion_tokens", "INTEGER")?;
+        Self::ensure_column(conn, "tasks", "cost_usd", "REAL")?;
+        Self::ensure_column(conn, "tasks", "prompt_templates", "TEXT")?;
+        Self::ensure_column(conn, "tasks", "planner_model", "TEXT")?;
+        Self::ensure_column(conn, "tasks", "rule_matches", "TEXT")?;
+        Self::ensure_column(conn, "plan_previews", "prompt_tokens", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(conn, "plan_previews", "completion_tokens", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(conn, "plan_previews", "cost_usd", "REAL")?;
+        Self::ensure_column(conn, "plan_previews", "tool_calls", "TEXT NOT NULL DEFAULT '[]'")?;
+        Self::ensure_column(conn, "sessions", "require_plan_approval", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(conn, "sessions", "tags", "TEXT NOT NULL DEFAULT '[]'")?;
+        Self::ensure_column(conn, "sessions", "priority", "TEXT NOT NULL DEFAULT 'Normal'")?;
+        Self::ensure_column(conn, "sessions", "needs_repair", "TEXT")?;
+
+        Ok(())
+    }
+
+    fn ensure_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>("name"))?
+            .filter_map(|name| name.ok())
+            .any(|name| name == column);
+
+        if !exists {
+            conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))?;
+        }
+
         Ok(())
     }
 
@@ -77,10 +208,11 @@ impl Database {
         conn.execute(
             r#"
             INSERT INTO sessions 
-            (id, name, project_path, description, status, created_at, updated_at, worktree_path, branch_name)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            (id, name, project_path, description, status, created_at, updated_at, worktree_path, branch_name,
+             require_plan_approval, tags, priority, needs_repair)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
             "#,
-            (
+            rusqlite::params![
                 &session.id,
                 &session.name,
                 &session.project_path,
@@ -90,7 +222,11 @@ impl Database {
                 &session.updated_at.to_rfc3339(),
                 &session.worktree_path,
                 &session.branch_name,
-            )
+                session.require_plan_approval,
+                serde_json::to_string(&session.tags)?,
+                format!("{:?}", session.priority),
+                &session.needs_repair,
+            ],
         )?;
 
         Ok(())
@@ -101,15 +237,7 @@ impl Database {
         let mut stmt = conn.prepare("SELECT * FROM sessions ORDER BY updated_at DESC")?;
         
         let session_iter = stmt.query_map([], |row| {
-            let status_str: String = row.get("status")?;
-            let status = match status_str.as_str() {
-                "Created" => SessionStatus::Created,
-                "Active" => SessionStatus::Active,
-                "Paused" => SessionStatus::Paused,
-                "Completed" => SessionStatus::Completed,
-                "Failed" => SessionStatus::Failed,
-                _ => SessionStatus::Created,
-            };
+            let status = parse_session_status(&row.get::<_, String>("status")?);
 
             Ok(Session {
                 id: row.get("id")?,
@@ -125,6 +253,14 @@ impl Database {
                     .with_timezone(&chrono::Utc),
                 worktree_path: row.get("worktree_path")?,
                 branch_name: row.get("branch_name")?,
+                require_plan_approval: row.get("require_plan_approval")?,
+                tags: serde_json::from_str(&row.get::<_, String>("tags")?).unwrap_or_default(),
+                priority: match row.get::<_, String>("priority")?.as_str() {
+                    "High" => SessionPriority::High,
+                    "Low" => SessionPriority::Low,
+                    _ => SessionPriority::Normal,
+                },
+                needs_repair: row.get("needs_repair")?,
             })
         })?;
 
@@ -136,30 +272,571 @@ impl Database {
         Ok(sessions)
     }
 
-    // Commented out unused method to remove dead code warning
-    // pub fn create_task(&self, task: &TaskResult) -> Result<()> {
-    //     let conn = self.conn.lock().unwrap();
-    //     conn.execute(
-    //         r#"
-    //         INSERT INTO tasks 
-    //         (id, session_id, task_description, agent_type, status, result, error, created_at, completed_at)
-    //         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
-    //         "#,
-    //         (
-    //             &task.id,
-    //             &task.session_id,
-    //             &task.task_description,
-    //             &task.agent_type,
-    //             &format!("{:?}", task.status),
-    //             &task.result,
-    //             &task.error,
-    //             &task.created_at.to_rfc3339(),
-    //             &task.completed_at.map(|dt| dt.to_rfc3339()),
-    //         )
-    //     )?;
-
-    //     Ok(())
-    // }
+    pub fn create_task(&self, task: &TaskResult) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        let usage = task.metadata.usage.as_ref();
+        conn.execute(
+            r#"
+            INSERT OR REPLACE INTO tasks 
+            (id, session_id, task_description, agent_type, status, result, error, created_at, completed_at,
+             model, category, retries, prompt_tokens, completion_tokens, cost_usd, prompt_templates,
+             planner_model, rule_matches)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)
+            "#,
+            rusqlite::params![
+                &task.id,
+                &task.session_id,
+                &task.task_description,
+                &task.agent_type,
+                &format!("{:?}", task.status),
+                &task.result,
+                &task.error,
+                &task.created_at.to_rfc3339(),
+                &task.completed_at.map(|dt| dt.to_rfc3339()),
+                &task.metadata.model,
+                &task.metadata.category,
+                task.metadata.retries,
+                usage.map(|u| u.prompt_tokens as i64),
+                usage.map(|u| u.completion_tokens as i64),
+                usage.and_then(|u| u.cost_usd),
+                serde_json::to_string(&task.metadata.prompt_templates)?,
+                &task.metadata.planner_model,
+                serde_json::to_string(&task.metadata.rule_matches)?,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_tasks(&self, agent_type: Option<&str>) -> Result<Vec<TaskResult>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT * FROM tasks WHERE (?1 IS NULL OR agent_type = ?1) ORDER BY created_at",
+        )?;
+
+        let task_iter = stmt.query_map([agent_type], |row| {
+            let status_str: String = row.get("status")?;
+            let status = match status_str.as_str() {
+                "Pending" => TaskStatus::Pending,
+                "InProgress" => TaskStatus::InProgress,
+                "Completed" => TaskStatus::Completed,
+                "Failed" => TaskStatus::Failed,
+                "Cancelled" => TaskStatus::Cancelled,
+                _ => TaskStatus::Pending,
+            };
+
+            let prompt_tokens: Option<i64> = row.get("prompt_tokens")?;
+            let completion_tokens: Option<i64> = row.get("completion_tokens")?;
+            let cost_usd: Option<f64> = row.get("cost_usd")?;
+            let usage = if prompt_tokens.is_some() || completion_tokens.is_some() || cost_usd.is_some() {
+                Some(TokenUsage {
+                    prompt_tokens: prompt_tokens.unwrap_or(0) as u64,
+                    completion_tokens: completion_tokens.unwrap_or(0) as u64,
+                    cost_usd,
+                })
+            } else {
+                None
+            };
+
+            Ok(TaskResult {
+                id: row.get("id")?,
+                session_id: row.get("session_id")?,
+                task_description: row.get("task_description")?,
+                agent_type: row.get("agent_type")?,
+                status,
+                result: row.get("result")?,
+                error: row.get("error")?,
+                created_at: parse_timestamp(row, "created_at")?,
+                completed_at: row
+                    .get::<_, Option<String>>("completed_at")?
+                    .map(|_| parse_timestamp(row, "completed_at"))
+                    .transpose()?,
+                metadata: TaskMetadata {
+                    model: row.get("model")?,
+                    category: row.get("category")?,
+                    retries: row.get("retries")?,
+                    usage,
+                    prompt_templates: row
+                        .get::<_, Option<String>>("prompt_templates")?
+                        .and_then(|json| serde_json::from_str(&json).ok())
+                        .unwrap_or_default(),
+                    planner_model: row.get("planner_model")?,
+                    rule_matches: row
+                        .get::<_, Option<String>>("rule_matches")?
+                        .and_then(|json| serde_json::from_str(&json).ok())
+                        .unwrap_or_default(),
+                },
+            })
+        })?;
+
+        let mut tasks = Vec::new();
+        for task in task_iter {
+            tasks.push(task?);
+        }
+
+        Ok(tasks)
+    }
+
+    pub fn record_llm_call(&self, session_id: &str, call: &LlmCall) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            r#"
+            INSERT INTO llm_calls
+            (id, session_id, purpose, model, prompt_tokens, completion_tokens, cost_usd, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            "#,
+            rusqlite::params![
+                uuid::Uuid::new_v4().to_string(),
+                session_id,
+                &call.purpose,
+                &call.model,
+                call.usage.prompt_tokens as i64,
+                call.usage.completion_tokens as i64,
+                call.usage.cost_usd,
+                chrono::Utc::now().to_rfc3339(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn record_tool_call(&self, session_id: &str, call: &ToolCallRecord) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            r#"
+            INSERT INTO planner_tool_calls
+            (id, session_id, name, arguments, output, error, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            "#,
+            rusqlite::params![
+                uuid::Uuid::new_v4().to_string(),
+                session_id,
+                &call.name,
+                call.arguments.to_string(),
+                &call.output,
+                &call.error,
+                chrono::Utc::now().to_rfc3339(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn set_plan_approval(&self, session_id: &str, required: bool) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE sessions SET require_plan_approval = ?1 WHERE id = ?2",
+            rusqlite::params![required, session_id],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn set_session_tags(&self, session_id: &str, tags: &[String]) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE sessions SET tags = ?1 WHERE id = ?2",
+            rusqlite::params![serde_json::to_string(tags)?, session_id],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn set_session_priority(&self, session_id: &str, priority: &SessionPriority) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE sessions SET priority = ?1 WHERE id = ?2",
+            rusqlite::params![format!("{:?}", priority), session_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Move a session to a new status and add the move to its status history.
+    pub fn record_status_change(&self, change: &StatusChange) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "UPDATE sessions SET status = ?1, updated_at = ?2 WHERE id = ?3",
+            rusqlite::params![format!("{:?}", change.to), change.created_at.to_rfc3339(), change.session_id],
+        )?;
+        tx.execute(
+            "INSERT INTO session_status_history (session_id, from_status, to_status, reason, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![
+                change.session_id,
+                change.from.as_ref().map(|status| format!("{:?}", status)),
+                format!("{:?}", change.to),
+                change.reason,
+                change.created_at.to_rfc3339(),
+            ],
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// A session's status changes, oldest first.
+    pub fn get_status_history(&self, session_id: &str) -> Result<Vec<StatusChange>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT from_status, to_status, reason, created_at FROM session_status_history
+             WHERE session_id = ?1 ORDER BY id",
+        )?;
+        let history = stmt
+            .query_map([session_id], |row| {
+                let created_at: String = row.get(3)?;
+                Ok(StatusChange {
+                    session_id: session_id.to_string(),
+                    from: row.get::<_, Option<String>>(0)?.map(|status| parse_session_status(&status)),
+                    to: parse_session_status(&row.get::<_, String>(1)?),
+                    reason: row.get(2)?,
+                    created_at: chrono::DateTime::parse_from_rfc3339(&created_at)
+                        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, Box::new(e)))?
+                        .with_timezone(&chrono::Utc),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(history)
+    }
+
+    pub fn set_session_worktree(&self, session_id: &str, worktree_path: Option<&str>, branch_name: Option<&str>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE sessions SET worktree_path = ?1, branch_name = ?2 WHERE id = ?3",
+            rusqlite::params![worktree_path, branch_name, session_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Delete a session and everything stored for it, apart from its task and LLM call
+    /// records, which agent statistics and project spending are built from.
+    pub fn delete_session(&self, session_id: &str) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        for table in [
+            "conversation_messages",
+            "pending_plans",
+            "conversation_summaries",
+            "plan_previews",
+            "ensemble_candidates",
+            "planner_tool_calls",
+            "session_status_history",
+        ] {
+            tx.execute(&format!("DELETE FROM {} WHERE session_id = ?1", table), [session_id])?;
+        }
+        tx.execute("DELETE FROM budgets WHERE scope = 'session' AND scope_id = ?1", [session_id])?;
+        tx.execute("DELETE FROM sessions WHERE id = ?1", [session_id])?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Record why a session needs repair, or clear it with `None`.
+    pub fn set_session_repair(&self, session_id: &str, reason: Option<&str>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE sessions SET needs_repair = ?1 WHERE id = ?2",
+            rusqlite::params![reason, session_id],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn save_pending_plan(&self, plan: &PendingPlan) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO pending_plans (session_id, plan, updated_at) VALUES (?1, ?2, ?3)",
+            rusqlite::params![plan.session_id, serde_json::to_string(plan)?, plan.updated_at.to_rfc3339()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_pending_plan(&self, session_id: &str) -> Result<Option<PendingPlan>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT plan FROM pending_plans WHERE session_id = ?1")?;
+        let mut rows = stmt.query_map([session_id], |row| row.get::<_, String>(0))?;
+
+        match rows.next().transpose()? {
+            Some(plan) => Ok(Some(serde_json::from_str(&plan)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn delete_pending_plan(&self, session_id: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM pending_plans WHERE session_id = ?1", [session_id])?;
+
+        Ok(())
+    }
+
+    /// Store a preview along with what planning it cost and the tools the planner called,
+    /// which are kept here rather than with the session's own calls.
+    pub fn save_plan_preview(&self, preview: &PlanPreview) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            r#"
+            INSERT INTO plan_previews
+            (id, session_id, request, preview, prompt_tokens, completion_tokens, cost_usd, tool_calls, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            "#,
+            rusqlite::params![
+                preview.id,
+                preview.session_id,
+                preview.request,
+                serde_json::to_string(preview)?,
+                preview.planning_usage.prompt_tokens as i64,
+                preview.planning_usage.completion_tokens as i64,
+                preview.planning_usage.cost_usd,
+                serde_json::to_string(&preview.decomposition.tool_calls)?,
+                preview.created_at.to_rfc3339(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn save_message(&self, message: &ConversationMessage) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            r#"
+            INSERT OR REPLACE INTO conversation_messages
+            (id, session_id, role, agent_type, content, pinned, created_at, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            "#,
+            rusqlite::params![
+                message.id,
+                message.session_id,
+                format!("{:?}", message.role),
+                message.agent_type,
+                message.content,
+                message.pinned,
+                message.created_at.to_rfc3339(),
+                chrono::Utc::now().to_rfc3339(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn update_message_content(&self, message_id: &str, content: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE conversation_messages SET content = ?1, updated_at = ?2 WHERE id = ?3",
+            rusqlite::params![content, chrono::Utc::now().to_rfc3339(), message_id],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn set_message_pinned(&self, message_id: &str, pinned: bool) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE conversation_messages SET pinned = ?1, updated_at = ?2 WHERE id = ?3",
+            rusqlite::params![pinned, chrono::Utc::now().to_rfc3339(), message_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// A session's conversation, oldest message first.
+    pub fn get_messages(&self, session_id: &str) -> Result<Vec<ConversationMessage>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT * FROM conversation_messages WHERE session_id = ?1 ORDER BY created_at, rowid",
+        )?;
+        let messages = stmt
+            .query_map([session_id], |row| {
+                let role = match row.get::<_, String>("role")?.as_str() {
+                    "User" => MessageRole::User,
+                    "Assistant" => MessageRole::Assistant,
+                    _ => MessageRole::System,
+                };
+                let created_at: String = row.get("created_at")?;
+
+                Ok(ConversationMessage {
+                    id: row.get("id")?,
+                    session_id: row.get("session_id")?,
+                    role,
+                    content: row.get("content")?,
+                    agent_type: row.get("agent_type")?,
+                    pinned: row.get("pinned")?,
+                    created_at: chrono::DateTime::parse_from_rfc3339(&created_at)
+                        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e)))?
+                        .with_timezone(&chrono::Utc),
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(messages)
+    }
+
+    pub fn save_ensemble_candidate(&self, candidate: &EnsembleCandidate) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO ensemble_candidates (id, session_id, candidate, created_at) VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![
+                candidate.id,
+                candidate.session_id,
+                serde_json::to_string(candidate)?,
+                candidate.created_at.to_rfc3339(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_ensemble_candidates(&self, session_id: &str) -> Result<Vec<EnsembleCandidate>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT candidate FROM ensemble_candidates WHERE session_id = ?1 ORDER BY created_at",
+        )?;
+        let candidates = stmt
+            .query_map([session_id], |row| row.get::<_, String>(0))?
+            .map(|candidate| Ok(serde_json::from_str(&candidate?)?))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(candidates)
+    }
+
+    pub fn save_conversation_summary(&self, summary: &ConversationSummary) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO conversation_summaries (session_id, summary, covered_messages, updated_at)
+             VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![
+                summary.session_id,
+                summary.summary,
+                summary.covered_messages as i64,
+                summary.updated_at.to_rfc3339(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_conversation_summary(&self, session_id: &str) -> Result<Option<ConversationSummary>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT summary, covered_messages, updated_at FROM conversation_summaries WHERE session_id = ?1",
+        )?;
+        let mut rows = stmt.query_map([session_id], |row| {
+            let updated_at: String = row.get(2)?;
+            Ok(ConversationSummary {
+                session_id: session_id.to_string(),
+                summary: row.get(0)?,
+                covered_messages: row.get::<_, i64>(1)? as usize,
+                updated_at: chrono::DateTime::parse_from_rfc3339(&updated_at)
+                    .map_err(|e| rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, Box::new(e)))?
+                    .with_timezone(&chrono::Utc),
+            })
+        })?;
+
+        Ok(rows.next().transpose()?)
+    }
+
+    /// Set the budget for a `"session"` or `"project"` scope.
+    pub fn set_budget(&self, scope: &str, scope_id: &str, budget: &Budget) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO budgets (scope, scope_id, limit_usd, warn_threshold, updated_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![scope, scope_id, budget.limit_usd, budget.warn_threshold, chrono::Utc::now().to_rfc3339()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_budget(&self, scope: &str, scope_id: &str) -> Result<Option<Budget>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT limit_usd, warn_threshold FROM budgets WHERE scope = ?1 AND scope_id = ?2")?;
+        let mut rows = stmt.query_map([scope, scope_id], |row| {
+            Ok(Budget {
+                limit_usd: row.get(0)?,
+                warn_threshold: row.get(1)?,
+            })
+        })?;
+
+        Ok(rows.next().transpose()?)
+    }
+
+    /// Token and cost totals for a session and for every session of its project, covering
+    /// agent tasks, middle-manager LLM calls and the planning done for previews.
+    pub fn get_usage_summary(&self, session_id: &str) -> Result<Option<UsageSummary>> {
+        let project_path: Option<String> = {
+            let conn = self.conn.lock().unwrap();
+            let mut stmt = conn.prepare("SELECT project_path FROM sessions WHERE id = ?1")?;
+            let mut rows = stmt.query_map([session_id], |row| row.get(0))?;
+            rows.next().transpose()?
+        };
+        let Some(project_path) = project_path else {
+            return Ok(None);
+        };
+
+        let session_usage = self.sum_usage("SELECT ?1", session_id)?;
+        let project_usage = self.sum_usage("SELECT id FROM sessions WHERE project_path = ?1", &project_path)?;
+        let mut summary = UsageSummary {
+            session_id: session_id.to_string(),
+            project_path: project_path.clone(),
+            session_usage,
+            project_usage,
+            session_budget: self.get_budget("session", session_id)?,
+            project_budget: self.get_budget("project", &project_path)?,
+            status: BudgetStatus::Ok,
+        };
+        summary.status = crate::cost_tracker::overall_budget_status(&summary);
+
+        Ok(Some(summary))
+    }
+
+    fn sum_usage(&self, session_ids_query: &str, param: &str) -> Result<TokenUsage> {
+        let conn = self.conn.lock().unwrap();
+        let sql = format!(
+            r#"
+            SELECT COALESCE(SUM(prompt_tokens), 0), COALESCE(SUM(completion_tokens), 0), SUM(cost_usd)
+            FROM (
+                SELECT prompt_tokens, completion_tokens, cost_usd FROM tasks WHERE session_id IN ({0})
+                UNION ALL
+                SELECT prompt_tokens, completion_tokens, cost_usd FROM llm_calls WHERE session_id IN ({0})
+                UNION ALL
+                SELECT prompt_tokens, completion_tokens, cost_usd FROM plan_previews WHERE session_id IN ({0})
+            )
+            "#,
+            session_ids_query
+        );
+
+        let usage = conn.query_row(&sql, [param], |row| {
+            Ok(TokenUsage {
+                prompt_tokens: row.get::<_, i64>(0)? as u64,
+                completion_tokens: row.get::<_, i64>(1)? as u64,
+                cost_usd: row.get(2)?,
+            })
+        })?;
+
+        Ok(usage)
+    }
+}
+
+fn parse_timestamp(row: &rusqlite::Row, column: &str) -> rusqlite::Result<chrono::DateTime<chrono::Utc>> {
+    let index = row.as_ref().column_index(column)?;
+    let value: String = row.get(index)?;
+    chrono::DateTime::parse_from_rfc3339(&value)
+        .map(|dt| dt.with_timezone(&chrono::Utc))
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+fn parse_session_status(status: &str) -> SessionStatus {
+    match status {
+        "Created" => SessionStatus::Created,
+        "Active" => SessionStatus::Active,
+        "Paused" => SessionStatus::Paused,
+        "Completed" => SessionStatus::Completed,
+        "Failed" => SessionStatus::Failed,
+        "Archived" => SessionStatus::Archived,
+        _ => SessionStatus::Created,
+    }
 }
 
 static mut DATABASE: Option<Database> = None;
@@ -197,19 +874,143 @@ pub async fn store_agent_config(config: &AgentConfig) -> Result<(), Box<dyn std:
     Ok(())
 }
 
-pub async fn store_message(session_id: &str, message: &ConversationMessage) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let db = get_database();
-    let conn = db.conn.lock().unwrap();
-    conn.execute(
-        "INSERT INTO agent_messages (id, from_agent, to_agent, message_type, content, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
-        (
-            &uuid::Uuid::new_v4().to_string(),
-            &format!("{:?}", message.role),
-            &session_id,
-            "user_message",
-            &message.content,
-            &message.created_at.to_rfc3339(),
-        ),
-    )?;
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_conversation_messages_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let database = Database::new(dir.path().join("agenttool.db").to_str().unwrap()).unwrap();
+        let message = |id: &str, role: MessageRole, content: &str| ConversationMessage {
+            id: id.to_string(),
+            session_id: "session".to_string(),
+            role,
+            content: content.to_string(),
+            agent_type: None,
+            pinned: false,
+            created_at: chrono::Utc::now(),
+        };
+
+        database.save_message(&message("1", MessageRole::User, "fix the parser")).unwrap();
+        database.save_message(&message("2", MessageRole::Assistant, "Planning...")).unwrap();
+        database.update_message_content("2", "Task decomposition: sequential").unwrap();
+        database.set_message_pinned("1", true).unwrap();
+
+        let messages = database.get_messages("session").unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(matches!(messages[0].role, MessageRole::User));
+        assert!(messages[0].pinned);
+        assert_eq!(messages[1].content, "Task decomposition: sequential");
+        assert!(database.get_messages("other").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_session_state_survives_reopening() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("agenttool.db");
+        let database = Database::new(path.to_str().unwrap()).unwrap();
+        database
+            .create_session(&Session {
+                id: "session".to_string(),
+                name: "parser".to_string(),
+                project_path: "/tmp/project".to_string(),
+                description: None,
+                status: SessionStatus::Active,
+                created_at: chrono::Utc::now(),
+                updated_at: chrono::Utc::now(),
+                worktree_path: Some("/tmp/worktrees/session".to_string()),
+                branch_name: Some("session/session".to_string()),
+                require_plan_approval: true,
+                tags: vec!["backend".to_string()],
+                priority: SessionPriority::High,
+                needs_repair: None,
+            })
+            .unwrap();
+        database
+            .record_status_change(&StatusChange {
+                session_id: "session".to_string(),
+                from: Some(SessionStatus::Active),
+                to: SessionStatus::Paused,
+                reason: Some("paused by the user".to_string()),
+                created_at: chrono::Utc::now(),
+            })
+            .unwrap();
+        database.set_session_repair("session", Some("worktree is gone")).unwrap();
+        database
+            .save_conversation_summary(&ConversationSummary {
+                session_id: "session".to_string(),
+                summary: "The user wants the parser fixed".to_string(),
+                covered_messages: 4,
+                updated_at: chrono::Utc::now(),
+            })
+            .unwrap();
+        drop(database);
+
+        let database = Database::new(path.to_str().unwrap()).unwrap();
+        let sessions = database.get_sessions().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert!(matches!(sessions[0].status, SessionStatus::Paused));
+        let history = database.get_status_history("session").unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].from, Some(SessionStatus::Active));
+        assert_eq!(sessions[0].priority, SessionPriority::High);
+        assert_eq!(sessions[0].needs_repair.as_deref(), Some("worktree is gone"));
+        let summary = database.get_conversation_summary("session").unwrap().unwrap();
+        assert_eq!(summary.covered_messages, 4);
+        assert!(database.get_pending_plan("session").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_preview_planning_counts_without_touching_session_calls() {
+        let dir = TempDir::new().unwrap();
+        let database = Database::new(dir.path().join("agenttool.db").to_str().unwrap()).unwrap();
+        database
+            .create_session(&Session {
+                id: "session".to_string(),
+                name: "parser".to_string(),
+                project_path: "/tmp/project".to_string(),
+                description: None,
+                status: SessionStatus::Active,
+                created_at: chrono::Utc::now(),
+                updated_at: chrono::Utc::now(),
+                worktree_path: None,
+                branch_name: None,
+                require_plan_approval: false,
+                tags: vec![],
+                priority: SessionPriority::Normal,
+                needs_repair: None,
+            })
+            .unwrap();
+        let decomposition = serde_json::from_value(serde_json::json!({
+            "strategy": "delegate",
+            "reasoning": "one step",
+            "subtasks": [],
+            "llm_calls": [
+                {"purpose": "plan", "model": "test-model", "usage": {"prompt_tokens": 500, "completion_tokens": 100, "cost_usd": 0.01}}
+            ],
+            "tool_calls": [
+                {"name": "read_file", "arguments": {"path": "src/lib.rs"}, "output": "fn main() {}", "error": null}
+            ]
+        }))
+        .unwrap();
+        let preview = PlanPreview::new("session", "fix the parser", decomposition, &[], |_| None);
+        database.save_plan_preview(&preview).unwrap();
+
+        let summary = database.get_usage_summary("session").unwrap().unwrap();
+        assert_eq!(summary.session_usage.prompt_tokens, 500);
+        assert_eq!(summary.project_usage.completion_tokens, 100);
+
+        let conn = database.conn.lock().unwrap();
+        let count = |table: &str| -> i64 {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0)).unwrap()
+        };
+        assert_eq!(count("llm_calls"), 0);
+        assert_eq!(count("planner_tool_calls"), 0);
+        let tool_calls: String = conn
+            .query_row("SELECT tool_calls FROM plan_previews WHERE id = ?1", [&preview.id], |row| row.get(0))
+            .unwrap();
+        assert!(tool_calls.contains("read_file"));
+    }
 }
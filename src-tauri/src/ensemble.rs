@@ -0,0 +1,168 @@
+use crate::git_worktree_manager::DiffStat;
+use crate::models::TaskStatus;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Agents that can take part in an ensemble: the ones that change code.
+pub const ENSEMBLE_AGENTS: [&str; 2] = ["claude_code", "gemini_cli"];
+
+/// Longest diff shown to the judge for each candidate.
+const MAX_JUDGED_DIFF_CHARS: usize = 8000;
+/// Longest agent output shown to the judge for each candidate.
+const MAX_JUDGED_OUTPUT_CHARS: usize = 2000;
+
+/// One agent's attempt at an ensemble subtask. The winner is applied to the session
+/// worktree; the others stay on their branches and worktrees for inspection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnsembleCandidate {
+    pub id: String,
+    pub session_id: String,
+    pub subtask_id: String,
+    pub subtask_description: String,
+    pub agent: String,
+    pub branch: String,
+    /// Cleared once the worktree is removed, as it is for the winner.
+    pub worktree_path: Option<String>,
+    pub status: TaskStatus,
+    pub output: Option<String>,
+    pub error: Option<String>,
+    /// Whether the subtask's acceptance checks passed, or `None` when it has none.
+    pub checks_passed: Option<bool>,
+    /// The commit holding the candidate's changes, if it made any.
+    pub commit: Option<String>,
+    pub diff: DiffStat,
+    pub winner: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A candidate as described to the middle manager for judging.
+#[derive(Debug, Clone, Serialize)]
+pub struct CandidateReport {
+    pub agent: String,
+    pub status: String,
+    pub checks: String,
+    pub diff_stat: String,
+    pub diff: String,
+    pub output: String,
+}
+
+impl CandidateReport {
+    pub fn new(candidate: &EnsembleCandidate, diff: &str) -> Self {
+        let checks = match candidate.checks_passed {
+            Some(true) => "passed",
+            Some(false) => "failed",
+            None => "none defined",
+        };
+        let output = candidate.output.as_deref().or(candidate.error.as_deref()).unwrap_or("");
+        Self {
+            agent: candidate.agent.clone(),
+            status: format!("{:?}", candidate.status),
+            checks: checks.to_string(),
+            diff_stat: format!(
+                "{} file(s) changed, +{} -{}",
+                candidate.diff.files_changed, candidate.diff.insertions, candidate.diff.deletions
+            ),
+            diff: shorten(diff, MAX_JUDGED_DIFF_CHARS),
+            output: shorten(output, MAX_JUDGED_OUTPUT_CHARS),
+        }
+    }
+}
+
+fn shorten(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        text.to_string()
+    } else {
+        format!("{}\n[... truncated ...]", text.chars().take(max_chars).collect::<String>())
+    }
+}
+
+/// Candidates still in the running: those that completed, narrowed to the ones whose
+/// acceptance checks did not fail unless every one of them failed.
+pub fn contenders(candidates: &[EnsembleCandidate]) -> Vec<usize> {
+    let completed: Vec<usize> = (0..candidates.len())
+        .filter(|index| matches!(candidates[*index].status, TaskStatus::Completed))
+        .collect();
+    let passing: Vec<usize> = completed
+        .iter()
+        .copied()
+        .filter(|index| candidates[*index].checks_passed != Some(false))
+        .collect();
+
+    if passing.is_empty() {
+        completed
+    } else {
+        passing
+    }
+}
+
+/// Pick the winning candidate: the judge's choice among the contenders when it made one,
+/// otherwise the contender with the smallest change that actually changed something.
+pub fn pick_winner(candidates: &[EnsembleCandidate], judged: Option<&str>) -> Option<usize> {
+    let contenders = contenders(candidates);
+    if let Some(index) = contenders.iter().find(|index| Some(candidates[**index].agent.as_str()) == judged) {
+        return Some(*index);
+    }
+    contenders
+        .into_iter()
+        .min_by_key(|index| (candidates[*index].diff.is_empty(), candidates[*index].diff.lines_changed()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(agent: &str, status: TaskStatus, checks_passed: Option<bool>, lines: u64) -> EnsembleCandidate {
+        EnsembleCandidate {
+            id: agent.to_string(),
+            session_id: "session".to_string(),
+            subtask_id: "1".to_string(),
+            subtask_description: "fix the parser".to_string(),
+            agent: agent.to_string(),
+            branch: format!("ensemble/session/1-{}", agent),
+            worktree_path: None,
+            status,
+            output: None,
+            error: None,
+            checks_passed,
+            commit: None,
+            diff: DiffStat {
+                files_changed: lines.min(1),
+                insertions: lines,
+                deletions: 0,
+            },
+            winner: false,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_pick_winner() {
+        let candidates = vec![
+            candidate("claude_code", TaskStatus::Completed, Some(true), 40),
+            candidate("gemini_cli", TaskStatus::Completed, Some(false), 5),
+        ];
+        // Failing checks rule a candidate out, whatever the judge thinks
+        assert_eq!(pick_winner(&candidates, Some("gemini_cli")), Some(0));
+
+        let candidates = vec![
+            candidate("claude_code", TaskStatus::Completed, None, 40),
+            candidate("gemini_cli", TaskStatus::Completed, None, 5),
+        ];
+        assert_eq!(pick_winner(&candidates, Some("claude_code")), Some(0));
+        // Without a judgement the smaller change wins
+        assert_eq!(pick_winner(&candidates, None), Some(1));
+
+        // An agent that changed nothing loses to one that did
+        let candidates = vec![
+            candidate("claude_code", TaskStatus::Completed, None, 0),
+            candidate("gemini_cli", TaskStatus::Completed, None, 12),
+        ];
+        assert_eq!(pick_winner(&candidates, None), Some(1));
+
+        let candidates = vec![
+            candidate("claude_code", TaskStatus::Failed, None, 3),
+            candidate("gemini_cli", TaskStatus::Failed, None, 3),
+        ];
+        assert_eq!(pick_winner(&candidates, Some("claude_code")), None);
+    }
+}
@@ -1,4 +1,6 @@
 use crate::models::*;
+use crate::prompt_templates::{self, RenderedPrompt};
+use std::path::Path;
 use std::process::Stdio;
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
@@ -14,7 +16,7 @@ pub struct GeminiCliAdapter {
 struct GeminiCliProcess {
     child: Child,
     _session_id: String,
-    _project_path: String,
+    project_path: String,
     permissions: AgentPermissions,
 }
 
@@ -80,7 +82,7 @@ impl GeminiCliAdapter {
         let process = GeminiCliProcess {
             child,
             _session_id: session_id.clone(),
-            _project_path: project_path,
+            project_path,
             permissions,
         };
 
@@ -97,11 +99,11 @@ impl GeminiCliAdapter {
         context: Option<&str>,
     ) -> Result<TaskResult> {
         // Check permissions without holding the lock
-        let permissions = {
+        let (permissions, project_path) = {
             let processes = self.processes.lock().unwrap();
             let process = processes.get(session_id)
                 .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
-            process.permissions.clone()
+            (process.permissions.clone(), process.project_path.clone())
         };
 
         // Validate task against permissions
@@ -116,24 +118,29 @@ impl GeminiCliAdapter {
                 error: Some("Task not allowed by current permissions".to_string()),
                 created_at: chrono::Utc::now(),
                 completed_at: Some(chrono::Utc::now()),
+                metadata: TaskMetadata::default(),
             });
         }
 
         // For now, return a mock result since we can't safely execute with the current architecture
         // In a real implementation, we'd need to redesign the process management to use channels
         // or other async-safe communication patterns
-        let gemini_prompt = self.format_gemini_prompt(task, context);
-        
+        let gemini_prompt = self.format_gemini_prompt(task, context, Path::new(&project_path));
+
         Ok(TaskResult {
             id: uuid::Uuid::new_v4().to_string(),
             session_id: session_id.to_string(),
             task_description: task.to_string(),
             agent_type: "gemini_cli".to_string(),
             status: TaskStatus::Completed,
-            result: Some(format!("Gemini CLI would execute: {}", gemini_prompt)),
+            result: Some(format!("Gemini CLI would execute: {}", gemini_prompt.text)),
             error: None,
             created_at: chrono::Utc::now(),
             completed_at: Some(chrono::Utc::now()),
+            metadata: TaskMetadata {
+                prompt_templates: vec![gemini_prompt.template],
+                ..TaskMetadata::default()
+            },
         })
     }
 
@@ -187,20 +194,12 @@ impl GeminiCliAdapter {
     //     }
     // }
 
-    fn format_gemini_prompt(&self, task: &str, context: Option<&str>) -> String {
-        let mut prompt = String::new();
-        
-        if let Some(ctx) = context {
-            prompt.push_str("Context: ");
-            prompt.push_str(ctx);
-            prompt.push_str("\n\n");
-        }
-        
-        prompt.push_str("Task: ");
-        prompt.push_str(task);
-        prompt.push_str("\n\nPlease provide a clear and concise response.");
-        
-        prompt
+    fn format_gemini_prompt(&self, task: &str, context: Option<&str>, project_path: &Path) -> RenderedPrompt {
+        prompt_templates::render(
+            "gemini_task",
+            Some(project_path),
+            minijinja::context! { task => task, context => context },
+        )
     }
 
     // fn clean_gemini_output(&self, output: &str) -> String {
@@ -252,17 +251,22 @@ impl GeminiCliAdapter {
     //     Ok(())
     // }
 
-    pub async fn execute_quick_task(&self, task: &str, project_path: &str) -> Result<TaskResult> {
-        // For quick one-off tasks, spawn a temporary process
-        let session_id = uuid::Uuid::new_v4().to_string();
-        
-        let permissions = AgentPermissions {
+    /// Quick tasks may read the project and use the network, but not write or spawn processes.
+    pub fn quick_task_permissions(project_path: &str) -> AgentPermissions {
+        AgentPermissions {
             file_read: true,
             file_write: false,
             network_access: true,
             process_spawn: false,
             allowed_paths: vec![project_path.to_string()],
-        };
+        }
+    }
+
+    pub async fn execute_quick_task(&self, task: &str, project_path: &str) -> Result<TaskResult> {
+        // For quick one-off tasks, spawn a temporary process
+        let session_id = uuid::Uuid::new_v4().to_string();
+        
+        let permissions = Self::quick_task_permissions(project_path);
 
         self.start_session(session_id.clone(), project_path.to_string(), permissions).await?;
         let result = self.execute_task(&session_id, task, None).await?;
@@ -288,5 +292,6 @@ pub async fn execute_task(request: crate::commands::ExecuteTaskRequest) -> Resul
         error: None,
         created_at: chrono::Utc::now(),
         completed_at: Some(chrono::Utc::now()),
+        metadata: TaskMetadata::default(),
     })
 }
@@ -1,8 +1,30 @@
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 // use uuid::Uuid; // Removed unused import
 
+/// Written into each session worktree; never part of the session's commits.
+const SESSION_METADATA_FILE: &str = ".agenttool-session.json";
+
+/// How much a branch changed, as reported by `git diff --numstat`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DiffStat {
+    pub files_changed: u64,
+    pub insertions: u64,
+    pub deletions: u64,
+}
+
+impl DiffStat {
+    pub fn lines_changed(&self) -> u64 {
+        self.insertions + self.deletions
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files_changed == 0
+    }
+}
+
 pub struct GitWorktreeManager {
     base_worktree_dir: PathBuf,
 }
@@ -63,38 +85,61 @@ impl GitWorktreeManager {
         Ok(worktree_path)
     }
 
-    // Commented out unused methods to remove dead code warnings
-    // /// Remove a git worktree
-    // pub async fn remove_worktree(&self, project_path: &Path, worktree_path: &Path) -> Result<()> {
-    //     // Get the branch name before removing the worktree
-    //     let branch_name = self.get_worktree_branch(worktree_path)?;
+    /// Create a worktree on a new branch starting at `start_point`, for work that may be
+    /// thrown away. Unlike session worktrees, the main checkout is left untouched.
+    pub async fn create_scratch_worktree(
+        &self,
+        project_path: &Path,
+        name: &str,
+        branch_name: &str,
+        start_point: &str,
+    ) -> Result<PathBuf> {
+        tokio::fs::create_dir_all(&self.base_worktree_dir).await?;
+        let worktree_path = self.base_worktree_dir.join(name);
 
-    //     // Remove the worktree
-    //     let output = Command::new("git")
-    //         .current_dir(project_path)
-    //         .args([
-    //             "worktree",
-    //             "remove",
-    //             "--force",
-    //             worktree_path.to_str().unwrap(),
-    //         ])
-    //         .output()?;
+        let output = Command::new("git")
+            .current_dir(project_path)
+            .args(["worktree", "add", "-b", branch_name])
+            .arg(&worktree_path)
+            .arg(start_point)
+            .output()?;
 
-    //     if !output.status.success() {
-    //         eprintln!(
-    //             "Warning: Failed to remove worktree {}: {}",
-    //             worktree_path.display(),
-    //             String::from_utf8_lossy(&output.stderr)
-    //         );
-    //     }
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Failed to create worktree: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
 
-    //     // Clean up the branch if it was a session branch
-    //     if branch_name.starts_with("session/") {
-    //         let _ = self.delete_branch(project_path, &branch_name);
-    //     }
+        Ok(worktree_path)
+    }
 
-    //     Ok(())
-    // }
+    /// Remove a git worktree, deleting its branch too unless `keep_branch` is set
+    pub async fn remove_worktree(&self, project_path: &Path, worktree_path: &Path, keep_branch: bool) -> Result<()> {
+        // Get the branch name before removing the worktree
+        let branch_name = self.get_worktree_branch(worktree_path).ok();
+
+        // Remove the worktree
+        let output = Command::new("git")
+            .current_dir(project_path)
+            .args(["worktree", "remove", "--force"])
+            .arg(worktree_path)
+            .output()?;
+
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Failed to remove worktree {}: {}",
+                worktree_path.display(),
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        if let Some(branch_name) = branch_name.filter(|branch| !keep_branch && !branch.is_empty()) {
+            self.delete_branch(project_path, &branch_name)?;
+        }
+
+        Ok(())
+    }
 
     // /// Squash commits in a worktree and merge to main branch
     // pub async fn squash_and_merge_to_main(
@@ -183,22 +228,85 @@ impl GitWorktreeManager {
     //     Ok(())
     // }
 
-    // /// Get the current branch of a worktree
-    // pub fn get_worktree_branch(&self, worktree_path: &Path) -> Result<String> {
-    //     let output = Command::new("git")
-    //         .current_dir(worktree_path)
-    //         .args(["branch", "--show-current"])
-    //         .output()?;
+    /// Number of commits on `branch` that the project's main branch doesn't have
+    pub fn unmerged_commits(&self, project_path: &Path, branch: &str) -> Result<u64> {
+        let main_branch = self.get_main_branch(project_path).unwrap_or("main".to_string());
+        let count = run_git(project_path, &["rev-list", "--count", &format!("{}..{}", main_branch, branch)])?;
+        Ok(count.trim().parse()?)
+    }
 
-    //     if !output.status.success() {
-    //         return Err(anyhow::anyhow!(
-    //             "Failed to get current branch: {}",
-    //             String::from_utf8_lossy(&output.stderr)
-    //         ));
-    //     }
+    /// Whether a worktree has changes that aren't committed, apart from AgentTool's own metadata
+    pub fn has_uncommitted_changes(&self, worktree_path: &Path) -> Result<bool> {
+        let status = run_git(
+            worktree_path,
+            &["status", "--porcelain", "--", ".", &format!(":!{}", SESSION_METADATA_FILE)],
+        )?;
+        Ok(!status.trim().is_empty())
+    }
 
-    //     Ok(String::from_utf8(output.stdout)?.trim().to_string())
-    // }
+    /// Get the current branch of a worktree
+    pub fn get_worktree_branch(&self, worktree_path: &Path) -> Result<String> {
+        Ok(run_git(worktree_path, &["branch", "--show-current"])?.trim().to_string())
+    }
+
+    /// The commit checked out in a worktree
+    pub fn head_commit(&self, worktree_path: &Path) -> Result<String> {
+        Ok(run_git(worktree_path, &["rev-parse", "HEAD"])?.trim().to_string())
+    }
+
+    /// Commit everything that changed in a worktree, apart from AgentTool's own metadata.
+    /// Returns the new commit, or `None` when there was nothing to commit.
+    pub fn commit_all(&self, worktree_path: &Path, message: &str) -> Result<Option<String>> {
+        run_git(worktree_path, &["add", "-A", "--", ".", &format!(":!{}", SESSION_METADATA_FILE)])?;
+
+        let staged = Command::new("git")
+            .current_dir(worktree_path)
+            .args(["diff", "--cached", "--quiet"])
+            .status()?;
+        if staged.success() {
+            return Ok(None);
+        }
+
+        run_git(
+            worktree_path,
+            &["-c", "user.name=AgentTool", "-c", "user.email=agenttool@localhost", "commit", "--no-verify", "-m", message],
+        )?;
+        Ok(Some(self.head_commit(worktree_path)?))
+    }
+
+    /// Size of the changes from `base` to the worktree's HEAD
+    pub fn diff_stat(&self, worktree_path: &Path, base: &str) -> Result<DiffStat> {
+        let numstat = run_git(worktree_path, &["diff", "--numstat", base, "HEAD"])?;
+        let mut stat = DiffStat::default();
+        for line in numstat.lines() {
+            let mut fields = line.split('\t');
+            // Binary files show "-" for both counts
+            let insertions = fields.next().and_then(|n| n.parse::<u64>().ok()).unwrap_or(0);
+            let deletions = fields.next().and_then(|n| n.parse::<u64>().ok()).unwrap_or(0);
+            stat.files_changed += 1;
+            stat.insertions += insertions;
+            stat.deletions += deletions;
+        }
+        Ok(stat)
+    }
+
+    /// The changes from `base` to the worktree's HEAD as a patch
+    pub fn diff(&self, worktree_path: &Path, base: &str) -> Result<String> {
+        run_git(worktree_path, &["diff", base, "HEAD"])
+    }
+
+    /// Apply a commit from another branch on top of the worktree's branch. A conflicting
+    /// pick is aborted so the worktree is left as it was.
+    pub fn cherry_pick(&self, worktree_path: &Path, commit: &str) -> Result<()> {
+        let result = run_git(
+            worktree_path,
+            &["-c", "user.name=AgentTool", "-c", "user.email=agenttool@localhost", "cherry-pick", commit],
+        );
+        if result.is_err() {
+            let _ = run_git(worktree_path, &["cherry-pick", "--abort"]);
+        }
+        result.map(|_| ())
+    }
 
     /// Get the main branch name (main, master, or develop)
     pub fn get_main_branch(&self, project_path: &Path) -> Option<String> {
@@ -320,23 +428,28 @@ impl GitWorktreeManager {
         Ok(())
     }
 
-    // /// Delete a branch
-    // fn delete_branch(&self, project_path: &Path, branch_name: &str) -> Result<()> {
-    //     let output = Command::new("git")
-    //         .current_dir(project_path)
-    //         .args(["branch", "-D", branch_name])
-    //         .output()?;
+    /// Whether a local branch exists
+    pub fn branch_exists(&self, project_path: &Path, branch_name: &str) -> bool {
+        run_git(project_path, &["show-ref", "--verify", "--quiet", &format!("refs/heads/{}", branch_name)]).is_ok()
+    }
 
-    //     if !output.status.success() {
-    //         return Err(anyhow::anyhow!(
-    //             "Failed to delete branch {}: {}",
-    //             branch_name,
-    //             String::from_utf8_lossy(&output.stderr)
-    //         ));
-    //     }
+    /// Delete a branch
+    pub fn delete_branch(&self, project_path: &Path, branch_name: &str) -> Result<()> {
+        let output = Command::new("git")
+            .current_dir(project_path)
+            .args(["branch", "-D", branch_name])
+            .output()?;
 
-    //     Ok(())
-    // }
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "Failed to delete branch {}: {}",
+                branch_name,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        Ok(())
+    }
 
     /// Initialize worktree with session metadata
     async fn initialize_worktree_metadata(
@@ -352,7 +465,7 @@ impl GitWorktreeManager {
             "agent_tool_version": env!("CARGO_PKG_VERSION"),
         });
 
-        let metadata_path = worktree_path.join(".agenttool-session.json");
+        let metadata_path = worktree_path.join(SESSION_METADATA_FILE);
         tokio::fs::write(metadata_path, metadata.to_string()).await?;
 
         Ok(())
@@ -386,6 +499,19 @@ impl GitWorktreeManager {
     // }
 }
 
+/// Run a git command in `dir`, returning its stdout or failing with its stderr
+fn run_git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git").current_dir(dir).args(args).output()?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
 // Commented out to remove dead code warning - fields are never read
 // #[derive(Debug, Clone, Default)]
 // pub struct WorktreeInfo {
@@ -404,4 +530,38 @@ mod tests {
         // This test would require a git repository setup
         // Implementation would depend on test infrastructure
     }
+
+    #[tokio::test]
+    async fn test_scratch_worktree_changes_can_be_applied() {
+        let repo = TempDir::new().unwrap();
+        let worktrees = TempDir::new().unwrap();
+        run_git(repo.path(), &["init", "-q"]).unwrap();
+        std::fs::write(repo.path().join("lib.rs"), "fn main() {}\n").unwrap();
+
+        let manager = GitWorktreeManager::new(worktrees.path().to_path_buf());
+        let base = manager.commit_all(repo.path(), "Initial commit").unwrap().unwrap();
+        assert_eq!(manager.commit_all(repo.path(), "Nothing to do").unwrap(), None);
+
+        let scratch = manager
+            .create_scratch_worktree(repo.path(), "attempt", "ensemble/attempt", &base)
+            .await
+            .unwrap();
+        std::fs::write(scratch.join("lib.rs"), "fn main() {\n    run();\n}\n").unwrap();
+        std::fs::write(scratch.join(SESSION_METADATA_FILE), "{}").unwrap();
+        assert!(manager.has_uncommitted_changes(&scratch).unwrap());
+        let commit = manager.commit_all(&scratch, "Attempt").unwrap().unwrap();
+        assert!(!manager.has_uncommitted_changes(&scratch).unwrap());
+        assert_eq!(manager.unmerged_commits(repo.path(), "ensemble/attempt").unwrap(), 1);
+
+        let stat = manager.diff_stat(&scratch, &base).unwrap();
+        assert_eq!(stat, DiffStat { files_changed: 1, insertions: 3, deletions: 1 });
+        assert!(manager.diff(&scratch, &base).unwrap().contains("+    run();"));
+
+        manager.cherry_pick(repo.path(), &commit).unwrap();
+        assert!(std::fs::read_to_string(repo.path().join("lib.rs")).unwrap().contains("run();"));
+
+        manager.remove_worktree(repo.path(), &scratch, false).await.unwrap();
+        assert!(!scratch.exists());
+        assert!(!manager.branch_exists(repo.path(), "ensemble/attempt"));
+    }
 }
\ No newline at end of file
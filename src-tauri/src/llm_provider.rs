@@ -0,0 +1,1357 @@
+use crate::models::TokenUsage;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    OpenRouter,
+    Anthropic,
+    OpenAiCompatible,
+    Ollama,
+}
+
+impl ProviderKind {
+    pub fn default_base_url(&self) -> &'static str {
+        match self {
+            ProviderKind::OpenRouter => "https://openrouter.ai/api/v1",
+            ProviderKind::Anthropic => "https://api.anthropic.com/v1",
+            ProviderKind::OpenAiCompatible => "https://api.openai.com/v1",
+            ProviderKind::Ollama => "http://localhost:11434",
+        }
+    }
+
+    fn api_key_env_var(&self) -> Option<&'static str> {
+        match self {
+            ProviderKind::OpenRouter => Some("OPENROUTER_API_KEY"),
+            ProviderKind::Anthropic => Some("ANTHROPIC_API_KEY"),
+            ProviderKind::OpenAiCompatible => Some("OPENAI_API_KEY"),
+            ProviderKind::Ollama => None,
+        }
+    }
+}
+
+impl std::str::FromStr for ProviderKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "openrouter" => Ok(ProviderKind::OpenRouter),
+            "anthropic" => Ok(ProviderKind::Anthropic),
+            "openai" | "openai_compatible" => Ok(ProviderKind::OpenAiCompatible),
+            "ollama" => Ok(ProviderKind::Ollama),
+            other => Err(format!("Unknown LLM provider: {}", other)),
+        }
+    }
+}
+
+/// Connection settings for one LLM provider.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderConfig {
+    pub kind: ProviderKind,
+    pub base_url: String,
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+}
+
+/// How transient failures are retried. Delays grow exponentially from `base_delay_ms` up to
+/// `max_delay_ms`, with jitter. A rate limit's `Retry-After` is honoured unless it asks for
+/// longer than `max_retry_after_secs`, in which case the call fails straight away.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub max_retry_after_secs: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay_ms: 500,
+            max_delay_ms: 30_000,
+            max_retry_after_secs: 120,
+        }
+    }
+}
+
+/// After `failure_threshold` consecutive transient failures the provider is left alone for
+/// `cooldown_secs`, then a single call is let through to probe whether it has recovered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    pub failure_threshold: u32,
+    pub cooldown_secs: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            cooldown_secs: 60,
+        }
+    }
+}
+
+fn default_timeout_secs() -> u64 {
+    120
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    10
+}
+
+impl ProviderConfig {
+    pub fn new(kind: ProviderKind, api_key: Option<String>) -> Self {
+        Self {
+            kind,
+            base_url: kind.default_base_url().to_string(),
+            api_key,
+            headers: HashMap::new(),
+            timeout_secs: default_timeout_secs(),
+            connect_timeout_secs: default_connect_timeout_secs(),
+            retry: RetryPolicy::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+        }
+    }
+
+    /// Build the provider configuration from the environment.
+    ///
+    /// `AGENT_TOOL_LLM_CONFIG` may point at a JSON file holding a full configuration.
+    /// Otherwise `AGENT_TOOL_LLM_PROVIDER`, `AGENT_TOOL_LLM_BASE_URL`, `AGENT_TOOL_LLM_API_KEY`,
+    /// `AGENT_TOOL_LLM_HEADERS` (a JSON object) and `AGENT_TOOL_LLM_TIMEOUT_SECS` override the
+    /// OpenRouter defaults, with the provider's usual API key variable as a fallback.
+    pub fn from_env() -> Self {
+        if let Ok(path) = std::env::var("AGENT_TOOL_LLM_CONFIG") {
+            match std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| serde_json::from_str::<ProviderConfig>(&contents).map_err(|e| e.to_string()))
+            {
+                Ok(config) => return config,
+                Err(e) => eprintln!("Warning: Ignoring LLM config at {}: {}", path, e),
+            }
+        }
+
+        let kind = std::env::var("AGENT_TOOL_LLM_PROVIDER")
+            .ok()
+            .and_then(|value| value.parse().map_err(|e| eprintln!("Warning: {}", e)).ok())
+            .unwrap_or(ProviderKind::OpenRouter);
+
+        let api_key = std::env::var("AGENT_TOOL_LLM_API_KEY")
+            .ok()
+            .or_else(|| kind.api_key_env_var().and_then(|var| std::env::var(var).ok()))
+            .filter(|key| !key.is_empty());
+
+        let mut config = Self::new(kind, api_key);
+        if let Ok(base_url) = std::env::var("AGENT_TOOL_LLM_BASE_URL") {
+            config.base_url = base_url;
+        }
+        if let Ok(headers) = std::env::var("AGENT_TOOL_LLM_HEADERS") {
+            match serde_json::from_str(&headers) {
+                Ok(headers) => config.headers = headers,
+                Err(e) => eprintln!("Warning: Ignoring AGENT_TOOL_LLM_HEADERS: {}", e),
+            }
+        }
+        if let Some(timeout) = std::env::var("AGENT_TOOL_LLM_TIMEOUT_SECS").ok().and_then(|v| v.parse().ok()) {
+            config.timeout_secs = timeout;
+        }
+
+        config
+    }
+}
+
+/// Failure classes of an LLM call, as reported to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LlmErrorKind {
+    NotConfigured,
+    Auth,
+    RateLimited,
+    Server,
+    Timeout,
+    Connection,
+    Request,
+    ContextLength,
+    Malformed,
+    CircuitOpen,
+}
+
+impl std::fmt::Display for LlmErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            LlmErrorKind::NotConfigured => "not configured",
+            LlmErrorKind::Auth => "authentication failed",
+            LlmErrorKind::RateLimited => "rate limited",
+            LlmErrorKind::Server => "provider error",
+            LlmErrorKind::Timeout => "timed out",
+            LlmErrorKind::Connection => "connection failed",
+            LlmErrorKind::Request => "request rejected",
+            LlmErrorKind::ContextLength => "prompt too long for model",
+            LlmErrorKind::Malformed => "malformed response",
+            LlmErrorKind::CircuitOpen => "provider temporarily disabled",
+        };
+        f.write_str(label)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum LlmError {
+    #[error("{0:?} API key not configured")]
+    NotConfigured(ProviderKind),
+    #[error("Authentication failed ({status}): {message}")]
+    Auth { status: u16, message: String },
+    #[error("Rate limited: {message}")]
+    RateLimited { retry_after: Option<Duration>, message: String },
+    #[error("Provider error ({status}): {message}")]
+    Server { status: u16, message: String },
+    #[error("Request timed out: {0}")]
+    Timeout(String),
+    #[error("Connection failed: {0}")]
+    Connection(String),
+    #[error("Request rejected ({status}): {message}")]
+    Request { status: u16, message: String },
+    #[error("Prompt exceeds the model's context window: {0}")]
+    ContextLength(String),
+    #[error("Malformed response: {0}")]
+    Malformed(String),
+    #[error("Provider disabled after repeated failures; retrying in {}s", .retry_in.as_secs())]
+    CircuitOpen { retry_in: Duration },
+}
+
+impl LlmError {
+    pub fn kind(&self) -> LlmErrorKind {
+        match self {
+            LlmError::NotConfigured(_) => LlmErrorKind::NotConfigured,
+            LlmError::Auth { .. } => LlmErrorKind::Auth,
+            LlmError::RateLimited { .. } => LlmErrorKind::RateLimited,
+            LlmError::Server { .. } => LlmErrorKind::Server,
+            LlmError::Timeout(_) => LlmErrorKind::Timeout,
+            LlmError::Connection(_) => LlmErrorKind::Connection,
+            LlmError::Request { .. } => LlmErrorKind::Request,
+            LlmError::ContextLength(_) => LlmErrorKind::ContextLength,
+            LlmError::Malformed(_) => LlmErrorKind::Malformed,
+            LlmError::CircuitOpen { .. } => LlmErrorKind::CircuitOpen,
+        }
+    }
+
+    /// Whether trying the same request again later could succeed.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self.kind(),
+            LlmErrorKind::RateLimited | LlmErrorKind::Server | LlmErrorKind::Timeout | LlmErrorKind::Connection
+        )
+    }
+
+    fn from_reqwest(error: reqwest::Error) -> Self {
+        if error.is_timeout() {
+            LlmError::Timeout(error.to_string())
+        } else if error.is_decode() {
+            LlmError::Malformed(error.to_string())
+        } else {
+            LlmError::Connection(error.to_string())
+        }
+    }
+
+    /// Classify a non-2xx response from its status, headers and body.
+    async fn from_response(response: reqwest::Response) -> Self {
+        let status = response.status().as_u16();
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+        let body = response.text().await.unwrap_or_default();
+        let message = if body.trim().is_empty() {
+            format!("HTTP {}", status)
+        } else {
+            body.trim().chars().take(MAX_ERROR_BODY_CHARS).collect()
+        };
+
+        match status {
+            401 | 403 => LlmError::Auth { status, message },
+            429 => LlmError::RateLimited { retry_after, message },
+            408 => LlmError::Timeout(message),
+            500..=599 => LlmError::Server { status, message },
+            400 | 413 if is_context_length_message(&message) => LlmError::ContextLength(message),
+            _ => LlmError::Request { status, message },
+        }
+    }
+}
+
+/// Providers word context overflows differently, but all mention the context or length.
+fn is_context_length_message(message: &str) -> bool {
+    let message = message.to_lowercase();
+    ["context_length", "context length", "context window", "maximum context", "prompt is too long", "too many tokens"]
+        .iter()
+        .any(|needle| message.contains(needle))
+}
+
+/// A failed LLM call, kept so it can be reported in the session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LlmFailure {
+    pub purpose: String,
+    pub model: String,
+    pub kind: LlmErrorKind,
+    pub message: String,
+}
+
+impl LlmFailure {
+    pub fn new(purpose: &str, model: &str, error: &LlmError) -> Self {
+        Self {
+            purpose: purpose.to_string(),
+            model: model.to_string(),
+            kind: error.kind(),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// One entry in the middle manager's ordered list of models. Entries without a provider use
+/// the default provider configuration; unset parameters use the middle manager's defaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelSpec {
+    pub model: String,
+    #[serde(default)]
+    pub provider: Option<ProviderConfig>,
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    /// Offer the model tools during planning. Turn off for models without function calling.
+    #[serde(default = "default_tools")]
+    pub tools: bool,
+}
+
+fn default_tools() -> bool {
+    true
+}
+
+impl ModelSpec {
+    pub fn new(model: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+            provider: None,
+            max_tokens: None,
+            temperature: None,
+            tools: true,
+        }
+    }
+
+    /// Read the model chain from `AGENT_TOOL_LLM_MODELS`, either a JSON array of specs or a
+    /// comma-separated list of model IDs, falling back to the single `AGENT_TOOL_LLM_MODEL`.
+    pub fn chain_from_env(default_model: &str) -> Vec<ModelSpec> {
+        if let Ok(models) = std::env::var("AGENT_TOOL_LLM_MODELS") {
+            let chain = if models.trim_start().starts_with('[') {
+                serde_json::from_str::<Vec<ModelSpec>>(&models)
+                    .map_err(|e| eprintln!("Warning: Ignoring AGENT_TOOL_LLM_MODELS: {}", e))
+                    .unwrap_or_default()
+            } else {
+                models
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|model| !model.is_empty())
+                    .map(ModelSpec::new)
+                    .collect()
+            };
+            if !chain.is_empty() {
+                return chain;
+            }
+        }
+
+        let model = std::env::var("AGENT_TOOL_LLM_MODEL").unwrap_or_else(|_| default_model.to_string());
+        vec![ModelSpec::new(model)]
+    }
+}
+
+const MAX_ERROR_BODY_CHARS: usize = 500;
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let wait = at.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(wait.to_std().unwrap_or(Duration::ZERO))
+}
+
+/// Circuit breakers shared by every client of the same provider, so that once a provider is
+/// down all the models on it leave it alone. Providers are told apart by base URL.
+#[derive(Default)]
+pub struct CircuitBreakers {
+    breakers: Mutex<HashMap<String, Arc<CircuitBreaker>>>,
+}
+
+impl CircuitBreakers {
+    /// A client for `config` that shares its provider's breaker.
+    pub fn client(&self, config: ProviderConfig) -> LlmClient {
+        let breaker = self
+            .breakers
+            .lock()
+            .unwrap()
+            .entry(config.base_url.clone())
+            .or_insert_with(|| Arc::new(CircuitBreaker::new(config.circuit_breaker.clone())))
+            .clone();
+        LlmClient::with_breaker(config, breaker)
+    }
+}
+
+struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    /// When the single call let through after the cooldown went out, until its outcome is
+    /// recorded. A probe that never reports back stops blocking others after a cooldown.
+    probing_since: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    fn check(&self) -> Result<(), LlmError> {
+        let mut state = self.state.lock().unwrap();
+        let Some(until) = state.open_until else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        if now < until {
+            return Err(LlmError::CircuitOpen { retry_in: until - now });
+        }
+
+        // Half-open: let a single call through to probe the provider; the rest wait for it
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+        if let Some(since) = state.probing_since.filter(|since| now < *since + cooldown) {
+            return Err(LlmError::CircuitOpen {
+                retry_in: since + cooldown - now,
+            });
+        }
+        state.probing_since = Some(now);
+        Ok(())
+    }
+
+    fn record(&self, result: &Result<ChatResponse, LlmError>) {
+        let mut state = self.state.lock().unwrap();
+        match result {
+            Ok(_) => *state = BreakerState::default(),
+            Err(error) if error.is_transient() => {
+                state.consecutive_failures += 1;
+                let probe_failed = state.probing_since.take().is_some();
+                if probe_failed
+                    || (self.config.failure_threshold > 0
+                        && state.consecutive_failures >= self.config.failure_threshold)
+                {
+                    state.open_until = Some(Instant::now() + Duration::from_secs(self.config.cooldown_secs));
+                }
+            }
+            // The provider answered, so it is up even if it refused this call
+            Err(_) if state.probing_since.is_some() => *state = BreakerState::default(),
+            Err(_) => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+    /// Tools the assistant asked to call in this turn.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// Set on a message carrying the result of the tool call with this ID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: "system".to_string(),
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: "user".to_string(),
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: None,
+        }
+    }
+
+    /// An assistant turn that called tools, to be followed by one result per call.
+    pub fn assistant_tool_calls(content: impl Into<String>, tool_calls: Vec<ToolCall>) -> Self {
+        Self {
+            role: "assistant".to_string(),
+            content: content.into(),
+            tool_calls,
+            tool_call_id: None,
+        }
+    }
+
+    pub fn tool_result(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            role: "tool".to_string(),
+            content: content.into(),
+            tool_calls: Vec::new(),
+            tool_call_id: Some(tool_call_id.into()),
+        }
+    }
+}
+
+/// A function the model may call, described by a JSON schema for its arguments.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolSpec {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: serde_json::Value,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    pub max_tokens: u32,
+    pub temperature: f32,
+    /// Tools offered to the model. Only supported by non-streaming requests.
+    pub tools: Vec<ToolSpec>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatResponse {
+    pub content: String,
+    pub model: String,
+    pub usage: Option<TokenUsage>,
+    pub tool_calls: Vec<ToolCall>,
+}
+
+/// Chat-completion client that speaks each supported provider's wire format.
+pub struct LlmClient {
+    config: ProviderConfig,
+    http: reqwest::Client,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl LlmClient {
+    fn with_breaker(config: ProviderConfig, breaker: Arc<CircuitBreaker>) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+
+        Self { config, http, breaker }
+    }
+
+    /// Send a chat completion, retrying transient failures.
+    pub async fn chat(&self, request: &ChatRequest) -> Result<ChatResponse, LlmError> {
+        let mut attempt = 1;
+        loop {
+            self.breaker.check()?;
+            let result = self.chat_once(request).await;
+            if !self.retry_after_failure(&result, attempt, true).await {
+                return result;
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Stream a chat completion, calling `on_delta` with each fragment of generated text as
+    /// it arrives. The complete response is returned once the stream ends.
+    ///
+    /// Transient failures are retried as long as nothing has been streamed yet; once text has
+    /// reached `on_delta` a failure is returned as is.
+    pub async fn chat_stream(
+        &self,
+        request: &ChatRequest,
+        on_delta: &mut (dyn FnMut(&str) + Send),
+    ) -> Result<ChatResponse, LlmError> {
+        let mut attempt = 1;
+        loop {
+            self.breaker.check()?;
+            let mut streamed_any = false;
+            let result = self
+                .chat_stream_once(request, &mut |delta| {
+                    streamed_any = true;
+                    on_delta(delta);
+                })
+                .await;
+            if !self.retry_after_failure(&result, attempt, !streamed_any).await {
+                return result;
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Record the outcome with the circuit breaker and, when the failure is worth retrying,
+    /// wait out the backoff. Returns whether the caller should try again.
+    async fn retry_after_failure(&self, result: &Result<ChatResponse, LlmError>, attempt: u32, can_retry: bool) -> bool {
+        self.breaker.record(result);
+        let error = match result {
+            Err(error) if can_retry && error.is_transient() && attempt < self.config.retry.max_attempts => error,
+            _ => return false,
+        };
+
+        let policy = &self.config.retry;
+        let delay = match error {
+            LlmError::RateLimited { retry_after: Some(retry_after), .. } => {
+                if retry_after.as_secs() > policy.max_retry_after_secs {
+                    return false;
+                }
+                *retry_after
+            }
+            _ => backoff_delay(policy, attempt),
+        };
+
+        eprintln!(
+            "{:?} call failed ({}), retrying in {}ms (attempt {} of {}): {}",
+            self.config.kind,
+            error.kind(),
+            delay.as_millis(),
+            attempt + 1,
+            policy.max_attempts,
+            error
+        );
+        tokio::time::sleep(delay).await;
+        true
+    }
+
+    async fn chat_once(&self, request: &ChatRequest) -> Result<ChatResponse, LlmError> {
+        if self.config.api_key.is_none() && self.config.kind != ProviderKind::Ollama {
+            return Err(LlmError::NotConfigured(self.config.kind));
+        }
+
+        let response = self
+            .request_builder()
+            .json(&self.build_payload(request))
+            .send()
+            .await
+            .map_err(LlmError::from_reqwest)?;
+
+        if !response.status().is_success() {
+            return Err(LlmError::from_response(response).await);
+        }
+
+        let response_text = response.text().await.map_err(LlmError::from_reqwest)?;
+
+        let response_json: serde_json::Value = serde_json::from_str(&response_text)
+            .map_err(|e| LlmError::Malformed(format!("Failed to parse JSON: {}", e)))?;
+
+        self.parse_response(&response_json, &request.model)
+    }
+
+    async fn chat_stream_once(
+        &self,
+        request: &ChatRequest,
+        on_delta: &mut (dyn FnMut(&str) + Send),
+    ) -> Result<ChatResponse, LlmError> {
+        if self.config.api_key.is_none() && self.config.kind != ProviderKind::Ollama {
+            return Err(LlmError::NotConfigured(self.config.kind));
+        }
+
+        let mut payload = self.build_payload(request);
+        payload["stream"] = json!(true);
+        if matches!(self.config.kind, ProviderKind::OpenRouter | ProviderKind::OpenAiCompatible) {
+            payload["stream_options"] = json!({ "include_usage": true });
+        }
+
+        let mut response = self
+            .request_builder()
+            .header("Accept", "text/event-stream")
+            .json(&payload)
+            .send()
+            .await
+            .map_err(LlmError::from_reqwest)?;
+
+        if !response.status().is_success() {
+            return Err(LlmError::from_response(response).await);
+        }
+
+        // Some compatible servers ignore the stream flag and answer with a single JSON body
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        if content_type.starts_with("application/json") {
+            let response_json: serde_json::Value = response.json().await
+                .map_err(|e| LlmError::Malformed(format!("Failed to parse JSON: {}", e)))?;
+            let chat_response = self.parse_response(&response_json, &request.model)?;
+            on_delta(&chat_response.content);
+            return Ok(chat_response);
+        }
+
+        let mut stream = StreamAccumulator::new(self.config.kind, &request.model);
+        let mut buffer: Vec<u8> = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(LlmError::from_reqwest)? {
+            buffer.extend_from_slice(&chunk);
+            while let Some(newline) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=newline).collect();
+                let line = String::from_utf8_lossy(&line);
+                if let Some(delta) = stream.feed_line(line.trim_end())? {
+                    on_delta(&delta);
+                }
+            }
+        }
+        if let Some(delta) = stream.feed_line(String::from_utf8_lossy(&buffer).trim_end())? {
+            on_delta(&delta);
+        }
+
+        stream.finish()
+    }
+
+    fn endpoint(&self) -> String {
+        let base_url = self.config.base_url.trim_end_matches('/');
+        match self.config.kind {
+            ProviderKind::OpenRouter | ProviderKind::OpenAiCompatible => format!("{}/chat/completions", base_url),
+            ProviderKind::Anthropic => format!("{}/messages", base_url),
+            ProviderKind::Ollama => format!("{}/api/chat", base_url),
+        }
+    }
+
+    fn request_builder(&self) -> reqwest::RequestBuilder {
+        let mut builder = self
+            .http
+            .post(self.endpoint())
+            .header("Content-Type", "application/json");
+
+        if let Some(api_key) = &self.config.api_key {
+            builder = match self.config.kind {
+                ProviderKind::Anthropic => builder
+                    .header("x-api-key", api_key)
+                    .header("anthropic-version", "2023-06-01"),
+                _ => builder.header("Authorization", format!("Bearer {}", api_key)),
+            };
+        }
+
+        for (name, value) in &self.config.headers {
+            builder = builder.header(name, value);
+        }
+
+        builder
+    }
+
+    fn build_payload(&self, request: &ChatRequest) -> serde_json::Value {
+        let mut payload = match self.config.kind {
+            ProviderKind::OpenRouter | ProviderKind::OpenAiCompatible => json!({
+                "model": request.model,
+                "messages": self.wire_messages(&request.messages),
+                "max_tokens": request.max_tokens,
+                "temperature": request.temperature
+            }),
+            ProviderKind::Anthropic => {
+                // The Messages API takes the system prompt separately from the turns
+                let system: Vec<&str> = request
+                    .messages
+                    .iter()
+                    .filter(|m| m.role == "system")
+                    .map(|m| m.content.as_str())
+                    .collect();
+                let messages: Vec<ChatMessage> = request.messages.iter().filter(|m| m.role != "system").cloned().collect();
+
+                let mut payload = json!({
+                    "model": request.model,
+                    "messages": self.wire_messages(&messages),
+                    "max_tokens": request.max_tokens,
+                    "temperature": request.temperature
+                });
+                if !system.is_empty() {
+                    payload["system"] = json!(system.join("\n\n"));
+                }
+                payload
+            }
+            ProviderKind::Ollama => json!({
+                "model": request.model,
+                "messages": self.wire_messages(&request.messages),
+                "stream": false,
+                "options": {
+                    "temperature": request.temperature,
+                    "num_predict": request.max_tokens
+                }
+            }),
+        };
+
+        if !request.tools.is_empty() {
+            payload["tools"] = match self.config.kind {
+                ProviderKind::Anthropic => json!(request
+                    .tools
+                    .iter()
+                    .map(|tool| json!({
+                        "name": tool.name,
+                        "description": tool.description,
+                        "input_schema": tool.parameters
+                    }))
+                    .collect::<Vec<_>>()),
+                _ => json!(request
+                    .tools
+                    .iter()
+                    .map(|tool| json!({
+                        "type": "function",
+                        "function": {
+                            "name": tool.name,
+                            "description": tool.description,
+                            "parameters": tool.parameters
+                        }
+                    }))
+                    .collect::<Vec<_>>()),
+            };
+        }
+
+        payload
+    }
+
+    /// Messages in the provider's format. Plain messages are just a role and content; tool
+    /// calls and their results differ between providers.
+    fn wire_messages(&self, messages: &[ChatMessage]) -> Vec<serde_json::Value> {
+        let mut wire: Vec<serde_json::Value> = Vec::new();
+        for message in messages {
+            let value = match (self.config.kind, message.tool_call_id.as_ref()) {
+                (ProviderKind::Anthropic, Some(tool_call_id)) => {
+                    let result = json!({
+                        "type": "tool_result",
+                        "tool_use_id": tool_call_id,
+                        "content": message.content
+                    });
+                    // All results for one assistant turn go back in a single user turn
+                    if let Some(blocks) = wire
+                        .last_mut()
+                        .filter(|last| last["role"] == "user")
+                        .and_then(|last| last["content"].as_array_mut())
+                    {
+                        blocks.push(result);
+                        continue;
+                    }
+                    json!({ "role": "user", "content": [result] })
+                }
+                (ProviderKind::Anthropic, None) if !message.tool_calls.is_empty() => {
+                    let mut blocks = Vec::new();
+                    if !message.content.is_empty() {
+                        blocks.push(json!({ "type": "text", "text": message.content }));
+                    }
+                    blocks.extend(message.tool_calls.iter().map(|call| json!({
+                        "type": "tool_use",
+                        "id": call.id,
+                        "name": call.name,
+                        "input": call.arguments
+                    })));
+                    json!({ "role": "assistant", "content": blocks })
+                }
+                (ProviderKind::Ollama, Some(_)) => json!({ "role": "tool", "content": message.content }),
+                (ProviderKind::Ollama, None) if !message.tool_calls.is_empty() => json!({
+                    "role": "assistant",
+                    "content": message.content,
+                    "tool_calls": message.tool_calls.iter().map(|call| json!({
+                        "function": { "name": call.name, "arguments": call.arguments }
+                    })).collect::<Vec<_>>()
+                }),
+                (_, Some(tool_call_id)) => json!({
+                    "role": "tool",
+                    "tool_call_id": tool_call_id,
+                    "content": message.content
+                }),
+                (_, None) if !message.tool_calls.is_empty() => json!({
+                    "role": "assistant",
+                    "content": message.content,
+                    "tool_calls": message.tool_calls.iter().map(|call| json!({
+                        "id": call.id,
+                        "type": "function",
+                        "function": { "name": call.name, "arguments": call.arguments.to_string() }
+                    })).collect::<Vec<_>>()
+                }),
+                _ => json!({ "role": message.role, "content": message.content }),
+            };
+            wire.push(value);
+        }
+        wire
+    }
+
+    fn parse_response(&self, body: &serde_json::Value, requested_model: &str) -> Result<ChatResponse, LlmError> {
+        let model = body["model"].as_str().unwrap_or(requested_model).to_string();
+
+        let (content, usage) = match self.config.kind {
+            ProviderKind::OpenRouter | ProviderKind::OpenAiCompatible => (
+                body["choices"][0]["message"]["content"].as_str().map(str::to_string),
+                body["usage"].as_object().map(|usage| TokenUsage {
+                    prompt_tokens: usage.get("prompt_tokens").and_then(|v| v.as_u64()).unwrap_or(0),
+                    completion_tokens: usage.get("completion_tokens").and_then(|v| v.as_u64()).unwrap_or(0),
+                    cost_usd: usage.get("cost").and_then(|v| v.as_f64()),
+                }),
+            ),
+            ProviderKind::Anthropic => (
+                body["content"].as_array().map(|blocks| {
+                    blocks
+                        .iter()
+                        .filter_map(|block| block["text"].as_str())
+                        .collect::<Vec<_>>()
+                        .join("")
+                }),
+                body["usage"].as_object().map(|usage| TokenUsage {
+                    prompt_tokens: usage.get("input_tokens").and_then(|v| v.as_u64()).unwrap_or(0),
+                    completion_tokens: usage.get("output_tokens").and_then(|v| v.as_u64()).unwrap_or(0),
+                    cost_usd: None,
+                }),
+            ),
+            ProviderKind::Ollama => (
+                body["message"]["content"].as_str().map(str::to_string),
+                body["prompt_eval_count"].as_u64().map(|prompt_tokens| TokenUsage {
+                    prompt_tokens,
+                    completion_tokens: body["eval_count"].as_u64().unwrap_or(0),
+                    cost_usd: Some(0.0),
+                }),
+            ),
+        };
+
+        let tool_calls = self.parse_tool_calls(body);
+        // A turn that only calls tools may come without any text
+        let content = content.or_else(|| (!tool_calls.is_empty()).then(String::new));
+
+        match content {
+            Some(content) => Ok(ChatResponse { content, model, usage, tool_calls }),
+            None => Err(LlmError::Malformed(format!("Invalid response format from {:?}", self.config.kind))),
+        }
+    }
+
+    fn parse_tool_calls(&self, body: &serde_json::Value) -> Vec<ToolCall> {
+        let calls = match self.config.kind {
+            ProviderKind::OpenRouter | ProviderKind::OpenAiCompatible => &body["choices"][0]["message"]["tool_calls"],
+            ProviderKind::Anthropic => &body["content"],
+            ProviderKind::Ollama => &body["message"]["tool_calls"],
+        };
+
+        calls
+            .as_array()
+            .map(|calls| {
+                calls
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, call)| match self.config.kind {
+                        ProviderKind::Anthropic => (call["type"] == "tool_use").then(|| ToolCall {
+                            id: call["id"].as_str().unwrap_or_default().to_string(),
+                            name: call["name"].as_str().unwrap_or_default().to_string(),
+                            arguments: call["input"].clone(),
+                        }),
+                        _ => {
+                            let function = &call["function"];
+                            // OpenAI-style APIs send the arguments as a JSON string
+                            let arguments = match &function["arguments"] {
+                                serde_json::Value::String(text) => serde_json::from_str(text).unwrap_or(json!({})),
+                                other => other.clone(),
+                            };
+                            Some(ToolCall {
+                                id: call["id"].as_str().map(str::to_string).unwrap_or_else(|| format!("call_{}", index)),
+                                name: function["name"].as_str()?.to_string(),
+                                arguments,
+                            })
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Exponential backoff with jitter: a random delay between half and all of the capped
+/// exponential delay for this attempt.
+fn backoff_delay(policy: &RetryPolicy, attempt: u32) -> Duration {
+    let exponential = policy
+        .base_delay_ms
+        .saturating_mul(1u64 << attempt.saturating_sub(1).min(16))
+        .min(policy.max_delay_ms);
+    let jittered = rand::thread_rng().gen_range(exponential / 2..=exponential);
+    Duration::from_millis(jittered)
+}
+
+/// Reassembles a streamed completion from server-sent events, or from newline-delimited JSON
+/// in Ollama's case.
+struct StreamAccumulator {
+    kind: ProviderKind,
+    content: String,
+    model: String,
+    usage: Option<TokenUsage>,
+}
+
+impl StreamAccumulator {
+    fn new(kind: ProviderKind, model: &str) -> Self {
+        Self {
+            kind,
+            content: String::new(),
+            model: model.to_string(),
+            usage: None,
+        }
+    }
+
+    /// Process one line of the stream, returning any newly generated text.
+    fn feed_line(&mut self, line: &str) -> Result<Option<String>, LlmError> {
+        let data = match self.kind {
+            ProviderKind::Ollama => line,
+            _ => match line.strip_prefix("data:") {
+                Some(data) => data.trim_start(),
+                // Event names, comments and keep-alives carry no payload
+                None => return Ok(None),
+            },
+        };
+        if data.is_empty() || data == "[DONE]" {
+            return Ok(None);
+        }
+
+        let event: serde_json::Value = serde_json::from_str(data)
+            .map_err(|e| LlmError::Malformed(format!("Failed to parse stream event: {}", e)))?;
+        if let Some(error) = event.get("error") {
+            return Err(LlmError::Server {
+                status: 200,
+                message: format!("Stream reported an error: {}", error),
+            });
+        }
+        if let Some(model) = event["model"].as_str().or_else(|| event["message"]["model"].as_str()) {
+            self.model = model.to_string();
+        }
+
+        let delta = match self.kind {
+            ProviderKind::OpenRouter | ProviderKind::OpenAiCompatible => {
+                if let Some(usage) = event["usage"].as_object() {
+                    self.usage = Some(TokenUsage {
+                        prompt_tokens: usage.get("prompt_tokens").and_then(|v| v.as_u64()).unwrap_or(0),
+                        completion_tokens: usage.get("completion_tokens").and_then(|v| v.as_u64()).unwrap_or(0),
+                        cost_usd: usage.get("cost").and_then(|v| v.as_f64()),
+                    });
+                }
+                event["choices"][0]["delta"]["content"].as_str().map(str::to_string)
+            }
+            ProviderKind::Anthropic => match event["type"].as_str() {
+                Some("message_start") => {
+                    let usage = self.usage.get_or_insert_with(TokenUsage::default);
+                    usage.prompt_tokens = event["message"]["usage"]["input_tokens"].as_u64().unwrap_or(0);
+                    None
+                }
+                Some("message_delta") => {
+                    let usage = self.usage.get_or_insert_with(TokenUsage::default);
+                    usage.completion_tokens = event["usage"]["output_tokens"].as_u64().unwrap_or(0);
+                    None
+                }
+                Some("content_block_delta") => event["delta"]["text"].as_str().map(str::to_string),
+                _ => None,
+            },
+            ProviderKind::Ollama => {
+                if event["done"].as_bool() == Some(true) {
+                    self.usage = Some(TokenUsage {
+                        prompt_tokens: event["prompt_eval_count"].as_u64().unwrap_or(0),
+                        completion_tokens: event["eval_count"].as_u64().unwrap_or(0),
+                        cost_usd: Some(0.0),
+                    });
+                }
+                event["message"]["content"].as_str().map(str::to_string)
+            }
+        };
+
+        Ok(delta.filter(|d| !d.is_empty()).inspect(|d| self.content.push_str(d)))
+    }
+
+    fn finish(self) -> Result<ChatResponse, LlmError> {
+        if self.content.is_empty() {
+            return Err(LlmError::Malformed(format!("{:?} stream ended without any content", self.kind)));
+        }
+        Ok(ChatResponse {
+            content: self.content,
+            model: self.model,
+            usage: self.usage,
+            tool_calls: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Serve one canned JSON response on a local port and hand back the raw request received.
+    pub(crate) async fn mock_server(status: u16, body: String) -> (String, tokio::task::JoinHandle<String>) {
+        let (base_url, server) = mock_server_sequence(vec![(status, vec![], body)]).await;
+        (base_url, tokio::spawn(async move { server.await.unwrap().remove(0) }))
+    }
+
+    /// Serve canned responses, with extra headers, to successive connections and hand back
+    /// the raw requests received.
+    pub(crate) async fn mock_server_sequence(
+        responses: Vec<(u16, Vec<(&'static str, String)>, String)>,
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for (status, headers, body) in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 4096];
+                loop {
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some(header_end) = text.find("\r\n\r\n") {
+                        let content_length = text[..header_end]
+                            .lines()
+                            .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                            .unwrap_or(0);
+                        if request.len() >= header_end + 4 + content_length {
+                            break;
+                        }
+                    }
+                    if read == 0 {
+                        break;
+                    }
+                }
+
+                let content_type = if body.starts_with("data:") { "text/event-stream" } else { "application/json" };
+                let extra_headers: String = headers
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}\r\n", name, value))
+                    .collect();
+                let response = format!(
+                    "HTTP/1.1 {} OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+                    status,
+                    content_type,
+                    body.len(),
+                    extra_headers,
+                    body
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8_lossy(&request).to_string());
+            }
+            requests
+        });
+
+        (base_url, handle)
+    }
+
+    fn request() -> ChatRequest {
+        ChatRequest {
+            model: "test-model".to_string(),
+            messages: vec![ChatMessage::user("hello")],
+            max_tokens: 100,
+            temperature: 0.0,
+            tools: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openai_compatible_chat() {
+        let body = json!({
+            "model": "test-model",
+            "choices": [{"message": {"role": "assistant", "content": "hi there"}}],
+            "usage": {"prompt_tokens": 12, "completion_tokens": 3}
+        });
+        let (base_url, server) = mock_server(200, body.to_string()).await;
+
+        let mut config = ProviderConfig::new(ProviderKind::OpenAiCompatible, Some("secret".to_string()));
+        config.base_url = base_url;
+        config.headers.insert("X-Title".to_string(), "AgentTool".to_string());
+
+        let response = CircuitBreakers::default().client(config).chat(&request()).await.unwrap();
+        assert_eq!(response.content, "hi there");
+        assert_eq!(response.usage.unwrap().prompt_tokens, 12);
+
+        let raw_request = server.await.unwrap().to_lowercase();
+        assert!(raw_request.starts_with("post /chat/completions"));
+        assert!(raw_request.contains("authorization: bearer secret"));
+        assert!(raw_request.contains("x-title: agenttool"));
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_chat() {
+        let body = json!({
+            "model": "claude-test",
+            "content": [{"type": "text", "text": "hello "}, {"type": "text", "text": "world"}],
+            "usage": {"input_tokens": 7, "output_tokens": 2}
+        });
+        let (base_url, server) = mock_server(200, body.to_string()).await;
+
+        let mut config = ProviderConfig::new(ProviderKind::Anthropic, Some("secret".to_string()));
+        config.base_url = base_url;
+
+        let response = CircuitBreakers::default().client(config).chat(&request()).await.unwrap();
+        assert_eq!(response.content, "hello world");
+        assert_eq!(response.model, "claude-test");
+        assert_eq!(response.usage.unwrap().completion_tokens, 2);
+
+        let raw_request = server.await.unwrap().to_lowercase();
+        assert!(raw_request.starts_with("post /messages"));
+        assert!(raw_request.contains("x-api-key: secret"));
+    }
+
+    #[tokio::test]
+    async fn test_openai_compatible_stream() {
+        let events = [
+            json!({"model": "test-model", "choices": [{"delta": {"role": "assistant"}}]}),
+            json!({"choices": [{"delta": {"content": "Plan"}}]}),
+            json!({"choices": [{"delta": {"content": "ning done"}}]}),
+            json!({"choices": [], "usage": {"prompt_tokens": 4, "completion_tokens": 2}}),
+        ];
+        let body = events
+            .iter()
+            .map(|event| format!("data: {}\n\n", event))
+            .chain(std::iter::once("data: [DONE]\n\n".to_string()))
+            .collect::<String>();
+        let (base_url, server) = mock_server(200, body).await;
+
+        let mut config = ProviderConfig::new(ProviderKind::OpenAiCompatible, Some("secret".to_string()));
+        config.base_url = base_url;
+
+        let mut deltas = Vec::new();
+        let response = CircuitBreakers::default().client(config)
+            .chat_stream(&request(), &mut |delta| deltas.push(delta.to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(deltas, vec!["Plan", "ning done"]);
+        assert_eq!(response.content, "Planning done");
+        assert_eq!(response.usage.unwrap().completion_tokens, 2);
+        assert!(server.await.unwrap().contains("\"stream\":true"));
+    }
+
+    fn fast_retries(config: &mut ProviderConfig) {
+        config.retry.base_delay_ms = 1;
+        config.retry.max_delay_ms = 5;
+    }
+
+    #[tokio::test]
+    async fn test_transient_failures_are_retried() {
+        let success = json!({
+            "choices": [{"message": {"role": "assistant", "content": "recovered"}}]
+        });
+        let (base_url, server) = mock_server_sequence(vec![
+            (503, vec![], "overloaded".to_string()),
+            (429, vec![("Retry-After", "0".to_string())], "slow down".to_string()),
+            (200, vec![], success.to_string()),
+        ])
+        .await;
+
+        let mut config = ProviderConfig::new(ProviderKind::OpenAiCompatible, Some("secret".to_string()));
+        config.base_url = base_url;
+        fast_retries(&mut config);
+
+        let response = CircuitBreakers::default().client(config).chat(&request()).await.unwrap();
+        assert_eq!(response.content, "recovered");
+        assert_eq!(server.await.unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_half_open_breaker_lets_one_probe_through() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            cooldown_secs: 60,
+        });
+        let failure: Result<ChatResponse, LlmError> = Err(LlmError::Timeout("slow".to_string()));
+        let success: Result<ChatResponse, LlmError> = Ok(ChatResponse {
+            content: String::new(),
+            model: "test-model".to_string(),
+            usage: None,
+            tool_calls: Vec::new(),
+        });
+        let end_cooldown = || breaker.state.lock().unwrap().open_until = Some(Instant::now());
+
+        breaker.record(&failure);
+        assert!(matches!(breaker.check(), Err(LlmError::CircuitOpen { .. })));
+
+        // Once the cooldown is over one caller probes while the others are turned away
+        end_cooldown();
+        assert!(breaker.check().is_ok());
+        assert!(matches!(breaker.check(), Err(LlmError::CircuitOpen { .. })));
+
+        // A failed probe re-opens the circuit; a successful one closes it
+        breaker.record(&failure);
+        assert!(matches!(breaker.check(), Err(LlmError::CircuitOpen { .. })));
+        end_cooldown();
+        assert!(breaker.check().is_ok());
+        breaker.record(&success);
+        assert!(breaker.check().is_ok());
+        assert!(breaker.check().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_classified_and_trip_the_breaker() {
+        let (base_url, server) = mock_server_sequence(vec![
+            (401, vec![], "bad key".to_string()),
+            (500, vec![], "boom".to_string()),
+            (500, vec![], "boom".to_string()),
+        ])
+        .await;
+
+        let mut config = ProviderConfig::new(ProviderKind::OpenAiCompatible, Some("secret".to_string()));
+        config.base_url = base_url;
+        config.retry.max_attempts = 1;
+        config.circuit_breaker.failure_threshold = 2;
+        let client = CircuitBreakers::default().client(config);
+
+        // Authentication failures are not retried
+        let error = client.chat(&request()).await.unwrap_err();
+        assert_eq!(error.kind(), LlmErrorKind::Auth);
+        assert!(error.to_string().contains("bad key"));
+
+        assert_eq!(client.chat(&request()).await.unwrap_err().kind(), LlmErrorKind::Server);
+        assert_eq!(client.chat(&request()).await.unwrap_err().kind(), LlmErrorKind::Server);
+
+        // The breaker is now open, so no further request reaches the server
+        assert_eq!(client.chat(&request()).await.unwrap_err().kind(), LlmErrorKind::CircuitOpen);
+        assert_eq!(server.await.unwrap().len(), 3);
+
+        assert_eq!(parse_retry_after("12"), Some(Duration::from_secs(12)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+    }
+
+    #[tokio::test]
+    async fn test_clients_of_one_provider_share_a_breaker() {
+        let (base_url, server) = mock_server(500, "boom".to_string()).await;
+        let mut config = ProviderConfig::new(ProviderKind::OpenAiCompatible, Some("secret".to_string()));
+        config.base_url = base_url;
+        config.retry.max_attempts = 1;
+        config.circuit_breaker.failure_threshold = 1;
+
+        let breakers = CircuitBreakers::default();
+        let first = breakers.client(config.clone());
+        let second = breakers.client(config);
+        assert_eq!(first.chat(&request()).await.unwrap_err().kind(), LlmErrorKind::Server);
+
+        // The other model on the provider doesn't have to fail on its own first
+        assert_eq!(second.chat(&request()).await.unwrap_err().kind(), LlmErrorKind::CircuitOpen);
+        server.await.unwrap();
+    }
+}
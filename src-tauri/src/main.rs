@@ -5,11 +5,26 @@ mod commands;
 mod models;
 mod agent_registry;
 mod middle_manager;
+mod llm_provider;
 mod database;
 mod claude_code_adapter;
 mod gemini_cli_adapter;
 mod session_manager;
 mod git_worktree_manager;
+mod dag_executor;
+mod verification;
+mod cost_tracker;
+mod prompt_templates;
+mod plan_approval;
+mod repo_map;
+mod context_window;
+mod routing_rules;
+mod plan_preview;
+mod planner_tools;
+mod ensemble;
+mod scheduler;
+mod session_state;
+mod request_runs;
 
 // use tauri::Manager; // Removed unused import
 use commands::*;
@@ -23,6 +38,11 @@ async fn main() {
     // Initialize session manager
     let session_manager = SessionManager::new();
 
+    // Bring back the sessions from before the last restart
+    if let Err(e) = session_manager.restore_sessions().await {
+        eprintln!("Warning: Failed to restore sessions: {}", e);
+    }
+
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_fs::init())
@@ -36,10 +56,37 @@ async fn main() {
             get_agent_status,
             configure_agent,
             list_agents,
+            get_agent_stats,
             send_message,
             get_conversation_history,
+            pin_message,
+            get_conversation_summary,
+            get_ensemble_candidates,
+            get_plan_status,
+            set_plan_approval,
+            set_session_tags,
+            set_session_priority,
+            get_scheduler_queue,
+            get_pending_plan,
+            update_pending_subtask,
+            reassign_pending_subtask,
+            set_pending_subtask_dependencies,
+            approve_plan,
+            reject_plan,
+            get_usage_summary,
+            set_session_budget,
+            set_project_budget,
+            list_prompt_templates,
+            list_routing_rules,
             pause_session,
-            resume_session
+            resume_session,
+            complete_session,
+            archive_session,
+            delete_session,
+            get_session_status_history,
+            get_run_status,
+            get_run_results,
+            list_runs
         ])
         .setup(|app| {
             // Initialize agent registry on startup
@@ -1,27 +1,91 @@
 // use crate::models::*; // Unused - types are defined locally
-use serde_json::json;
+use crate::llm_provider::{
+    ChatMessage, ChatRequest, ChatResponse, CircuitBreakers, LlmClient, LlmError, LlmErrorKind, LlmFailure, ModelSpec,
+    ProviderConfig,
+};
+use crate::models::{LlmCall, PromptTemplateRef, RuleMatch, TaskResult};
+use crate::planner_tools::{RepoTools, ToolCallRecord};
+use crate::prompt_templates;
+use crate::routing_rules;
+use crate::cost_tracker::with_estimated_cost;
+use crate::context_window::truncate_to_tokens;
+use crate::dag_executor::DagOutcome;
+use crate::ensemble::{CandidateReport, ENSEMBLE_AGENTS};
+use crate::verification::AcceptanceCriterion;
+use minijinja::context;
+use std::path::Path;
 // Removed unused imports - these were only used in commented-out methods
 // use std::process::Stdio;
 // use tokio::process::Command;
 // use tokio::io::{AsyncBufReadExt, BufReader};
 
 pub struct MiddleManager {
-    openrouter_api_key: String,
-    default_model: String,
+    /// Models to try in order, each with a client for its provider.
+    models: Vec<PlannerModel>,
+    /// Provider for models named by routing rules that are not in the chain.
+    default_provider: ProviderConfig,
+    /// One circuit breaker per provider, shared by every model on it.
+    breakers: CircuitBreakers,
 }
 
-impl MiddleManager {
-    pub fn new() -> Self {
+struct PlannerModel {
+    spec: ModelSpec,
+    client: LlmClient,
+}
+
+impl PlannerModel {
+    fn new(spec: ModelSpec, default_provider: &ProviderConfig, breakers: &CircuitBreakers) -> Self {
         Self {
-            openrouter_api_key: std::env::var("OPENROUTER_API_KEY").unwrap_or_default(),
-            default_model: "anthropic/claude-3-sonnet".to_string(),
+            client: breakers.client(spec.provider.clone().unwrap_or_else(|| default_provider.clone())),
+            spec,
         }
     }
+}
+
+/// What the planner knows about the request beyond its text.
+#[derive(Default)]
+pub struct PlanningContext<'a> {
+    /// Earlier conversation, already fitted to the context budget.
+    pub conversation: &'a str,
+    /// Outline of the code the plan will touch.
+    pub repo_map: Option<&'a str>,
+    pub project_path: Option<&'a Path>,
+    /// Tags of the session, for routing rules to match on.
+    pub session_tags: &'a [String],
+    /// Read-only tools the planner may call to look at the code before planning.
+    pub tools: Option<&'a RepoTools>,
+}
+
+impl MiddleManager {
+    pub fn new() -> Self {
+        Self::with_models(
+            ProviderConfig::from_env(),
+            ModelSpec::chain_from_env("anthropic/claude-3-sonnet"),
+        )
+    }
+
+    /// The first model in the chain, used when nothing more specific is known.
+    pub fn default_model(&self) -> &str {
+        &self.models[0].spec.model
+    }
+
+    /// Build a middle manager that falls back through `models` in order. Models without
+    /// their own provider configuration use `default_provider`.
+    pub fn with_models(default_provider: ProviderConfig, models: Vec<ModelSpec>) -> Self {
+        let models: Vec<ModelSpec> = if models.is_empty() {
+            vec![ModelSpec::new("anthropic/claude-3-sonnet")]
+        } else {
+            models
+        };
 
-    pub fn new_with_config(openrouter_api_key: String, default_model: String) -> Self {
+        let breakers = CircuitBreakers::default();
         Self {
-            openrouter_api_key,
-            default_model,
+            models: models
+                .into_iter()
+                .map(|spec| PlannerModel::new(spec, &default_provider, &breakers))
+                .collect(),
+            default_provider,
+            breakers,
         }
     }
 
@@ -41,61 +105,484 @@ impl MiddleManager {
             error: None,
             created_at: chrono::Utc::now(),
             completed_at: Some(chrono::Utc::now()),
+            metadata: crate::models::TaskMetadata::default(),
         })
     }
 
     pub async fn process_task(&self, task: &str, context: &str) -> Result<TaskDecomposition, String> {
-        let prompt = format!(
-            r#"You are a Middle Manager Agent responsible for coordinating AI coding assistants.
-
-Your job is to analyze the given task and decide:
-1. Whether to handle it yourself or delegate to subagents
-2. If delegating, break it into smaller tasks
-3. Choose the appropriate agent(s) for each subtask
-
-Available agents:
-- claude_code: Best for code analysis, writing, debugging, complex reasoning
-- gemini_cli: Good for quick tasks, code generation, simple operations
-- middle_manager: For coordination, planning, high-level analysis
-
-Current task: {}
-Context: {}
-
-Respond with JSON in this format:
-{{
-  "strategy": "direct|delegate|hybrid",
-  "reasoning": "explanation of approach",
-  "subtasks": [
-    {{
-      "id": "unique_id",
-      "description": "task description", 
-      "agent": "claude_code|gemini_cli|middle_manager",
-      "priority": "high|medium|low",
-      "dependencies": ["other_task_ids"]
-    }}
-  ]
-}}"#,
-            task, context
+        let context = PlanningContext {
+            conversation: context,
+            ..PlanningContext::default()
+        };
+        self.process_task_streaming(task, &context, &|_| {}).await
+    }
+
+    /// Decompose a task while streaming the model's output. `on_progress` receives the text
+    /// generated so far each time more arrives; the plan itself is only parsed and validated
+    /// once the stream completes.
+    ///
+    /// Routing rules are checked first. A rule that pins an agent settles the plan without an
+    /// LLM call; rules that pin a model or template change how the plan is requested.
+    pub async fn process_task_streaming(
+        &self,
+        task: &str,
+        context: &PlanningContext<'_>,
+        on_progress: &(dyn Fn(&str) + Send + Sync),
+    ) -> Result<TaskDecomposition, String> {
+        let project_path = context.project_path;
+        let rule_matches = routing_rules::evaluate(
+            &routing_rules::load_rules(project_path),
+            task,
+            context.session_tags,
+        );
+        let routing = routing_rules::resolve(&rule_matches);
+        let mut trace = PlanningTrace {
+            rule_matches,
+            ..PlanningTrace::default()
+        };
+
+        if let Some(agent) = routing.agent {
+            let rules: Vec<String> = trace.rule_matches.iter().map(|m| m.rule.clone()).collect();
+            return Ok(trace.attach(TaskDecomposition {
+                strategy: "direct".to_string(),
+                reasoning: format!("Routed to {} by rule {}", agent, rules.join(", ")),
+                fallback: false,
+                ..Self::single_agent_decomposition(task, &agent)
+            }));
+        }
+
+        let variables = context! {
+            task => task,
+            context => context.conversation,
+            repo_map => context.repo_map,
+        };
+        let prompt = match &routing.template {
+            Some(template) => prompt_templates::render_named(template, "plan_decomposition", project_path, variables),
+            None => prompt_templates::render("plan_decomposition", project_path, variables),
+        };
+        trace.prompt_templates.push(prompt.template);
+
+        // A model pinned by a rule goes first, with the rest of the chain behind it
+        let pinned_planner;
+        let mut planners: Vec<&PlannerModel> = self.models.iter().collect();
+        if let Some(model) = &routing.model {
+            match planners.iter().position(|planner| &planner.spec.model == model) {
+                Some(index) => {
+                    let pinned = planners.remove(index);
+                    planners.insert(0, pinned);
+                }
+                None => {
+                    pinned_planner = PlannerModel::new(ModelSpec::new(model), &self.default_provider, &self.breakers);
+                    planners.insert(0, &pinned_planner);
+                }
+            }
+        }
+
+        // Move down the model chain until one produces a valid plan
+        for planner in planners {
+            let response = match context.tools.filter(|_| planner.spec.tools) {
+                Some(tools) => self.plan_with_tools(planner, &prompt.text, tools, on_progress, &mut trace).await,
+                None => self.call_llm_streaming(planner, &prompt.text, on_progress).await,
+            };
+            match response {
+                Ok(response) => {
+                    trace.llm_calls.push(Self::record_call("plan", &response));
+                    let planner_model = response.model.clone();
+                    if let Some(decomposition) = self
+                        .validate_or_repair(planner, &prompt.text, response.content, project_path, &mut trace)
+                        .await
+                    {
+                        return Ok(trace.attach(TaskDecomposition {
+                            planner_model: Some(planner_model),
+                            ..decomposition
+                        }));
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Task planning with {} failed: {}", planner.spec.model, e);
+                    trace.llm_failures.push(LlmFailure::new("plan", &planner.spec.model, &e));
+                }
+            }
+        }
+
+        eprintln!("No model produced a usable task plan, using fallback decomposition");
+        Ok(trace.attach(Self::fallback_decomposition(task)))
+    }
+
+    /// Request a plan, letting the model call `tools` to inspect the repository until it
+    /// answers without calling any. Every call is recorded in `trace`.
+    async fn plan_with_tools(
+        &self,
+        planner: &PlannerModel,
+        prompt: &str,
+        tools: &RepoTools,
+        on_progress: &(dyn Fn(&str) + Send + Sync),
+        trace: &mut PlanningTrace,
+    ) -> Result<ChatResponse, LlmError> {
+        let mut request = ChatRequest {
+            messages: vec![ChatMessage::system(PLANNING_TOOLS_HINT), ChatMessage::user(prompt)],
+            tools: RepoTools::specs(),
+            ..Self::chat_request(planner, prompt)
+        };
+        let mut activity = String::new();
+        let mut round = 0;
+
+        loop {
+            round += 1;
+            let response = planner.client.chat(&request).await?;
+            if response.tool_calls.is_empty() {
+                on_progress(&response.content);
+                return Ok(response);
+            }
+            trace.llm_calls.push(Self::record_call("plan_tools", &response));
+            if round == MAX_TOOL_ROUNDS {
+                return Err(LlmError::Malformed(format!(
+                    "Planner was still calling tools after {} rounds",
+                    MAX_TOOL_ROUNDS
+                )));
+            }
+
+            request.messages.push(ChatMessage::assistant_tool_calls(&response.content, response.tool_calls.clone()));
+            for call in &response.tool_calls {
+                activity.push_str(&format!("Inspecting repository: {} {}\n", call.name, call.arguments));
+                on_progress(&activity);
+
+                let result = tools.run(call).await;
+                trace.tool_calls.push(ToolCallRecord::new(call, &result));
+                let output = match result {
+                    Ok(output) => output,
+                    Err(e) => format!("Error: {}", e),
+                };
+                request.messages.push(ChatMessage::tool_result(&call.id, output));
+            }
+
+            // Results go back in a tool message, which most providers won't accept a user
+            // message right after, so the last-round warning rides along with them
+            if round == MAX_TOOL_ROUNDS - 1 {
+                if let Some(last) = request.messages.last_mut() {
+                    last.content.push_str(LAST_TOOL_ROUND_NOTE);
+                }
+            }
+        }
+    }
+
+    /// Write the final reply to the user from the original request, the plan and what
+    /// happened to each subtask.
+    pub async fn synthesize_response(
+        &self,
+        request: &str,
+        decomposition: &TaskDecomposition,
+        outcome: &DagOutcome,
+        project_path: Option<&Path>,
+        on_progress: &(dyn Fn(&str) + Send + Sync),
+    ) -> Synthesis {
+        let subtask_reports = Self::describe_outcome(decomposition, outcome);
+
+        let prompt = prompt_templates::render(
+            "synthesis",
+            project_path,
+            context! {
+                request => request,
+                strategy => &decomposition.strategy,
+                reasoning => &decomposition.reasoning,
+                subtask_reports => &subtask_reports,
+            },
+        );
+
+        let mut llm_failures = Vec::new();
+        for synthesizer in &self.models {
+            match self.call_llm_streaming(synthesizer, &prompt.text, on_progress).await {
+                Ok(response) => {
+                    return Synthesis {
+                        llm_call: Some(Self::record_call("synthesis", &response)),
+                        reply: response.content,
+                        llm_failures,
+                    };
+                }
+                Err(e) => {
+                    eprintln!("Result synthesis with {} failed: {}", synthesizer.spec.model, e);
+                    llm_failures.push(LlmFailure::new("synthesis", &synthesizer.spec.model, &e));
+                }
+            }
+        }
+
+        Synthesis {
+            reply: format!("Here is what happened with your request:\n{}", subtask_reports),
+            llm_call: None,
+            llm_failures,
+        }
+    }
+
+    /// Fold messages that have dropped out of the planner's context into the rolling summary.
+    pub async fn summarize_conversation(
+        &self,
+        previous_summary: Option<&str>,
+        transcript: &str,
+        max_tokens: usize,
+        project_path: Option<&Path>,
+    ) -> Summarization {
+        let prompt = prompt_templates::render(
+            "conversation_summary",
+            project_path,
+            context! {
+                previous_summary => previous_summary,
+                messages => transcript,
+                max_tokens => max_tokens,
+            },
         );
 
-        match self.call_openrouter_api(&prompt).await {
-            Ok(response) => return self.parse_decomposition_response(&response),
-            Err(_) => {
-                // Fallback to simple decomposition
-                println!("OpenRouter API call failed, using fallback decomposition");
+        let mut llm_failures = Vec::new();
+        for summarizer in &self.models {
+            match self.call_llm(summarizer, &prompt.text).await {
+                Ok(response) => {
+                    return Summarization {
+                        llm_call: Some(Self::record_call("summary", &response)),
+                        summary: Some(truncate_to_tokens(response.content.trim(), max_tokens)),
+                        llm_failures,
+                    };
+                }
+                Err(e) => {
+                    eprintln!("Conversation summary with {} failed: {}", summarizer.spec.model, e);
+                    llm_failures.push(LlmFailure::new("summary", &summarizer.spec.model, &e));
+                }
             }
         }
-        Ok(TaskDecomposition {
+
+        Summarization {
+            summary: None,
+            llm_call: None,
+            llm_failures,
+        }
+    }
+
+    /// Do a subtask the plan gave to the middle manager itself, such as a summary, spec or
+    /// review, working from the outputs of the subtasks it depends on.
+    pub async fn execute_subtask(
+        &self,
+        description: &str,
+        dependencies: &[DependencyOutput],
+        project_path: Option<&Path>,
+    ) -> SubtaskExecution {
+        let prompt = prompt_templates::render(
+            "manager_subtask",
+            project_path,
+            context! { task => description, dependencies => dependencies },
+        );
+
+        let mut llm_failures = Vec::new();
+        for worker in &self.models {
+            match self.call_llm(worker, &prompt.text).await {
+                Ok(response) => {
+                    return SubtaskExecution {
+                        llm_call: Some(Self::record_call("subtask", &response)),
+                        output: Some(response.content),
+                        prompt_template: prompt.template,
+                        llm_failures,
+                    };
+                }
+                Err(e) => {
+                    eprintln!("Middle manager subtask with {} failed: {}", worker.spec.model, e);
+                    llm_failures.push(LlmFailure::new("subtask", &worker.spec.model, &e));
+                }
+            }
+        }
+
+        SubtaskExecution {
+            output: None,
+            llm_call: None,
+            prompt_template: prompt.template,
+            llm_failures,
+        }
+    }
+
+    /// Ask the model which ensemble attempt to keep. Models whose answer names no attempt are
+    /// treated as failed, and the next one in the chain is asked.
+    pub async fn judge_ensemble(
+        &self,
+        description: &str,
+        candidates: &[CandidateReport],
+        project_path: Option<&Path>,
+    ) -> Judgement {
+        let prompt = prompt_templates::render(
+            "ensemble_judgement",
+            project_path,
+            context! { task => description, candidates => candidates },
+        );
+
+        let mut llm_calls = Vec::new();
+        let mut llm_failures = Vec::new();
+        for judge in &self.models {
+            match self.call_llm(judge, &prompt.text).await {
+                Ok(response) => {
+                    llm_calls.push(Self::record_call("ensemble_judgement", &response));
+                    let verdict = parse_json_object(&response.content)
+                        .filter(|verdict| candidates.iter().any(|c| Some(c.agent.as_str()) == verdict["winner"].as_str()));
+                    match verdict {
+                        Some(verdict) => {
+                            return Judgement {
+                                winner: verdict["winner"].as_str().map(str::to_string),
+                                reasoning: verdict["reasoning"].as_str().map(str::to_string),
+                                llm_calls,
+                                llm_failures,
+                            };
+                        }
+                        None => llm_failures.push(LlmFailure {
+                            purpose: "ensemble_judgement".to_string(),
+                            model: judge.spec.model.clone(),
+                            kind: LlmErrorKind::Malformed,
+                            message: "Judgement did not name one of the attempts".to_string(),
+                        }),
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Ensemble judgement with {} failed: {}", judge.spec.model, e);
+                    llm_failures.push(LlmFailure::new("ensemble_judgement", &judge.spec.model, &e));
+                }
+            }
+        }
+
+        Judgement {
+            winner: None,
+            reasoning: None,
+            llm_calls,
+            llm_failures,
+        }
+    }
+
+    fn describe_outcome(decomposition: &TaskDecomposition, outcome: &DagOutcome) -> String {
+        decomposition
+            .subtasks
+            .iter()
+            .map(|subtask| {
+                let status = outcome
+                    .nodes
+                    .iter()
+                    .find(|node| node.subtask_id == subtask.id)
+                    .map(|node| format!("{:?}", node.status))
+                    .unwrap_or_else(|| "NotRun".to_string());
+                let detail = match outcome.results.get(&subtask.id) {
+                    Some(result) => match (&result.result, &result.error) {
+                        (_, Some(error)) => format!("error: {}", truncate(error, MAX_RESULT_CHARS)),
+                        (Some(output), None) => format!("output: {}", truncate(output, MAX_RESULT_CHARS)),
+                        (None, None) => "no output".to_string(),
+                    },
+                    None => "did not run".to_string(),
+                };
+                format!("- [{}] {} ({}): {}", status, subtask.description, subtask.agent, detail)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn fallback_decomposition(task: &str) -> TaskDecomposition {
+        Self::single_agent_decomposition(task, "claude_code")
+    }
+
+    /// Hand the whole task to one agent as is.
+    fn single_agent_decomposition(task: &str, agent: &str) -> TaskDecomposition {
+        TaskDecomposition {
             strategy: "delegate".to_string(),
             reasoning: "Task requires code analysis and implementation".to_string(),
             subtasks: vec![SubTask {
                 id: uuid::Uuid::new_v4().to_string(),
                 description: task.to_string(),
-                agent: "claude_code".to_string(),
+                agent: agent.to_string(),
                 priority: "high".to_string(),
+                category: None,
                 dependencies: vec![],
+                acceptance_criteria: vec![],
+                ensemble: vec![],
             }],
-        })
+            repair_attempts: vec![],
+            llm_calls: vec![],
+            prompt_templates: vec![],
+            llm_failures: vec![],
+            fallback: true,
+            planner_model: None,
+            rule_matches: vec![],
+            tool_calls: vec![],
+        }
+    }
+
+    /// Parse and validate a planning response, asking the model to fix its plan when it is
+    /// malformed or inconsistent. Returns `None` once the repair attempts are exhausted;
+    /// every attempt is recorded in `trace` either way.
+    async fn validate_or_repair(
+        &self,
+        planner: &PlannerModel,
+        prompt: &str,
+        mut response: String,
+        project_path: Option<&Path>,
+        trace: &mut PlanningTrace,
+    ) -> Option<TaskDecomposition> {
+        let mut attempts = 0;
+
+        loop {
+            let errors = match self.parse_decomposition_response(&response) {
+                Ok(decomposition) => match validate_decomposition(&decomposition) {
+                    Ok(()) => {
+                        if attempts > 0 {
+                            if let Some(last) = trace.repair_attempts.last_mut() {
+                                last.succeeded = true;
+                            }
+                        }
+                        return Some(decomposition);
+                    }
+                    Err(errors) => errors,
+                },
+                Err(e) => vec![PlanValidationError::Malformed(e)],
+            };
+
+            let error_messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            if attempts >= MAX_REPAIR_ATTEMPTS {
+                eprintln!(
+                    "Task plan from {} still invalid after {} repair attempt(s): {}",
+                    planner.spec.model,
+                    attempts,
+                    error_messages.join("; ")
+                );
+                trace.llm_failures.push(LlmFailure {
+                    purpose: "plan".to_string(),
+                    model: planner.spec.model.clone(),
+                    kind: LlmErrorKind::Malformed,
+                    message: format!(
+                        "Plan still invalid after {} repair attempt(s): {}",
+                        attempts,
+                        error_messages.join("; ")
+                    ),
+                });
+                return None;
+            }
+
+            attempts += 1;
+            let attempt = attempts as u32;
+            eprintln!("Repairing task plan (attempt {}): {}", attempt, error_messages.join("; "));
+            trace.repair_attempts.push(RepairAttempt {
+                attempt,
+                model: planner.spec.model.clone(),
+                errors: error_messages.clone(),
+                succeeded: false,
+            });
+
+            let repair_prompt = prompt_templates::render(
+                "plan_repair",
+                project_path,
+                context! { prompt => prompt, response => &response, errors => &error_messages },
+            );
+            trace.prompt_templates.push(repair_prompt.template);
+
+            response = match self.call_llm(planner, &repair_prompt.text).await {
+                Ok(response) => {
+                    trace.llm_calls.push(Self::record_call("plan_repair", &response));
+                    response.content
+                }
+                Err(e) => {
+                    eprintln!("Task plan repair attempt {} failed: {}", attempt, e);
+                    trace.llm_failures.push(LlmFailure::new("plan_repair", &planner.spec.model, &e));
+                    return None;
+                }
+            };
+        }
     }
 
     // Commented out unused methods to remove dead code warnings
@@ -183,49 +670,42 @@ Respond with JSON in this format:
     //     })
     // }
 
-    async fn call_openrouter_api(&self, prompt: &str) -> Result<String, String> {
-        if self.openrouter_api_key.is_empty() {
-            return Err("OpenRouter API key not configured".to_string());
+    fn record_call(purpose: &str, response: &ChatResponse) -> LlmCall {
+        LlmCall {
+            purpose: purpose.to_string(),
+            model: response.model.clone(),
+            usage: with_estimated_cost(&response.model, response.usage.clone().unwrap_or_default()),
         }
+    }
 
-        let client = reqwest::Client::new();
-        let payload = json!({
-            "model": self.default_model,
-            "messages": [
-                {
-                    "role": "user",
-                    "content": prompt
-                }
-            ],
-            "max_tokens": 2000,
-            "temperature": 0.7
-        });
-
-        let response = client
-            .post("https://openrouter.ai/api/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.openrouter_api_key))
-            .header("Content-Type", "application/json")
-            .json(&payload)
-            .send()
-            .await
-            .map_err(|e| format!("HTTP request failed: {}", e))?;
-
-        if !response.status().is_success() {
-            return Err(format!("API request failed with status: {}", response.status()));
+    fn chat_request(planner: &PlannerModel, prompt: &str) -> ChatRequest {
+        ChatRequest {
+            model: planner.spec.model.clone(),
+            messages: vec![ChatMessage::user(prompt)],
+            max_tokens: planner.spec.max_tokens.unwrap_or(2000),
+            temperature: planner.spec.temperature.unwrap_or(0.7),
+            tools: vec![],
         }
+    }
 
-        let response_text = response.text().await
-            .map_err(|e| format!("Failed to read response: {}", e))?;
-
-        // Parse the OpenRouter response
-        let response_json: serde_json::Value = serde_json::from_str(&response_text)
-            .map_err(|e| format!("Failed to parse JSON: {}", e))?;
+    async fn call_llm(&self, planner: &PlannerModel, prompt: &str) -> Result<ChatResponse, LlmError> {
+        planner.client.chat(&Self::chat_request(planner, prompt)).await
+    }
 
-        if let Some(content) = response_json["choices"][0]["message"]["content"].as_str() {
-            Ok(content.to_string())
-        } else {
-            Err("Invalid response format from OpenRouter".to_string())
-        }
+    async fn call_llm_streaming(
+        &self,
+        planner: &PlannerModel,
+        prompt: &str,
+        on_progress: &(dyn Fn(&str) + Send + Sync),
+    ) -> Result<ChatResponse, LlmError> {
+        let mut streamed = String::new();
+        planner
+            .client
+            .chat_stream(&Self::chat_request(planner, prompt), &mut |delta| {
+                streamed.push_str(delta);
+                on_progress(&streamed);
+            })
+            .await
     }
 
     fn parse_decomposition_response(&self, response: &str) -> Result<TaskDecomposition, String> {
@@ -235,41 +715,677 @@ Respond with JSON in this format:
         
         if let (Some(start), Some(end)) = (json_start, json_end) {
             let json_str = &response[start..=end];
-            match serde_json::from_str::<TaskDecomposition>(json_str) {
-                Ok(decomposition) => Ok(decomposition),
-                Err(_) => {
-                    // Fallback to simple parsing or default
-                    Ok(TaskDecomposition {
-                        strategy: "delegate".to_string(),
-                        reasoning: "Parsed from AI response".to_string(),
-                        subtasks: vec![SubTask {
-                            id: uuid::Uuid::new_v4().to_string(),
-                            description: response.lines().next().unwrap_or("AI generated task").to_string(),
-                            agent: "claude_code".to_string(),
-                            priority: "high".to_string(),
-                            dependencies: vec![],
-                        }],
-                    })
-                }
-            }
+            serde_json::from_str::<PlanResponse>(json_str)
+                .map(TaskDecomposition::from)
+                .map_err(|e| format!("Response is not a valid task plan: {}", e))
         } else {
             Err("No valid JSON found in response".to_string())
         }
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
+const MAX_REPAIR_ATTEMPTS: usize = 2;
+
+/// Most rounds of tool calls the planner gets before it must produce a plan.
+const MAX_TOOL_ROUNDS: usize = 8;
+
+const PLANNING_TOOLS_HINT: &str = "You can call tools to look at the repository before planning. \
+Inspect whatever you need to plan well, then reply with the plan and no further tool calls.";
+
+const LAST_TOOL_ROUND_NOTE: &str = "\n\nThat was your last tool call. Reply with the plan now.";
+
+/// Longest subtask output quoted back to the model when synthesising a reply or handing it
+/// to a dependent subtask.
+const MAX_RESULT_CHARS: usize = 4000;
+
+/// The outermost `{...}` in a model's reply, parsed as JSON.
+fn parse_json_object(response: &str) -> Option<serde_json::Value> {
+    let start = response.find('{')?;
+    let end = response.rfind('}')?;
+    if end < start {
+        return None;
+    }
+    serde_json::from_str(&response[start..=end]).ok()
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        text.to_string()
+    } else {
+        format!("{}... [truncated]", text.chars().take(max_chars).collect::<String>())
+    }
+}
+
+pub const KNOWN_AGENTS: [&str; 3] = ["claude_code", "gemini_cli", "middle_manager"];
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum PlanValidationError {
+    #[error("{0}")]
+    Malformed(String),
+    #[error("the plan contains no subtasks")]
+    Empty,
+    #[error("subtask '{0}' has an empty id")]
+    EmptyId(String),
+    #[error("subtask id '{0}' is used more than once")]
+    DuplicateId(String),
+    #[error("subtask '{0}' has an empty description")]
+    EmptyDescription(String),
+    #[error("subtask '{subtask_id}' is assigned to unknown agent '{agent}' (expected one of: {})", KNOWN_AGENTS.join(", "))]
+    UnknownAgent { subtask_id: String, agent: String },
+    #[error("subtask '{subtask_id}' depends on '{dependency}', which is not in the plan")]
+    DanglingDependency { subtask_id: String, dependency: String },
+    #[error("subtask '{0}' depends on itself")]
+    SelfDependency(String),
+    #[error("the dependencies between subtasks form a cycle")]
+    DependencyCycle,
+    #[error("subtask '{0}' has an acceptance criterion with an empty command or path")]
+    EmptyCriterion(String),
+    #[error("subtask '{subtask_id}' lists '{agent}' in its ensemble (expected coding agents: {})", ENSEMBLE_AGENTS.join(", "))]
+    InvalidEnsembleAgent { subtask_id: String, agent: String },
+}
+
+/// Check a decomposition for problems that would stop it from executing as planned.
+pub fn validate_decomposition(decomposition: &TaskDecomposition) -> Result<(), Vec<PlanValidationError>> {
+    let mut errors = Vec::new();
+
+    if decomposition.subtasks.is_empty() {
+        errors.push(PlanValidationError::Empty);
+    }
+
+    let mut seen_ids = std::collections::HashSet::new();
+    for subtask in &decomposition.subtasks {
+        if subtask.id.trim().is_empty() {
+            errors.push(PlanValidationError::EmptyId(subtask.description.clone()));
+        } else if !seen_ids.insert(subtask.id.as_str()) {
+            errors.push(PlanValidationError::DuplicateId(subtask.id.clone()));
+        }
+        if subtask.description.trim().is_empty() {
+            errors.push(PlanValidationError::EmptyDescription(subtask.id.clone()));
+        }
+        let empty_criterion = subtask.acceptance_criteria.iter().any(|criterion| match criterion {
+            AcceptanceCriterion::Command { command } => command.trim().is_empty(),
+            AcceptanceCriterion::FileContains { path, .. } | AcceptanceCriterion::FileExists { path } => path.trim().is_empty(),
+        });
+        if empty_criterion {
+            errors.push(PlanValidationError::EmptyCriterion(subtask.id.clone()));
+        }
+        if !KNOWN_AGENTS.contains(&subtask.agent.as_str()) {
+            errors.push(PlanValidationError::UnknownAgent {
+                subtask_id: subtask.id.clone(),
+                agent: subtask.agent.clone(),
+            });
+        }
+        for agent in subtask.ensemble.iter().filter(|agent| !ENSEMBLE_AGENTS.contains(&agent.as_str())) {
+            errors.push(PlanValidationError::InvalidEnsembleAgent {
+                subtask_id: subtask.id.clone(),
+                agent: agent.clone(),
+            });
+        }
+    }
+
+    for subtask in &decomposition.subtasks {
+        for dependency in &subtask.dependencies {
+            if *dependency == subtask.id {
+                errors.push(PlanValidationError::SelfDependency(subtask.id.clone()));
+            } else if !seen_ids.contains(dependency.as_str()) {
+                errors.push(PlanValidationError::DanglingDependency {
+                    subtask_id: subtask.id.clone(),
+                    dependency: dependency.clone(),
+                });
+            }
+        }
+    }
+
+    // Cycles are only meaningful once ids and dependencies are otherwise sound
+    if errors.is_empty() && crate::dag_executor::topological_order(&decomposition.subtasks).is_err() {
+        errors.push(PlanValidationError::DependencyCycle);
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct TaskDecomposition {
     pub strategy: String,
     pub reasoning: String,
     pub subtasks: Vec<SubTask>,
+    #[serde(default)]
+    pub repair_attempts: Vec<RepairAttempt>,
+    /// LLM requests made while producing this plan, for cost accounting.
+    #[serde(default)]
+    pub llm_calls: Vec<LlmCall>,
+    /// Prompt templates used while producing this plan.
+    #[serde(default)]
+    pub prompt_templates: Vec<PromptTemplateRef>,
+    /// LLM calls that failed while producing this plan.
+    #[serde(default)]
+    pub llm_failures: Vec<LlmFailure>,
+    /// Set when no usable plan came back and the task was handed to a single agent as is.
+    #[serde(default)]
+    pub fallback: bool,
+    /// The model that produced this plan, as reported by its provider.
+    #[serde(default)]
+    pub planner_model: Option<String>,
+    /// Routing rules that matched the request.
+    #[serde(default)]
+    pub rule_matches: Vec<RuleMatch>,
+    /// Tools the planner called to look at the repository.
+    #[serde(default)]
+    pub tool_calls: Vec<ToolCallRecord>,
+}
+
+/// A plan as the model writes it. Everything else on a `TaskDecomposition` is ours to fill
+/// in, so the model can't set it.
+#[derive(serde::Deserialize)]
+struct PlanResponse {
+    strategy: String,
+    reasoning: String,
+    subtasks: Vec<SubTask>,
+}
+
+impl From<PlanResponse> for TaskDecomposition {
+    fn from(plan: PlanResponse) -> Self {
+        Self {
+            strategy: plan.strategy,
+            reasoning: plan.reasoning,
+            subtasks: plan.subtasks,
+            repair_attempts: vec![],
+            llm_calls: vec![],
+            prompt_templates: vec![],
+            llm_failures: vec![],
+            fallback: false,
+            planner_model: None,
+            rule_matches: vec![],
+            tool_calls: vec![],
+        }
+    }
+}
+
+/// The reply written for the user, with the LLM calls made and failed along the way.
+pub struct Synthesis {
+    pub reply: String,
+    pub llm_call: Option<LlmCall>,
+    pub llm_failures: Vec<LlmFailure>,
+}
+
+/// An updated conversation summary, or `None` when every model failed.
+pub struct Summarization {
+    pub summary: Option<String>,
+    pub llm_call: Option<LlmCall>,
+    pub llm_failures: Vec<LlmFailure>,
+}
+
+/// The result of a subtask the middle manager did itself, or `None` when every model failed.
+pub struct SubtaskExecution {
+    pub output: Option<String>,
+    pub llm_call: Option<LlmCall>,
+    pub prompt_template: PromptTemplateRef,
+    pub llm_failures: Vec<LlmFailure>,
+}
+
+/// Which ensemble attempt the model preferred and why, or `None` when no model said.
+pub struct Judgement {
+    pub winner: Option<String>,
+    pub reasoning: Option<String>,
+    pub llm_calls: Vec<LlmCall>,
+    pub llm_failures: Vec<LlmFailure>,
+}
+
+/// What a finished dependency produced, as handed to a middle manager subtask.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DependencyOutput {
+    pub description: String,
+    pub agent: String,
+    pub status: String,
+    pub output: String,
+}
+
+impl From<&TaskResult> for DependencyOutput {
+    fn from(result: &TaskResult) -> Self {
+        let output = match (&result.result, &result.error) {
+            (_, Some(error)) => format!("Error: {}", error),
+            (Some(output), None) => output.clone(),
+            (None, None) => "(no output)".to_string(),
+        };
+        Self {
+            description: result.task_description.clone(),
+            agent: result.agent_type.clone(),
+            status: format!("{:?}", result.status),
+            output: truncate(&output, MAX_RESULT_CHARS),
+        }
+    }
+}
+
+/// What happened on the way to a plan, attached to whichever plan is finally used.
+#[derive(Default)]
+struct PlanningTrace {
+    repair_attempts: Vec<RepairAttempt>,
+    llm_calls: Vec<LlmCall>,
+    prompt_templates: Vec<PromptTemplateRef>,
+    llm_failures: Vec<LlmFailure>,
+    rule_matches: Vec<RuleMatch>,
+    tool_calls: Vec<ToolCallRecord>,
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
+impl PlanningTrace {
+    fn attach(self, decomposition: TaskDecomposition) -> TaskDecomposition {
+        TaskDecomposition {
+            repair_attempts: self.repair_attempts,
+            llm_calls: self.llm_calls,
+            prompt_templates: self.prompt_templates,
+            llm_failures: self.llm_failures,
+            rule_matches: self.rule_matches,
+            tool_calls: self.tool_calls,
+            ..decomposition
+        }
+    }
+}
+
+/// One round of asking the model to fix a plan that failed validation.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RepairAttempt {
+    pub attempt: u32,
+    /// The planner model asked to repair its plan.
+    #[serde(default)]
+    pub model: String,
+    pub errors: Vec<String>,
+    pub succeeded: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct SubTask {
     pub id: String,
     pub description: String,
     pub agent: String,
     pub priority: String,
+    #[serde(default)]
+    pub category: Option<String>,
     pub dependencies: Vec<String>,
+    #[serde(default)]
+    pub acceptance_criteria: Vec<AcceptanceCriterion>,
+    /// Agents to run this subtask on side by side, each in its own worktree, keeping the best
+    /// result. Fewer than two runs it on `agent` alone.
+    #[serde(default)]
+    pub ensemble: Vec<String>,
+}
+
+impl SubTask {
+    /// Category reported by the planner, or a keyword-based guess when it gave none.
+    pub fn category(&self) -> String {
+        self.category.clone().unwrap_or_else(|| categorize_task(&self.description))
+    }
+}
+
+pub fn categorize_task(description: &str) -> String {
+    let description = description.to_lowercase();
+    let category = if description.contains("test") {
+        "testing"
+    } else if ["fix", "bug", "debug", "error"].iter().any(|k| description.contains(k)) {
+        "bugfix"
+    } else if ["refactor", "clean up", "rename"].iter().any(|k| description.contains(k)) {
+        "refactor"
+    } else if ["document", "readme", "docs", "comment"].iter().any(|k| description.contains(k)) {
+        "documentation"
+    } else if ["review", "audit", "analyze", "analyse"].iter().any(|k| description.contains(k)) {
+        "review"
+    } else if ["implement", "add", "create", "build", "write"].iter().any(|k| description.contains(k)) {
+        "feature"
+    } else {
+        "general"
+    };
+    category.to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subtask(id: &str, agent: &str, dependencies: &[&str]) -> SubTask {
+        SubTask {
+            id: id.to_string(),
+            description: format!("task {}", id),
+            agent: agent.to_string(),
+            priority: "medium".to_string(),
+            category: None,
+            dependencies: dependencies.iter().map(|d| d.to_string()).collect(),
+            acceptance_criteria: vec![],
+            ensemble: vec![],
+        }
+    }
+
+    fn plan(subtasks: Vec<SubTask>) -> TaskDecomposition {
+        TaskDecomposition {
+            strategy: "delegate".to_string(),
+            reasoning: "test".to_string(),
+            subtasks,
+            repair_attempts: vec![],
+            llm_calls: vec![],
+            prompt_templates: vec![],
+            llm_failures: vec![],
+            fallback: false,
+            planner_model: None,
+            rule_matches: vec![],
+            tool_calls: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_process_task_against_mock_provider() {
+        let plan_json = serde_json::json!({
+            "strategy": "delegate",
+            "reasoning": "two steps",
+            "subtasks": [
+                {"id": "1", "description": "write code", "agent": "claude_code", "priority": "high", "dependencies": []},
+                {"id": "2", "description": "review code", "agent": "gemini_cli", "priority": "low", "dependencies": ["1"]}
+            ]
+        });
+        let body = serde_json::json!({
+            "choices": [{"message": {"role": "assistant", "content": format!("Here is the plan:\n{}", plan_json)}}]
+        });
+        let (base_url, _server) = crate::llm_provider::tests::mock_server(200, body.to_string()).await;
+        let (retired_url, _retired) =
+            crate::llm_provider::tests::mock_server(404, r#"{"error": "model not found"}"#.to_string()).await;
+
+        let mut provider = ProviderConfig::new(crate::llm_provider::ProviderKind::OpenAiCompatible, Some("key".to_string()));
+        provider.base_url = base_url;
+        let mut retired_provider = provider.clone();
+        retired_provider.base_url = retired_url;
+
+        // The first model in the chain has been withdrawn, so planning moves on to the next
+        let manager = MiddleManager::with_models(
+            provider,
+            vec![
+                ModelSpec {
+                    provider: Some(retired_provider),
+                    ..ModelSpec::new("retired-model")
+                },
+                ModelSpec::new("test-model"),
+            ],
+        );
+
+        let decomposition = manager.process_task("build a feature", "").await.unwrap();
+        assert_eq!(decomposition.planner_model.as_deref(), Some("test-model"));
+        assert_eq!(decomposition.llm_failures.len(), 1);
+        assert_eq!(decomposition.llm_failures[0].model, "retired-model");
+        assert_eq!(decomposition.llm_failures[0].kind, LlmErrorKind::Request);
+        assert!(!decomposition.fallback);
+        assert_eq!(decomposition.prompt_templates[0].name, "plan_decomposition");
+        assert_eq!(decomposition.reasoning, "two steps");
+        assert_eq!(decomposition.subtasks.len(), 2);
+        assert!(decomposition.repair_attempts.is_empty());
+        assert_eq!(decomposition.llm_calls.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pinned_model_falls_back_to_the_rest_of_the_chain() {
+        let plan_json = serde_json::json!({
+            "strategy": "delegate",
+            "reasoning": "one step",
+            "subtasks": [
+                {"id": "1", "description": "write code", "agent": "claude_code", "priority": "high", "dependencies": []}
+            ]
+        });
+        let body = serde_json::json!({
+            "choices": [{"message": {"role": "assistant", "content": plan_json.to_string()}}]
+        });
+        let (base_url, _server) = crate::llm_provider::tests::mock_server(200, body.to_string()).await;
+        let (retired_url, _retired) =
+            crate::llm_provider::tests::mock_server(404, r#"{"error": "model not found"}"#.to_string()).await;
+
+        let mut provider = ProviderConfig::new(crate::llm_provider::ProviderKind::OpenAiCompatible, Some("key".to_string()));
+        provider.base_url = base_url;
+        let mut retired_provider = provider.clone();
+        retired_provider.base_url = retired_url;
+        let manager = MiddleManager::with_models(
+            provider,
+            vec![
+                ModelSpec::new("test-model"),
+                ModelSpec {
+                    provider: Some(retired_provider),
+                    ..ModelSpec::new("retired-model")
+                },
+            ],
+        );
+
+        let project = tempfile::TempDir::new().unwrap();
+        let rules_path = routing_rules::project_rules_path(project.path());
+        std::fs::create_dir_all(rules_path.parent().unwrap()).unwrap();
+        std::fs::write(
+            &rules_path,
+            r#"[{"name": "pin", "message_pattern": "feature", "model": "retired-model"}]"#,
+        )
+        .unwrap();
+        let context = PlanningContext {
+            project_path: Some(project.path()),
+            ..PlanningContext::default()
+        };
+
+        // The pinned model is tried first; when it fails the configured chain still plans
+        let decomposition = manager.process_task_streaming("build a feature", &context, &|_| {}).await.unwrap();
+        assert_eq!(decomposition.llm_failures.len(), 1);
+        assert_eq!(decomposition.llm_failures[0].model, "retired-model");
+        assert_eq!(decomposition.planner_model.as_deref(), Some("test-model"));
+        assert!(!decomposition.fallback);
+    }
+
+    #[tokio::test]
+    async fn test_failed_repairs_are_kept_on_fallback_plan() {
+        let body = serde_json::json!({
+            "choices": [{"message": {"role": "assistant", "content": "I would rather not plan this"}}]
+        })
+        .to_string();
+        let (base_url, _server) = crate::llm_provider::tests::mock_server_sequence(vec![
+            (200, vec![], body.clone()),
+            (200, vec![], body.clone()),
+            (200, vec![], body),
+        ])
+        .await;
+
+        let mut provider = ProviderConfig::new(crate::llm_provider::ProviderKind::OpenAiCompatible, Some("key".to_string()));
+        provider.base_url = base_url;
+        let manager = MiddleManager::with_models(provider, vec![ModelSpec::new("test-model")]);
+
+        let decomposition = manager.process_task("build a feature", "").await.unwrap();
+        assert!(decomposition.fallback);
+        assert_eq!(decomposition.repair_attempts.len(), MAX_REPAIR_ATTEMPTS);
+        assert!(decomposition.repair_attempts.iter().all(|attempt| !attempt.succeeded));
+        assert_eq!(decomposition.repair_attempts[0].model, "test-model");
+        assert!(!decomposition.repair_attempts[0].errors.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_planner_inspects_repository_with_tools() {
+        let repo = tempfile::TempDir::new().unwrap();
+        std::fs::write(repo.path().join("lib.rs"), "pub fn parse_config() {}\n").unwrap();
+        let tools = RepoTools::new(
+            repo.path(),
+            &crate::models::AgentPermissions {
+                file_read: true,
+                file_write: false,
+                network_access: false,
+                process_spawn: false,
+                allowed_paths: vec!["**".to_string()],
+            },
+        )
+        .unwrap();
+
+        let tool_round = serde_json::json!({
+            "choices": [{"message": {"role": "assistant", "content": null, "tool_calls": [
+                {"id": "call_1", "type": "function", "function": {"name": "read_file", "arguments": "{\"path\": \"lib.rs\"}"}}
+            ]}}]
+        });
+        let plan_json = serde_json::json!({
+            "strategy": "delegate",
+            "reasoning": "parse_config lives in lib.rs",
+            "subtasks": [
+                {"id": "1", "description": "extend parse_config in lib.rs", "agent": "claude_code", "priority": "high", "dependencies": []}
+            ]
+        });
+        let plan_round = serde_json::json!({
+            "choices": [{"message": {"role": "assistant", "content": plan_json.to_string()}}]
+        });
+        let (base_url, server) = crate::llm_provider::tests::mock_server_sequence(vec![
+            (200, vec![], tool_round.to_string()),
+            (200, vec![], plan_round.to_string()),
+        ])
+        .await;
+
+        let mut provider = ProviderConfig::new(crate::llm_provider::ProviderKind::OpenAiCompatible, Some("key".to_string()));
+        provider.base_url = base_url;
+        let manager = MiddleManager::with_models(provider, vec![ModelSpec::new("test-model")]);
+
+        let context = PlanningContext {
+            tools: Some(&tools),
+            ..PlanningContext::default()
+        };
+        let decomposition = manager.process_task_streaming("extend the config parser", &context, &|_| {}).await.unwrap();
+        assert!(!decomposition.fallback);
+        assert_eq!(decomposition.reasoning, "parse_config lives in lib.rs");
+        assert_eq!(decomposition.tool_calls.len(), 1);
+        assert_eq!(decomposition.tool_calls[0].name, "read_file");
+        assert_eq!(decomposition.tool_calls[0].output, "pub fn parse_config() {}\n");
+        let purposes: Vec<&str> = decomposition.llm_calls.iter().map(|call| call.purpose.as_str()).collect();
+        assert_eq!(purposes, vec!["plan_tools", "plan"]);
+
+        // The file contents went back to the model as the result of its call
+        let requests = server.await.unwrap();
+        assert!(requests[0].contains("\"tools\""));
+        assert!(requests[1].contains("\"tool_call_id\":\"call_1\""));
+        assert!(requests[1].contains("pub fn parse_config()"));
+    }
+
+    #[tokio::test]
+    async fn test_planner_tool_rounds_are_all_recorded_at_the_limit() {
+        let repo = tempfile::TempDir::new().unwrap();
+        std::fs::write(repo.path().join("lib.rs"), "pub fn parse_config() {}\n").unwrap();
+        let tools = RepoTools::new(
+            repo.path(),
+            &crate::models::AgentPermissions {
+                file_read: true,
+                file_write: false,
+                network_access: false,
+                process_spawn: false,
+                allowed_paths: vec!["**".to_string()],
+            },
+        )
+        .unwrap();
+
+        let tool_round = serde_json::json!({
+            "choices": [{"message": {"role": "assistant", "content": null, "tool_calls": [
+                {"id": "call_1", "type": "function", "function": {"name": "read_file", "arguments": "{\"path\": \"lib.rs\"}"}}
+            ]}}]
+        });
+        let (base_url, _server) = crate::llm_provider::tests::mock_server_sequence(
+            (0..MAX_TOOL_ROUNDS).map(|_| (200, vec![], tool_round.to_string())).collect(),
+        )
+        .await;
+
+        let mut provider = ProviderConfig::new(crate::llm_provider::ProviderKind::OpenAiCompatible, Some("key".to_string()));
+        provider.base_url = base_url;
+        let manager = MiddleManager::with_models(provider, vec![ModelSpec::new("test-model")]);
+
+        let context = PlanningContext {
+            tools: Some(&tools),
+            ..PlanningContext::default()
+        };
+        let decomposition = manager.process_task_streaming("extend the config parser", &context, &|_| {}).await.unwrap();
+        assert!(decomposition.fallback);
+        assert_eq!(decomposition.llm_calls.len(), MAX_TOOL_ROUNDS);
+        assert!(decomposition.llm_calls.iter().all(|call| call.purpose == "plan_tools"));
+    }
+
+    #[tokio::test]
+    async fn test_execute_subtask_works_from_dependency_outputs() {
+        let body = serde_json::json!({
+            "choices": [{"message": {"role": "assistant", "content": "The parser now rejects empty keys."}}],
+            "usage": {"prompt_tokens": 120, "completion_tokens": 8}
+        });
+        let (base_url, server) = crate::llm_provider::tests::mock_server(200, body.to_string()).await;
+        let mut provider = ProviderConfig::new(crate::llm_provider::ProviderKind::OpenAiCompatible, Some("key".to_string()));
+        provider.base_url = base_url;
+        let manager = MiddleManager::with_models(provider, vec![ModelSpec::new("test-model")]);
+
+        let dependency = TaskResult {
+            id: "task-1".to_string(),
+            session_id: "session".to_string(),
+            task_description: "reject empty keys in the parser".to_string(),
+            agent_type: "claude_code".to_string(),
+            status: crate::models::TaskStatus::Completed,
+            result: Some("Added a check for empty keys in parse_line".to_string()),
+            error: None,
+            created_at: chrono::Utc::now(),
+            completed_at: Some(chrono::Utc::now()),
+            metadata: crate::models::TaskMetadata::default(),
+        };
+        let execution = manager
+            .execute_subtask("summarise the parser change", &[DependencyOutput::from(&dependency)], None)
+            .await;
+
+        assert_eq!(execution.output.as_deref(), Some("The parser now rejects empty keys."));
+        assert_eq!(execution.prompt_template.name, "manager_subtask");
+        let call = execution.llm_call.unwrap();
+        assert_eq!(call.purpose, "subtask");
+        assert_eq!(call.usage.prompt_tokens, 120);
+
+        let request = server.await.unwrap();
+        assert!(request.contains("summarise the parser change"));
+        assert!(request.contains("Added a check for empty keys in parse_line"));
+    }
+
+    #[test]
+    fn test_plan_response_cannot_set_bookkeeping_fields() {
+        let manager = MiddleManager::with_models(
+            ProviderConfig::new(crate::llm_provider::ProviderKind::OpenAiCompatible, None),
+            vec![ModelSpec::new("test-model")],
+        );
+        let response = serde_json::json!({
+            "strategy": "delegate",
+            "reasoning": "one step",
+            "subtasks": [
+                {"id": "1", "description": "do it", "agent": "claude_code", "priority": "high", "dependencies": []}
+            ],
+            "fallback": true,
+            "planner_model": "made-up-model",
+            "repair_attempts": [{"attempt": 1, "errors": [], "succeeded": true}]
+        });
+
+        let decomposition = manager.parse_decomposition_response(&response.to_string()).unwrap();
+        assert!(!decomposition.fallback);
+        assert!(decomposition.planner_model.is_none());
+        assert!(decomposition.repair_attempts.is_empty());
+        assert_eq!(decomposition.subtasks.len(), 1);
+    }
+
+    #[test]
+    fn test_validate_decomposition() {
+        let valid = plan(vec![subtask("a", "claude_code", &[]), subtask("b", "gemini_cli", &["a"])]);
+        assert!(validate_decomposition(&valid).is_ok());
+
+        let mut empty_description = subtask("c", "claude_code", &[]);
+        empty_description.description = " ".to_string();
+        let invalid = plan(vec![
+            subtask("a", "copilot", &[]),
+            subtask("a", "claude_code", &["missing"]),
+            empty_description,
+        ]);
+        let errors = validate_decomposition(&invalid).unwrap_err();
+        assert!(errors.contains(&PlanValidationError::UnknownAgent {
+            subtask_id: "a".to_string(),
+            agent: "copilot".to_string(),
+        }));
+        assert!(errors.contains(&PlanValidationError::DuplicateId("a".to_string())));
+        assert!(errors.contains(&PlanValidationError::DanglingDependency {
+            subtask_id: "a".to_string(),
+            dependency: "missing".to_string(),
+        }));
+        assert!(errors.contains(&PlanValidationError::EmptyDescription("c".to_string())));
+
+        let cyclic = plan(vec![subtask("a", "claude_code", &["b"]), subtask("b", "claude_code", &["a"])]);
+        assert_eq!(validate_decomposition(&cyclic).unwrap_err(), vec![PlanValidationError::DependencyCycle]);
+    }
 }
@@ -12,15 +12,37 @@ pub struct Session {
     pub updated_at: DateTime<Utc>,
     pub worktree_path: Option<String>,
     pub branch_name: Option<String>,
+    /// Hold each plan for the user's approval before any subtask runs.
+    #[serde(default)]
+    pub require_plan_approval: bool,
+    /// Labels that routing rules can match on.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// How this session's subtasks rank against other sessions' when agents are busy.
+    #[serde(default)]
+    pub priority: SessionPriority,
+    /// Why the session's worktree can't be used, when it went missing or changed branch.
+    #[serde(default)]
+    pub needs_repair: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub enum SessionPriority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum SessionStatus {
     Created,
     Active,
     Paused,
     Completed,
     Failed,
+    /// Set aside: no longer loaded, but its history is kept.
+    Archived,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -34,6 +56,92 @@ pub struct TaskResult {
     pub error: Option<String>,
     pub created_at: DateTime<Utc>,
     pub completed_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub metadata: TaskMetadata,
+}
+
+/// Bookkeeping recorded alongside a task so outcomes can be analysed later.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TaskMetadata {
+    pub model: Option<String>,
+    pub category: Option<String>,
+    pub retries: u32,
+    pub usage: Option<TokenUsage>,
+    #[serde(default)]
+    pub prompt_templates: Vec<PromptTemplateRef>,
+    /// The model that planned the request this task belongs to.
+    #[serde(default)]
+    pub planner_model: Option<String>,
+    /// Routing rules that shaped the plan this task belongs to.
+    #[serde(default)]
+    pub rule_matches: Vec<RuleMatch>,
+}
+
+/// A routing rule that matched a request, why it matched and what it pinned.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RuleMatch {
+    pub rule: String,
+    /// The rules file the rule was loaded from.
+    pub source: String,
+    pub reasons: Vec<String>,
+    pub agent: Option<String>,
+    pub model: Option<String>,
+    pub template: Option<String>,
+}
+
+/// Which prompt template, and which revision of it, produced a prompt.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PromptTemplateRef {
+    pub name: String,
+    pub version: String,
+    pub origin: TemplateOrigin,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum TemplateOrigin {
+    Builtin,
+    Global,
+    Project,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost_usd: Option<f64>,
+}
+
+/// One request made to the middle manager's LLM, kept for cost accounting.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LlmCall {
+    pub purpose: String,
+    pub model: String,
+    pub usage: TokenUsage,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Budget {
+    pub limit_usd: f64,
+    /// Fraction of the limit at which to start warning, e.g. 0.8.
+    pub warn_threshold: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BudgetStatus {
+    Ok,
+    Warning,
+    Exceeded,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UsageSummary {
+    pub session_id: String,
+    pub project_path: String,
+    pub session_usage: TokenUsage,
+    pub project_usage: TokenUsage,
+    pub session_budget: Option<Budget>,
+    pub project_budget: Option<Budget>,
+    pub status: BudgetStatus,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -55,6 +163,29 @@ pub struct AgentStatus {
     pub last_activity: DateTime<Utc>,
 }
 
+/// Aggregated outcomes for a group of stored tasks.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct OutcomeStats {
+    pub total_tasks: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+    pub success_rate: f64,
+    pub mean_duration_ms: Option<f64>,
+    pub p95_duration_ms: Option<f64>,
+    pub total_retries: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost_usd: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AgentStats {
+    pub agent_type: String,
+    pub overall: OutcomeStats,
+    pub by_model: std::collections::HashMap<String, OutcomeStats>,
+    pub by_category: std::collections::HashMap<String, OutcomeStats>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AgentConfig {
     pub id: String,
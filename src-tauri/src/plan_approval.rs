@@ -0,0 +1,149 @@
+use crate::middle_manager::{validate_decomposition, TaskDecomposition};
+use crate::verification::AcceptanceCriterion;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A plan held back from execution until the user approves it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingPlan {
+    pub session_id: String,
+    /// The user message the plan answers.
+    pub request: String,
+    pub decomposition: TaskDecomposition,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Changes to a subtask's content. Fields left unset keep their current value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SubtaskEdit {
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub priority: Option<String>,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub acceptance_criteria: Option<Vec<AcceptanceCriterion>>,
+}
+
+/// One change the user makes to a pending plan.
+#[derive(Debug, Clone)]
+pub enum PlanEdit {
+    Update { subtask_id: String, edit: SubtaskEdit },
+    Reassign { subtask_id: String, agent: String },
+    SetDependencies { subtask_id: String, dependencies: Vec<String> },
+}
+
+impl PendingPlan {
+    /// Apply an edit, keeping the plan unchanged if the result would not pass validation.
+    pub fn apply(&mut self, edit: PlanEdit) -> Result<(), String> {
+        let mut decomposition = self.decomposition.clone();
+
+        let subtask_id = match &edit {
+            PlanEdit::Update { subtask_id, .. }
+            | PlanEdit::Reassign { subtask_id, .. }
+            | PlanEdit::SetDependencies { subtask_id, .. } => subtask_id.clone(),
+        };
+        let subtask = decomposition
+            .subtasks
+            .iter_mut()
+            .find(|subtask| subtask.id == subtask_id)
+            .ok_or_else(|| format!("Subtask not found in pending plan: {}", subtask_id))?;
+
+        match edit {
+            PlanEdit::Update { edit, .. } => {
+                if let Some(description) = edit.description {
+                    subtask.description = description;
+                }
+                if let Some(priority) = edit.priority {
+                    subtask.priority = priority;
+                }
+                if let Some(category) = edit.category {
+                    subtask.category = Some(category);
+                }
+                if let Some(acceptance_criteria) = edit.acceptance_criteria {
+                    subtask.acceptance_criteria = acceptance_criteria;
+                }
+            }
+            PlanEdit::Reassign { agent, .. } => subtask.agent = agent,
+            PlanEdit::SetDependencies { dependencies, .. } => subtask.dependencies = dependencies,
+        }
+
+        validate_decomposition(&decomposition).map_err(|errors| {
+            let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            format!("Edit rejected: {}", errors.join("; "))
+        })?;
+
+        self.decomposition = decomposition;
+        self.updated_at = Utc::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending_plan() -> PendingPlan {
+        let decomposition: TaskDecomposition = serde_json::from_value(serde_json::json!({
+            "strategy": "delegate",
+            "reasoning": "two steps",
+            "subtasks": [
+                {"id": "a", "description": "write code", "agent": "claude_code", "priority": "high", "dependencies": []},
+                {"id": "b", "description": "review code", "agent": "gemini_cli", "priority": "low", "dependencies": ["a"]}
+            ]
+        }))
+        .unwrap();
+
+        PendingPlan {
+            session_id: "session".to_string(),
+            request: "build a feature".to_string(),
+            decomposition,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_edits_are_validated() {
+        let mut plan = pending_plan();
+
+        plan.apply(PlanEdit::Reassign {
+            subtask_id: "b".to_string(),
+            agent: "claude_code".to_string(),
+        })
+        .unwrap();
+        plan.apply(PlanEdit::Update {
+            subtask_id: "a".to_string(),
+            edit: SubtaskEdit {
+                description: Some("write the parser".to_string()),
+                ..SubtaskEdit::default()
+            },
+        })
+        .unwrap();
+        assert_eq!(plan.decomposition.subtasks[0].description, "write the parser");
+        assert_eq!(plan.decomposition.subtasks[1].agent, "claude_code");
+
+        // Edits that break the plan leave it as it was
+        let cycle = plan.apply(PlanEdit::SetDependencies {
+            subtask_id: "a".to_string(),
+            dependencies: vec!["b".to_string()],
+        });
+        assert!(cycle.unwrap_err().contains("cycle"));
+        assert!(plan.decomposition.subtasks[0].dependencies.is_empty());
+
+        assert!(plan
+            .apply(PlanEdit::Reassign {
+                subtask_id: "a".to_string(),
+                agent: "copilot".to_string(),
+            })
+            .is_err());
+        assert!(plan
+            .apply(PlanEdit::Reassign {
+                subtask_id: "missing".to_string(),
+                agent: "claude_code".to_string(),
+            })
+            .is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::cost_tracker::{estimate_task_usage, sum_usage};
+use crate::middle_manager::TaskDecomposition;
+use crate::models::{AgentPermissions, AgentStats, TokenUsage};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// What a request would do if it were run: the plan, who would do each part, what it would
+/// likely cost and what each agent would be allowed to touch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlanPreview {
+    pub id: String,
+    pub session_id: String,
+    pub request: String,
+    pub decomposition: TaskDecomposition,
+    pub subtasks: Vec<SubtaskPreview>,
+    /// Spent producing the plan, which a preview cannot avoid.
+    pub planning_usage: TokenUsage,
+    /// Expected for running every subtask once.
+    pub estimated_usage: TokenUsage,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubtaskPreview {
+    pub subtask_id: String,
+    pub description: String,
+    pub agent: String,
+    pub dependencies: Vec<String>,
+    pub estimated_usage: TokenUsage,
+    /// `None` for work the middle manager does itself, which spawns no agent.
+    pub permissions: Option<AgentPermissions>,
+}
+
+impl PlanPreview {
+    /// Estimate each subtask from how tasks of the same agent and category have gone before.
+    pub fn new(
+        session_id: &str,
+        request: &str,
+        decomposition: TaskDecomposition,
+        stats: &[AgentStats],
+        permissions: impl Fn(&str) -> Option<AgentPermissions>,
+    ) -> Self {
+        let subtasks: Vec<SubtaskPreview> = decomposition
+            .subtasks
+            .iter()
+            .map(|subtask| {
+                let agent_stats = stats.iter().find(|stats| stats.agent_type == subtask.agent);
+                let history = agent_stats
+                    .and_then(|stats| stats.by_category.get(&subtask.category()))
+                    .filter(|category| category.prompt_tokens + category.completion_tokens > 0)
+                    .or(agent_stats.map(|stats| &stats.overall));
+
+                SubtaskPreview {
+                    subtask_id: subtask.id.clone(),
+                    description: subtask.description.clone(),
+                    agent: subtask.agent.clone(),
+                    dependencies: subtask.dependencies.clone(),
+                    estimated_usage: estimate_task_usage(history, &subtask.description),
+                    permissions: permissions(&subtask.agent),
+                }
+            })
+            .collect();
+
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            session_id: session_id.to_string(),
+            request: request.to_string(),
+            planning_usage: sum_usage(decomposition.llm_calls.iter().map(|call| &call.usage)),
+            estimated_usage: sum_usage(subtasks.iter().map(|subtask| &subtask.estimated_usage)),
+            decomposition,
+            subtasks,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::OutcomeStats;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_preview_estimates_from_history() {
+        let decomposition: TaskDecomposition = serde_json::from_value(serde_json::json!({
+            "strategy": "delegate",
+            "reasoning": "two steps",
+            "subtasks": [
+                {"id": "a", "description": "write code", "agent": "claude_code", "priority": "high", "category": "feature", "dependencies": []},
+                {"id": "b", "description": "summarise the change", "agent": "middle_manager", "priority": "low", "dependencies": ["a"]}
+            ],
+            "llm_calls": [
+                {"purpose": "plan", "model": "test-model", "usage": {"prompt_tokens": 500, "completion_tokens": 100, "cost_usd": 0.01}}
+            ]
+        }))
+        .unwrap();
+        let feature = OutcomeStats {
+            total_tasks: 4,
+            prompt_tokens: 8000,
+            completion_tokens: 2000,
+            cost_usd: 0.2,
+            ..Default::default()
+        };
+        let stats = vec![AgentStats {
+            agent_type: "claude_code".to_string(),
+            overall: OutcomeStats::default(),
+            by_model: HashMap::new(),
+            by_category: HashMap::from([("feature".to_string(), feature)]),
+        }];
+
+        let preview = PlanPreview::new("session", "build it", decomposition, &stats, |agent| {
+            (agent != "middle_manager").then(|| AgentPermissions {
+                file_read: true,
+                file_write: true,
+                network_access: false,
+                process_spawn: true,
+                allowed_paths: vec!["/work".to_string()],
+            })
+        });
+
+        assert_eq!(preview.planning_usage.prompt_tokens, 500);
+        assert_eq!(preview.subtasks[0].estimated_usage.prompt_tokens, 2000);
+        assert!((preview.subtasks[0].estimated_usage.cost_usd.unwrap() - 0.05).abs() < 1e-9);
+        assert!(preview.subtasks[0].permissions.is_some());
+
+        // No history for the middle manager, so only its description is counted
+        assert!(preview.subtasks[1].estimated_usage.prompt_tokens > 0);
+        assert_eq!(preview.subtasks[1].estimated_usage.cost_usd, None);
+        assert!(preview.subtasks[1].permissions.is_none());
+        assert_eq!(preview.estimated_usage.completion_tokens, 500);
+    }
+}
@@ -0,0 +1,419 @@
+use crate::llm_provider::{ToolCall, ToolSpec};
+use crate::models::AgentPermissions;
+use crate::repo_map::SKIPPED_DIRS;
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Most bytes of a file returned by `read_file`.
+const MAX_READ_BYTES: u64 = 64 * 1024;
+/// Most entries listed for one directory.
+const MAX_DIR_ENTRIES: usize = 500;
+const MAX_GREP_MATCHES: usize = 100;
+/// Files larger than this are skipped by `grep`.
+const MAX_GREP_FILE_BYTES: u64 = 1024 * 1024;
+/// Longest matching line quoted by `grep`.
+const MAX_GREP_LINE_CHARS: usize = 200;
+const DEFAULT_GIT_LOG_ENTRIES: u64 = 10;
+const MAX_GIT_LOG_ENTRIES: u64 = 50;
+/// Longest tool output kept in the record of a call. The model itself gets all of it.
+const MAX_RECORDED_OUTPUT_CHARS: usize = 2000;
+
+/// A tool the planner called while planning, and what came of it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCallRecord {
+    pub name: String,
+    pub arguments: serde_json::Value,
+    /// What the tool returned, shortened for storage.
+    pub output: String,
+    pub error: Option<String>,
+}
+
+impl ToolCallRecord {
+    pub fn new(call: &ToolCall, result: &Result<String, String>) -> Self {
+        let (output, error) = match result {
+            Ok(output) => (output.chars().take(MAX_RECORDED_OUTPUT_CHARS).collect(), None),
+            Err(error) => (String::new(), Some(error.clone())),
+        };
+        Self {
+            name: call.name.clone(),
+            arguments: call.arguments.clone(),
+            output,
+            error,
+        }
+    }
+}
+
+/// Read-only tools the planning model can call to look at a working tree. Every path is
+/// resolved inside the tree and checked against the middle manager's `allowed_paths`.
+#[derive(Debug, Clone)]
+pub struct RepoTools {
+    root: PathBuf,
+    allowed: Vec<AllowedPath>,
+}
+
+#[derive(Debug, Clone)]
+enum AllowedPath {
+    Everything,
+    /// A directory or file and everything below it.
+    Under(PathBuf),
+    /// A glob matched against paths relative to the tree.
+    Glob(GlobMatcher),
+}
+
+impl RepoTools {
+    /// Tools for the tree at `root`, or `None` when the permissions don't allow reading files.
+    pub fn new(root: &Path, permissions: &AgentPermissions) -> Option<Self> {
+        if !permissions.file_read {
+            return None;
+        }
+        let root = root.canonicalize().ok()?;
+        let allowed = permissions
+            .allowed_paths
+            .iter()
+            .filter_map(|allowed| {
+                if allowed == "**" {
+                    Some(AllowedPath::Everything)
+                } else if allowed.contains(['*', '?', '[']) {
+                    match Glob::new(allowed) {
+                        Ok(glob) => Some(AllowedPath::Glob(glob.compile_matcher())),
+                        Err(e) => {
+                            eprintln!("Warning: Ignoring allowed path '{}': {}", allowed, e);
+                            None
+                        }
+                    }
+                } else {
+                    let path = root.join(allowed);
+                    Some(AllowedPath::Under(path.canonicalize().unwrap_or(path)))
+                }
+            })
+            .collect();
+
+        Some(Self { root, allowed })
+    }
+
+    pub fn specs() -> Vec<ToolSpec> {
+        vec![
+            ToolSpec {
+                name: "list_directory".to_string(),
+                description: "List the files and subdirectories of a directory in the repository.".to_string(),
+                parameters: json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {"type": "string", "description": "Directory relative to the repository root. Defaults to the root."}
+                    }
+                }),
+            },
+            ToolSpec {
+                name: "read_file".to_string(),
+                description: format!("Read a text file from the repository. Only the first {} KB are returned.", MAX_READ_BYTES / 1024),
+                parameters: json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {"type": "string", "description": "File relative to the repository root."}
+                    },
+                    "required": ["path"]
+                }),
+            },
+            ToolSpec {
+                name: "grep".to_string(),
+                description: format!("Search files for lines matching a regular expression. Returns at most {} matches as path:line: text.", MAX_GREP_MATCHES),
+                parameters: json!({
+                    "type": "object",
+                    "properties": {
+                        "pattern": {"type": "string", "description": "Regular expression to search for."},
+                        "path": {"type": "string", "description": "File or directory to search, relative to the repository root. Defaults to the root."}
+                    },
+                    "required": ["pattern"]
+                }),
+            },
+            ToolSpec {
+                name: "git_log".to_string(),
+                description: "Show recent commits, one line each, optionally only those touching a path.".to_string(),
+                parameters: json!({
+                    "type": "object",
+                    "properties": {
+                        "path": {"type": "string", "description": "Only show commits touching this path."},
+                        "max_count": {"type": "integer", "description": format!("Number of commits, at most {}. Defaults to {}.", MAX_GIT_LOG_ENTRIES, DEFAULT_GIT_LOG_ENTRIES)}
+                    }
+                }),
+            },
+        ]
+    }
+
+    /// Run a tool call, returning the text to hand back to the model.
+    pub async fn run(&self, call: &ToolCall) -> Result<String, String> {
+        if call.name == "git_log" {
+            return self.git_log(&call.arguments).await;
+        }
+
+        let tools = self.clone();
+        let call = call.clone();
+        tokio::task::spawn_blocking(move || match call.name.as_str() {
+            "list_directory" => tools.list_directory(&call.arguments),
+            "read_file" => tools.read_file(&call.arguments),
+            "grep" => tools.grep(&call.arguments),
+            other => Err(format!("Unknown tool: {}", other)),
+        })
+        .await
+        .map_err(|e| format!("Tool failed: {}", e))?
+    }
+
+    fn list_directory(&self, arguments: &serde_json::Value) -> Result<String, String> {
+        let requested = optional_str(arguments, "path").unwrap_or(".");
+        let dir = self.resolve(requested)?;
+        if !dir.is_dir() {
+            return Err(format!("{} is not a directory", requested));
+        }
+        self.check_allowed(&dir, requested, true)?;
+
+        let mut entries: Vec<_> = std::fs::read_dir(&dir)
+            .map_err(|e| format!("Failed to list {}: {}", requested, e))?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name() != ".git")
+            .collect();
+        entries.sort_by_key(|entry| entry.file_name());
+
+        let lines: Vec<String> = entries
+            .iter()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let metadata = entry.metadata().ok()?;
+                if !self.permits(&entry.path(), metadata.is_dir()) {
+                    return None;
+                }
+                Some(if metadata.is_dir() {
+                    format!("{}/", name)
+                } else {
+                    format!("{} ({} bytes)", name, metadata.len())
+                })
+            })
+            .collect();
+
+        if lines.is_empty() {
+            return Ok("(empty)".to_string());
+        }
+        let mut listing = lines.iter().take(MAX_DIR_ENTRIES).cloned().collect::<Vec<_>>().join("\n");
+        if lines.len() > MAX_DIR_ENTRIES {
+            listing.push_str(&format!("\n... {} more entries", lines.len() - MAX_DIR_ENTRIES));
+        }
+        Ok(listing)
+    }
+
+    fn read_file(&self, arguments: &serde_json::Value) -> Result<String, String> {
+        let requested = required_str(arguments, "path")?;
+        let path = self.resolve(requested)?;
+        if !path.is_file() {
+            return Err(format!("{} is not a file", requested));
+        }
+        self.check_allowed(&path, requested, false)?;
+
+        let file = std::fs::File::open(&path).map_err(|e| format!("Failed to read {}: {}", requested, e))?;
+        let len = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        let mut bytes = Vec::new();
+        file.take(MAX_READ_BYTES)
+            .read_to_end(&mut bytes)
+            .map_err(|e| format!("Failed to read {}: {}", requested, e))?;
+        if bytes.contains(&0) {
+            return Err(format!("{} is a binary file", requested));
+        }
+
+        let mut text = String::from_utf8_lossy(&bytes).to_string();
+        if len > MAX_READ_BYTES {
+            text.push_str(&format!("\n[... truncated after {} of {} bytes ...]", MAX_READ_BYTES, len));
+        }
+        Ok(text)
+    }
+
+    fn grep(&self, arguments: &serde_json::Value) -> Result<String, String> {
+        let pattern = Regex::new(required_str(arguments, "pattern")?).map_err(|e| format!("Invalid pattern: {}", e))?;
+        let requested = optional_str(arguments, "path").unwrap_or(".");
+        let start = self.resolve(requested)?;
+        self.check_allowed(&start, requested, start.is_dir())?;
+
+        let mut files = Vec::new();
+        if start.is_dir() {
+            self.collect_files(&start, &mut files);
+        } else {
+            files.push(start);
+        }
+
+        let mut matches = Vec::new();
+        'files: for file in files {
+            let searchable = std::fs::metadata(&file).is_ok_and(|metadata| metadata.len() <= MAX_GREP_FILE_BYTES);
+            let Some(text) = searchable.then(|| std::fs::read_to_string(&file).ok()).flatten() else {
+                continue;
+            };
+            for (number, line) in text.lines().enumerate() {
+                if pattern.is_match(line) {
+                    if matches.len() >= MAX_GREP_MATCHES {
+                        matches.push(format!("[stopped after {} matches]", MAX_GREP_MATCHES));
+                        break 'files;
+                    }
+                    let line: String = line.trim().chars().take(MAX_GREP_LINE_CHARS).collect();
+                    matches.push(format!("{}:{}: {}", self.relative(&file), number + 1, line));
+                }
+            }
+        }
+
+        if matches.is_empty() {
+            Ok("No matches".to_string())
+        } else {
+            Ok(matches.join("\n"))
+        }
+    }
+
+    async fn git_log(&self, arguments: &serde_json::Value) -> Result<String, String> {
+        let max_count = arguments["max_count"]
+            .as_u64()
+            .unwrap_or(DEFAULT_GIT_LOG_ENTRIES)
+            .clamp(1, MAX_GIT_LOG_ENTRIES);
+
+        let mut command = tokio::process::Command::new("git");
+        command
+            .arg("-C")
+            .arg(&self.root)
+            .args(["log", "--oneline", "--no-decorate", "-n", &max_count.to_string()]);
+        if let Some(requested) = optional_str(arguments, "path") {
+            let path = self.resolve(requested)?;
+            self.check_allowed(&path, requested, path.is_dir())?;
+            command.arg("--").arg(&path);
+        }
+
+        let output = command.output().await.map_err(|e| format!("Failed to run git: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("git log failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+        }
+        let log = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok(if log.is_empty() { "No commits".to_string() } else { log })
+    }
+
+    /// Resolve a path given by the model, refusing anything outside the tree once `..` and
+    /// symlinks are followed.
+    fn resolve(&self, requested: &str) -> Result<PathBuf, String> {
+        let path = self
+            .root
+            .join(requested)
+            .canonicalize()
+            .map_err(|_| format!("No such file or directory: {}", requested))?;
+        if !path.starts_with(&self.root) {
+            return Err(format!("{} is outside the repository", requested));
+        }
+        Ok(path)
+    }
+
+    fn check_allowed(&self, path: &Path, requested: &str, is_dir: bool) -> Result<(), String> {
+        if self.permits(path, is_dir) {
+            Ok(())
+        } else {
+            Err(format!("{} is not in the middle manager's allowed paths", requested))
+        }
+    }
+
+    /// Whether `path` may be read. Directories leading to an allowed path may be listed so the
+    /// model can find its way there.
+    fn permits(&self, path: &Path, is_dir: bool) -> bool {
+        let relative = self.relative(path);
+        self.allowed.iter().any(|allowed| match allowed {
+            AllowedPath::Everything => true,
+            AllowedPath::Under(allowed) => path.starts_with(allowed) || (is_dir && allowed.starts_with(path)),
+            AllowedPath::Glob(glob) => is_dir || glob.is_match(&relative),
+        })
+    }
+
+    fn collect_files(&self, dir: &Path, files: &mut Vec<PathBuf>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        let mut entries: Vec<_> = entries.filter_map(|entry| entry.ok()).collect();
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+            if file_type.is_dir() {
+                if !SKIPPED_DIRS.contains(&name.as_str()) && self.permits(&entry.path(), true) {
+                    self.collect_files(&entry.path(), files);
+                }
+            } else if file_type.is_file() && self.permits(&entry.path(), false) {
+                files.push(entry.path());
+            }
+        }
+    }
+
+    fn relative(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+}
+
+fn required_str<'a>(arguments: &'a serde_json::Value, name: &str) -> Result<&'a str, String> {
+    optional_str(arguments, name).ok_or_else(|| format!("Missing required argument '{}'", name))
+}
+
+fn optional_str<'a>(arguments: &'a serde_json::Value, name: &str) -> Option<&'a str> {
+    arguments[name].as_str().filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn call(name: &str, arguments: serde_json::Value) -> ToolCall {
+        ToolCall {
+            id: "call_0".to_string(),
+            name: name.to_string(),
+            arguments,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tools_stay_inside_allowed_paths() {
+        let dir = TempDir::new().unwrap();
+        let repo = dir.path().join("repo");
+        std::fs::create_dir_all(repo.join("src")).unwrap();
+        std::fs::create_dir_all(repo.join("secrets")).unwrap();
+        std::fs::write(repo.join("src/lib.rs"), "pub fn parse() {}\n// TODO: handle errors\n").unwrap();
+        std::fs::write(repo.join("secrets/key.txt"), "TODO: rotate\n").unwrap();
+        std::fs::write(dir.path().join("outside.txt"), "not part of the repo\n").unwrap();
+
+        let permissions = AgentPermissions {
+            file_read: true,
+            file_write: false,
+            network_access: false,
+            process_spawn: false,
+            allowed_paths: vec!["src".to_string()],
+        };
+        let tools = RepoTools::new(&repo, &permissions).unwrap();
+
+        assert_eq!(tools.run(&call("list_directory", json!({}))).await.unwrap(), "src/");
+        assert_eq!(
+            tools.run(&call("read_file", json!({"path": "src/lib.rs"}))).await.unwrap(),
+            "pub fn parse() {}\n// TODO: handle errors\n"
+        );
+        assert_eq!(
+            tools.run(&call("grep", json!({"pattern": "TODO"}))).await.unwrap(),
+            "src/lib.rs:2: // TODO: handle errors"
+        );
+
+        let denied = tools.run(&call("read_file", json!({"path": "secrets/key.txt"}))).await;
+        assert!(denied.unwrap_err().contains("allowed paths"));
+        let escaped = tools.run(&call("read_file", json!({"path": "../outside.txt"}))).await;
+        assert!(escaped.unwrap_err().contains("outside the repository"));
+        assert!(tools.run(&call("read_file", json!({}))).await.unwrap_err().contains("Missing required argument"));
+
+        let no_read = AgentPermissions { file_read: false, ..permissions };
+        assert!(RepoTools::new(&repo, &no_read).is_none());
+    }
+}
@@ -0,0 +1,270 @@
+use crate::models::{PromptTemplateRef, TemplateOrigin};
+use minijinja::Environment;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+struct BuiltinTemplate {
+    name: &'static str,
+    version: u32,
+    source: &'static str,
+}
+
+/// Every prompt AgentTool sends. Bump a template's version whenever its wording changes so
+/// recorded tasks can be traced back to the prompt that produced them.
+const BUILTIN_TEMPLATES: &[BuiltinTemplate] = &[
+    BuiltinTemplate {
+        name: "plan_decomposition",
+        version: 3,
+        source: include_str!("../prompts/plan_decomposition.j2"),
+    },
+    BuiltinTemplate {
+        name: "plan_repair",
+        version: 1,
+        source: include_str!("../prompts/plan_repair.j2"),
+    },
+    BuiltinTemplate {
+        name: "synthesis",
+        version: 1,
+        source: include_str!("../prompts/synthesis.j2"),
+    },
+    BuiltinTemplate {
+        name: "conversation_summary",
+        version: 1,
+        source: include_str!("../prompts/conversation_summary.j2"),
+    },
+    BuiltinTemplate {
+        name: "manager_subtask",
+        version: 1,
+        source: include_str!("../prompts/manager_subtask.j2"),
+    },
+    BuiltinTemplate {
+        name: "ensemble_judgement",
+        version: 1,
+        source: include_str!("../prompts/ensemble_judgement.j2"),
+    },
+    BuiltinTemplate {
+        name: "subtask_retry",
+        version: 1,
+        source: include_str!("../prompts/subtask_retry.j2"),
+    },
+    BuiltinTemplate {
+        name: "gemini_task",
+        version: 1,
+        source: include_str!("../prompts/gemini_task.j2"),
+    },
+];
+
+const TEMPLATE_EXTENSION: &str = "j2";
+
+pub struct RenderedPrompt {
+    pub text: String,
+    pub template: PromptTemplateRef,
+}
+
+/// A template as it currently resolves for a project, for showing users what they can override.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateInfo {
+    pub template: PromptTemplateRef,
+    pub path: Option<String>,
+    pub source: String,
+}
+
+/// Directory holding user-wide overrides: `AGENT_TOOL_PROMPT_DIR`, or `~/.agenttool/prompts`.
+pub fn global_template_dir() -> Option<PathBuf> {
+    if let Ok(dir) = std::env::var("AGENT_TOOL_PROMPT_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    std::env::var("HOME")
+        .or_else(|_| std::env::var("USERPROFILE"))
+        .ok()
+        .map(|home| Path::new(&home).join(".agenttool").join("prompts"))
+}
+
+/// Directory holding overrides for one project, checked before the global directory.
+pub fn project_template_dir(project_path: &Path) -> PathBuf {
+    project_path.join(".agenttool").join("prompts")
+}
+
+/// Render a named prompt with the given variables.
+///
+/// An override at `<project>/.agenttool/prompts/<name>.j2` wins over one in the global
+/// directory, which wins over the built-in template. An override that fails to render is
+/// reported and the next candidate is used instead.
+pub fn render<S: Serialize>(name: &str, project_path: Option<&Path>, variables: S) -> RenderedPrompt {
+    let builtin = BUILTIN_TEMPLATES
+        .iter()
+        .find(|t| t.name == name)
+        .unwrap_or_else(|| panic!("Unknown prompt template: {}", name));
+
+    for (origin, source, path) in overrides(name, project_path) {
+        match render_source(&source, &variables) {
+            Ok(text) => {
+                return RenderedPrompt {
+                    text,
+                    template: PromptTemplateRef {
+                        name: name.to_string(),
+                        version: override_version(&source),
+                        origin,
+                    },
+                }
+            }
+            Err(e) => eprintln!(
+                "Warning: Ignoring prompt template override {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    let text = render_source(builtin.source, &variables)
+        .unwrap_or_else(|e| panic!("Built-in prompt template {} failed to render: {}", name, e));
+    RenderedPrompt {
+        text,
+        template: PromptTemplateRef {
+            name: name.to_string(),
+            version: builtin.version.to_string(),
+            origin: TemplateOrigin::Builtin,
+        },
+    }
+}
+
+/// Render a template chosen at runtime, such as one pinned by a routing rule. `name` may be a
+/// built-in template or one that only exists as an override; when it is neither, or every
+/// source of it fails to render, the `default` template is used instead.
+pub fn render_named<S: Serialize>(
+    name: &str,
+    default: &str,
+    project_path: Option<&Path>,
+    variables: S,
+) -> RenderedPrompt {
+    if BUILTIN_TEMPLATES.iter().any(|t| t.name == name) {
+        return render(name, project_path, variables);
+    }
+
+    for (origin, source, path) in overrides(name, project_path) {
+        match render_source(&source, &variables) {
+            Ok(text) => {
+                return RenderedPrompt {
+                    text,
+                    template: PromptTemplateRef {
+                        name: name.to_string(),
+                        version: override_version(&source),
+                        origin,
+                    },
+                }
+            }
+            Err(e) => eprintln!("Warning: Ignoring prompt template {}: {}", path.display(), e),
+        }
+    }
+
+    eprintln!("Warning: Prompt template {} not found, using {}", name, default);
+    render(default, project_path, variables)
+}
+
+/// Describe how every template resolves for a project.
+pub fn list_templates(project_path: Option<&Path>) -> Vec<TemplateInfo> {
+    BUILTIN_TEMPLATES
+        .iter()
+        .map(|builtin| match overrides(builtin.name, project_path).into_iter().next() {
+            Some((origin, source, path)) => TemplateInfo {
+                template: PromptTemplateRef {
+                    name: builtin.name.to_string(),
+                    version: override_version(&source),
+                    origin,
+                },
+                path: Some(path.to_string_lossy().to_string()),
+                source,
+            },
+            None => TemplateInfo {
+                template: PromptTemplateRef {
+                    name: builtin.name.to_string(),
+                    version: builtin.version.to_string(),
+                    origin: TemplateOrigin::Builtin,
+                },
+                path: None,
+                source: builtin.source.to_string(),
+            },
+        })
+        .collect()
+}
+
+/// Overrides for a template that exist on disk, most specific first.
+fn overrides(name: &str, project_path: Option<&Path>) -> Vec<(TemplateOrigin, String, PathBuf)> {
+    let file_name = format!("{}.{}", name, TEMPLATE_EXTENSION);
+    let candidates = [
+        project_path.map(|path| (TemplateOrigin::Project, project_template_dir(path).join(&file_name))),
+        global_template_dir().map(|dir| (TemplateOrigin::Global, dir.join(&file_name))),
+    ];
+
+    candidates
+        .into_iter()
+        .flatten()
+        .filter_map(|(origin, path)| {
+            std::fs::read_to_string(&path).ok().map(|source| (origin, source, path))
+        })
+        .collect()
+}
+
+fn render_source<S: Serialize>(source: &str, variables: &S) -> Result<String, minijinja::Error> {
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.render_str(source, variables)
+}
+
+/// Overrides declare their version with a leading `{# version: 2 #}` comment. Without one the
+/// version is a hash of the template text, so edits still show up in task history.
+fn override_version(source: &str) -> String {
+    let declared = source
+        .trim_start()
+        .strip_prefix("{#")
+        .and_then(|rest| rest.split_once("#}"))
+        .and_then(|(comment, _)| comment.trim().strip_prefix("version:"))
+        .map(|version| version.trim().to_string())
+        .filter(|version| !version.is_empty());
+
+    declared.unwrap_or_else(|| {
+        // FNV-1a, which unlike std's hasher is stable across releases
+        let hash = source.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        format!("hash-{:08x}", hash as u32)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minijinja::context;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_render_builtin_and_project_override() {
+        let project = TempDir::new().unwrap();
+
+        let rendered = render(
+            "plan_repair",
+            Some(project.path()),
+            context! { prompt => "Plan this", response => "{}", errors => vec!["no subtasks", "bad agent"] },
+        );
+        assert_eq!(rendered.template.origin, TemplateOrigin::Builtin);
+        assert_eq!(rendered.template.version, "1");
+        assert!(rendered.text.contains("reasons:\n- no subtasks\n- bad agent\n\nRespond"));
+
+        let rendered = render("gemini_task", None, context! { task => "Add tests", context => "" });
+        assert!(rendered.text.starts_with("Task: Add tests"));
+
+        let dir = project_template_dir(project.path());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("gemini_task.j2"), "{# version: 7 #}\nDo: {{ task }}").unwrap();
+        let rendered = render("gemini_task", Some(project.path()), context! { task => "Add tests" });
+        assert_eq!(rendered.text, "Do: Add tests");
+        assert_eq!(rendered.template.origin, TemplateOrigin::Project);
+        assert_eq!(rendered.template.version, "7");
+
+        // A broken override falls back to the built-in template
+        std::fs::write(dir.join("gemini_task.j2"), "Do: {{ task ").unwrap();
+        let rendered = render("gemini_task", Some(project.path()), context! { task => "Add tests" });
+        assert_eq!(rendered.template.origin, TemplateOrigin::Builtin);
+    }
+}
@@ -0,0 +1,321 @@
+use crate::context_window::count_tokens;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tree_sitter::{Language, Node, Parser};
+
+/// Directories that hold dependencies, build output or VCS data rather than project code.
+pub(crate) const SKIPPED_DIRS: &[&str] = &["target", "node_modules", "dist", "build", "vendor", "__pycache__", "venv"];
+/// Files larger than this are listed but not parsed.
+const MAX_PARSED_FILE_BYTES: u64 = 512 * 1024;
+/// Upper bound on files visited, so a huge checkout can't stall planning.
+const MAX_FILES: usize = 5000;
+
+/// A top-level declaration in a source file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Symbol {
+    pub kind: String,
+    pub name: String,
+}
+
+struct FileEntry {
+    modified: Option<SystemTime>,
+    len: u64,
+    symbols: Vec<Symbol>,
+}
+
+/// Builds repository maps and keeps the parsed symbols of every file it has seen, so later
+/// maps of the same tree only reparse files whose size or modification time changed.
+#[derive(Clone, Default)]
+pub struct RepoMapper {
+    cache: Arc<Mutex<HashMap<PathBuf, HashMap<PathBuf, FileEntry>>>>,
+}
+
+impl RepoMapper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Render the file tree under `root` with each file's top-level symbols, trimmed to
+    /// `token_budget` tokens.
+    pub async fn repo_map(&self, root: &Path, token_budget: usize) -> anyhow::Result<String> {
+        let mapper = self.clone();
+        let root = root.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let files = mapper.refresh(&root)?;
+            Ok(render_map(&files, token_budget))
+        })
+        .await?
+    }
+
+    /// Bring the cache for `root` up to date and return its files in map order.
+    fn refresh(&self, root: &Path) -> anyhow::Result<Vec<(PathBuf, Vec<Symbol>)>> {
+        let mut paths = Vec::new();
+        collect_files(root, root, &mut paths)?;
+
+        let mut cache = self.cache.lock().unwrap();
+        let entries = cache.entry(root.to_path_buf()).or_default();
+        entries.retain(|path, _| paths.contains(path));
+
+        let mut parser = Parser::new();
+        for relative in &paths {
+            let metadata = match std::fs::metadata(root.join(relative)) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            let modified = metadata.modified().ok();
+            let unchanged = entries
+                .get(relative)
+                .is_some_and(|entry| entry.modified == modified && entry.len == metadata.len());
+            if unchanged {
+                continue;
+            }
+
+            let symbols = if metadata.len() <= MAX_PARSED_FILE_BYTES {
+                std::fs::read_to_string(root.join(relative))
+                    .map(|source| extract_symbols(&mut parser, relative, &source))
+                    .unwrap_or_default()
+            } else {
+                Vec::new()
+            };
+            entries.insert(relative.clone(), FileEntry {
+                modified,
+                len: metadata.len(),
+                symbols,
+            });
+        }
+
+        // Shallow paths first so the overall layout survives trimming
+        paths.sort_by_key(|path| (path.components().count(), path.clone()));
+        Ok(paths
+            .into_iter()
+            .filter_map(|path| entries.get(&path).map(|entry| (path.clone(), entry.symbols.clone())))
+            .collect())
+    }
+}
+
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let mut entries: Vec<_> = std::fs::read_dir(dir)?.filter_map(|entry| entry.ok()).collect();
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        if files.len() >= MAX_FILES {
+            break;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(_) => continue,
+        };
+
+        if file_type.is_dir() {
+            if !SKIPPED_DIRS.contains(&name.as_str()) {
+                collect_files(root, &entry.path(), files)?;
+            }
+        } else if file_type.is_file() {
+            if let Ok(relative) = entry.path().strip_prefix(root) {
+                files.push(relative.to_path_buf());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn language_for(path: &Path) -> Option<Language> {
+    let language = match path.extension()?.to_str()? {
+        "rs" => tree_sitter_rust::LANGUAGE,
+        "py" => tree_sitter_python::LANGUAGE,
+        "js" | "jsx" | "mjs" | "cjs" => tree_sitter_javascript::LANGUAGE,
+        "ts" => tree_sitter_typescript::LANGUAGE_TYPESCRIPT,
+        "tsx" => tree_sitter_typescript::LANGUAGE_TSX,
+        "go" => tree_sitter_go::LANGUAGE,
+        _ => return None,
+    };
+    Some(language.into())
+}
+
+/// Parse a file and list its top-level declarations. Unsupported languages have none.
+pub fn extract_symbols(parser: &mut Parser, path: &Path, source: &str) -> Vec<Symbol> {
+    let Some(language) = language_for(path) else {
+        return Vec::new();
+    };
+    if parser.set_language(&language).is_err() {
+        return Vec::new();
+    }
+    let Some(tree) = parser.parse(source, None) else {
+        return Vec::new();
+    };
+
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    let mut symbols = Vec::new();
+    for node in root.named_children(&mut cursor) {
+        collect_declaration(node, source, &mut symbols);
+    }
+    symbols
+}
+
+fn collect_declaration(node: Node, source: &str, symbols: &mut Vec<Symbol>) {
+    let text = |node: Node| source[node.byte_range()].to_string();
+    let named = |kind: &str, field: &str| {
+        node.child_by_field_name(field).map(|name| Symbol {
+            kind: kind.to_string(),
+            name: text(name),
+        })
+    };
+
+    let symbol = match node.kind() {
+        // Rust
+        "function_item" | "function_signature_item" => named("fn", "name"),
+        "struct_item" => named("struct", "name"),
+        "enum_item" => named("enum", "name"),
+        "trait_item" => named("trait", "name"),
+        "type_item" => named("type", "name"),
+        "mod_item" => named("mod", "name"),
+        "macro_definition" => named("macro", "name"),
+        "impl_item" => node.child_by_field_name("type").map(|ty| Symbol {
+            kind: "impl".to_string(),
+            name: match node.child_by_field_name("trait") {
+                Some(trait_name) => format!("{} for {}", text(trait_name), text(ty)),
+                None => text(ty),
+            },
+        }),
+        // Python
+        "function_definition" => named("def", "name"),
+        "class_definition" => named("class", "name"),
+        "decorated_definition" => {
+            if let Some(definition) = node.child_by_field_name("definition") {
+                collect_declaration(definition, source, symbols);
+            }
+            None
+        }
+        // JavaScript, TypeScript and Go
+        "function_declaration" | "generator_function_declaration" => named("function", "name"),
+        "class_declaration" | "abstract_class_declaration" => named("class", "name"),
+        "interface_declaration" => named("interface", "name"),
+        "type_alias_declaration" => named("type", "name"),
+        "enum_declaration" => named("enum", "name"),
+        "internal_module" | "module" => named("namespace", "name"),
+        "method_declaration" => named("func", "name"),
+        "export_statement" => {
+            if let Some(declaration) = node.child_by_field_name("declaration") {
+                collect_declaration(declaration, source, symbols);
+            }
+            None
+        }
+        "type_declaration" => {
+            let mut cursor = node.walk();
+            for spec in node.named_children(&mut cursor) {
+                if let Some(name) = spec.child_by_field_name("name") {
+                    symbols.push(Symbol {
+                        kind: "type".to_string(),
+                        name: text(name),
+                    });
+                }
+            }
+            None
+        }
+        _ => None,
+    };
+
+    symbols.extend(symbol);
+}
+
+/// Lay out the map, giving each file its symbols while the budget allows, then just its path,
+/// and finally a count of the files left out.
+fn render_map(files: &[(PathBuf, Vec<Symbol>)], token_budget: usize) -> String {
+    let mut map = String::new();
+    let mut used = 0;
+
+    for (index, (path, symbols)) in files.iter().enumerate() {
+        let path_line = format!("{}\n", path.to_string_lossy().replace('\\', "/"));
+        let symbol_lines: String = symbols
+            .iter()
+            .map(|symbol| format!("  {} {}\n", symbol.kind, symbol.name))
+            .collect();
+
+        let full = format!("{}{}", path_line, symbol_lines);
+        let entry = if used + count_tokens(&full) <= token_budget {
+            full
+        } else if used + count_tokens(&path_line) <= token_budget {
+            path_line
+        } else {
+            map.push_str(&format!("... {} more files not shown\n", files.len() - index));
+            break;
+        };
+
+        used += count_tokens(&entry);
+        map.push_str(&entry);
+    }
+
+    map.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn symbol(kind: &str, name: &str) -> Symbol {
+        Symbol {
+            kind: kind.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_extract_symbols() {
+        let mut parser = Parser::new();
+
+        let rust = "mod tests;\npub struct Plan { id: u32 }\nimpl Display for Plan {}\nfn main() { fn inner() {} }\n";
+        assert_eq!(
+            extract_symbols(&mut parser, Path::new("main.rs"), rust),
+            vec![symbol("mod", "tests"), symbol("struct", "Plan"), symbol("impl", "Display for Plan"), symbol("fn", "main")]
+        );
+
+        let python = "import os\n\n@dataclass\nclass Job:\n    def run(self): pass\n\ndef main():\n    pass\n";
+        assert_eq!(
+            extract_symbols(&mut parser, Path::new("jobs.py"), python),
+            vec![symbol("class", "Job"), symbol("def", "main")]
+        );
+
+        let typescript = "export interface Props { id: string }\nexport function render() {}\n";
+        assert_eq!(
+            extract_symbols(&mut parser, Path::new("view.ts"), typescript),
+            vec![symbol("interface", "Props"), symbol("function", "render")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_repo_map_updates_and_trims() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        std::fs::create_dir_all(dir.path().join("node_modules/dep")).unwrap();
+        std::fs::write(dir.path().join("src/lib.rs"), "pub fn parse() {}\n").unwrap();
+        std::fs::write(dir.path().join("node_modules/dep/index.js"), "function hidden() {}\n").unwrap();
+        std::fs::write(dir.path().join("README.md"), "# Project\n").unwrap();
+
+        let mapper = RepoMapper::new();
+        let map = mapper.repo_map(dir.path(), 1000).await.unwrap();
+        assert_eq!(map, "README.md\nsrc/lib.rs\n  fn parse");
+
+        // Changed files are reparsed on the next build
+        std::fs::write(dir.path().join("src/lib.rs"), "pub fn parse() {}\npub struct Token;\n").unwrap();
+        let map = mapper.repo_map(dir.path(), 1000).await.unwrap();
+        assert!(map.contains("  struct Token"));
+
+        // Over budget, files lose their symbols first and are then left out entirely
+        let paths_only = count_tokens("README.md\n") + count_tokens("src/lib.rs\n");
+        assert_eq!(mapper.repo_map(dir.path(), paths_only).await.unwrap(), "README.md\nsrc/lib.rs");
+        assert_eq!(
+            mapper.repo_map(dir.path(), count_tokens("README.md\n")).await.unwrap(),
+            "README.md\n... 1 more files not shown"
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use crate::dag_executor::NodeState;
+use crate::models::TaskResult;
+use crate::session_manager::ConversationMessage;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum RunStatus {
+    Running,
+    Completed,
+    Failed,
+    /// Told to stop before all of its subtasks had run.
+    Cancelled,
+}
+
+/// A user request being planned and run in the background.
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestRun {
+    pub id: String,
+    pub session_id: String,
+    pub request: String,
+    pub status: RunStatus,
+    pub error: Option<String>,
+    /// Why the run was told to stop. Once set, no more of its subtasks start.
+    pub cancel_reason: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Where a run has got to: the subtasks running now and the state of its whole plan.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunProgress {
+    #[serde(flatten)]
+    pub run: RequestRun,
+    pub current_subtasks: Vec<NodeState>,
+    pub plan: Vec<NodeState>,
+}
+
+/// What a run has produced so far.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunResults {
+    pub run_id: String,
+    pub status: RunStatus,
+    /// Messages added to the conversation since the run started.
+    pub messages: Vec<ConversationMessage>,
+    /// Subtasks that have finished, in the order they finished.
+    pub task_results: Vec<TaskResult>,
+}
+
+/// Runs started since the app was launched. A session runs one request at a time.
+#[derive(Default)]
+pub struct RunRegistry {
+    runs: RwLock<HashMap<String, RequestRun>>,
+}
+
+impl RunRegistry {
+    /// Register a new run for a session, unless it is still running another.
+    pub fn start(&self, session_id: &str, request: &str) -> Result<RequestRun, String> {
+        let mut runs = self.runs.write().unwrap();
+        if let Some(running) = runs
+            .values()
+            .find(|run| run.session_id == session_id && run.status == RunStatus::Running)
+        {
+            return Err(format!("Session {} is still running request {}", session_id, running.id));
+        }
+
+        let run = RequestRun {
+            id: uuid::Uuid::new_v4().to_string(),
+            session_id: session_id.to_string(),
+            request: request.to_string(),
+            status: RunStatus::Running,
+            error: None,
+            cancel_reason: None,
+            started_at: Utc::now(),
+            finished_at: None,
+        };
+        runs.insert(run.id.clone(), run.clone());
+        Ok(run)
+    }
+
+    /// Mark a run as finished: cancelled if it was told to stop, otherwise failed if it
+    /// ended with `error`.
+    pub fn finish(&self, run_id: &str, error: Option<String>) {
+        let mut runs = self.runs.write().unwrap();
+        if let Some(run) = runs.get_mut(run_id) {
+            run.status = match (&run.cancel_reason, &error) {
+                (Some(_), _) => RunStatus::Cancelled,
+                (None, Some(_)) => RunStatus::Failed,
+                (None, None) => RunStatus::Completed,
+            };
+            run.error = error;
+            run.finished_at = Some(Utc::now());
+        }
+    }
+
+    /// Stop a session's running request from starting any more subtasks. Returns the run
+    /// told to stop, if there was one.
+    pub fn cancel(&self, session_id: &str, reason: &str) -> Option<RequestRun> {
+        let mut runs = self.runs.write().unwrap();
+        let run = runs
+            .values_mut()
+            .find(|run| run.session_id == session_id && run.status == RunStatus::Running)?;
+        run.cancel_reason.get_or_insert_with(|| reason.to_string());
+        Some(run.clone())
+    }
+
+    /// Why the session's running request was told to stop, if it was.
+    pub fn cancellation(&self, session_id: &str) -> Option<String> {
+        self.active(session_id).and_then(|run| run.cancel_reason)
+    }
+
+    /// The request a session is running, if any.
+    pub fn active(&self, session_id: &str) -> Option<RequestRun> {
+        self.runs
+            .read()
+            .unwrap()
+            .values()
+            .find(|run| run.session_id == session_id && run.status == RunStatus::Running)
+            .cloned()
+    }
+
+    pub fn get(&self, run_id: &str) -> Option<RequestRun> {
+        self.runs.read().unwrap().get(run_id).cloned()
+    }
+
+    /// A session's runs, newest first.
+    pub fn for_session(&self, session_id: &str) -> Vec<RequestRun> {
+        let mut runs: Vec<RequestRun> = self
+            .runs
+            .read()
+            .unwrap()
+            .values()
+            .filter(|run| run.session_id == session_id)
+            .cloned()
+            .collect();
+        runs.sort_by_key(|run| std::cmp::Reverse(run.started_at));
+        runs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_run_per_session_at_a_time() {
+        let registry = RunRegistry::default();
+        let first = registry.start("session", "fix the parser").unwrap();
+        assert!(registry.start("session", "and the lexer").is_err());
+        assert!(registry.start("other", "write docs").is_ok());
+
+        registry.finish(&first.id, Some("planner unavailable".to_string()));
+        let finished = registry.get(&first.id).unwrap();
+        assert_eq!(finished.status, RunStatus::Failed);
+        assert_eq!(finished.error.as_deref(), Some("planner unavailable"));
+        assert!(finished.finished_at.is_some());
+
+        let second = registry.start("session", "and the lexer").unwrap();
+        registry.finish(&second.id, None);
+        assert_eq!(registry.get(&second.id).unwrap().status, RunStatus::Completed);
+        let runs = registry.for_session("session");
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].id, second.id);
+    }
+
+    #[test]
+    fn test_cancelled_run_stops_and_finishes_cancelled() {
+        let registry = RunRegistry::default();
+        assert!(registry.cancel("session", "paused").is_none());
+
+        let run = registry.start("session", "fix the parser").unwrap();
+        assert!(registry.cancellation("session").is_none());
+        assert_eq!(registry.cancel("session", "Session paused").unwrap().id, run.id);
+        assert_eq!(registry.cancellation("session").as_deref(), Some("Session paused"));
+
+        // The run is still winding down until its worker finishes it
+        assert!(registry.start("session", "and the lexer").is_err());
+        registry.finish(&run.id, None);
+        let finished = registry.get(&run.id).unwrap();
+        assert_eq!(finished.status, RunStatus::Cancelled);
+        assert_eq!(finished.cancel_reason.as_deref(), Some("Session paused"));
+        assert!(registry.cancellation("session").is_none());
+    }
+}
@@ -0,0 +1,280 @@
+use crate::middle_manager::KNOWN_AGENTS;
+use crate::models::RuleMatch;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+const RULES_FILE: &str = "routing_rules.json";
+
+/// A routing decision made without asking the LLM. Every condition given must hold for the
+/// rule to match; a matching rule pins the request to an agent, a planning model or a planning
+/// prompt template.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingRule {
+    pub name: String,
+    /// Regex searched for in the user's message.
+    #[serde(default)]
+    pub message_pattern: Option<String>,
+    /// Globs checked against file paths mentioned in the message; any one matching is enough.
+    #[serde(default)]
+    pub file_globs: Vec<String>,
+    /// Session tags, of which the session must have at least one.
+    #[serde(default)]
+    pub session_tags: Vec<String>,
+    /// Hand the whole request to this agent without planning it.
+    #[serde(default)]
+    pub agent: Option<String>,
+    /// Plan the request with this model.
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Plan the request with this prompt template instead of `plan_decomposition`.
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+/// A rule as loaded for a project, for showing users which rules are in effect.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingRuleInfo {
+    pub rule: RoutingRule,
+    pub source: String,
+    /// Why the rule is being ignored, if it is.
+    pub error: Option<String>,
+}
+
+pub struct CompiledRule {
+    rule: RoutingRule,
+    source: PathBuf,
+    message_pattern: Option<Regex>,
+    file_globs: Option<GlobSet>,
+}
+
+/// What the matching rules pinned. The first rule to set each field wins.
+#[derive(Debug, Default, PartialEq)]
+pub struct Routing {
+    pub agent: Option<String>,
+    pub model: Option<String>,
+    pub template: Option<String>,
+}
+
+/// Rules file shared by all projects: `AGENT_TOOL_ROUTING_RULES`, or
+/// `~/.agenttool/routing_rules.json`.
+pub fn global_rules_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("AGENT_TOOL_ROUTING_RULES") {
+        return Some(PathBuf::from(path));
+    }
+    std::env::var("HOME")
+        .or_else(|_| std::env::var("USERPROFILE"))
+        .ok()
+        .map(|home| Path::new(&home).join(".agenttool").join(RULES_FILE))
+}
+
+/// Rules file for one project, whose rules are evaluated before the global ones.
+pub fn project_rules_path(project_path: &Path) -> PathBuf {
+    project_path.join(".agenttool").join(RULES_FILE)
+}
+
+/// Every rule configured for a project, including those that fail to compile.
+pub fn list_rules(project_path: Option<&Path>) -> Vec<RoutingRuleInfo> {
+    let mut infos = Vec::new();
+    for path in rule_files(project_path) {
+        match read_rules(&path) {
+            Ok(rules) => infos.extend(rules.into_iter().map(|rule| {
+                let error = compile(rule.clone(), &path).err();
+                RoutingRuleInfo {
+                    rule,
+                    source: path.to_string_lossy().to_string(),
+                    error,
+                }
+            })),
+            Err(e) => eprintln!("Warning: Ignoring routing rules in {}: {}", path.display(), e),
+        }
+    }
+    infos
+}
+
+/// Load the rules in effect for a project, most specific first. Invalid rules are reported
+/// and skipped.
+pub fn load_rules(project_path: Option<&Path>) -> Vec<CompiledRule> {
+    let mut compiled = Vec::new();
+    for path in rule_files(project_path) {
+        let rules = match read_rules(&path) {
+            Ok(rules) => rules,
+            Err(e) => {
+                eprintln!("Warning: Ignoring routing rules in {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        for rule in rules {
+            let name = rule.name.clone();
+            match compile(rule, &path) {
+                Ok(rule) => compiled.push(rule),
+                Err(e) => eprintln!("Warning: Ignoring routing rule '{}' in {}: {}", name, path.display(), e),
+            }
+        }
+    }
+    compiled
+}
+
+fn rule_files(project_path: Option<&Path>) -> Vec<PathBuf> {
+    [project_path.map(project_rules_path), global_rules_path()]
+        .into_iter()
+        .flatten()
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+fn read_rules(path: &Path) -> anyhow::Result<Vec<RoutingRule>> {
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+fn compile(rule: RoutingRule, source: &Path) -> Result<CompiledRule, String> {
+    if rule.message_pattern.is_none() && rule.file_globs.is_empty() && rule.session_tags.is_empty() {
+        return Err("rule has no conditions and would match every request".to_string());
+    }
+    if rule.agent.is_none() && rule.model.is_none() && rule.template.is_none() {
+        return Err("rule pins no agent, model or template".to_string());
+    }
+    if let Some(agent) = &rule.agent {
+        if !KNOWN_AGENTS.contains(&agent.as_str()) {
+            return Err(format!("unknown agent '{}' (expected one of: {})", agent, KNOWN_AGENTS.join(", ")));
+        }
+    }
+
+    let message_pattern = rule
+        .message_pattern
+        .as_deref()
+        .map(Regex::new)
+        .transpose()
+        .map_err(|e| format!("invalid message pattern: {}", e))?;
+    let file_globs = if rule.file_globs.is_empty() {
+        None
+    } else {
+        let mut builder = GlobSetBuilder::new();
+        for glob in &rule.file_globs {
+            builder.add(Glob::new(glob).map_err(|e| format!("invalid file glob: {}", e))?);
+        }
+        Some(builder.build().map_err(|e| format!("invalid file globs: {}", e))?)
+    };
+
+    Ok(CompiledRule {
+        rule,
+        source: source.to_path_buf(),
+        message_pattern,
+        file_globs,
+    })
+}
+
+/// Paths mentioned in a message: words that contain a `/` or end in a file extension.
+pub fn mentioned_files(message: &str) -> Vec<String> {
+    message
+        .split_whitespace()
+        .map(|word| {
+            word.trim_matches(|c: char| !(c.is_alphanumeric() || "._/-*".contains(c)))
+                .trim_end_matches('.')
+        })
+        .filter(|word| {
+            word.contains('/')
+                || word
+                    .rsplit_once('.')
+                    .is_some_and(|(stem, extension)| !stem.is_empty() && extension.chars().all(char::is_alphanumeric) && !extension.is_empty())
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// Check every rule against a request, returning the ones that matched with their reasons.
+pub fn evaluate(rules: &[CompiledRule], message: &str, session_tags: &[String]) -> Vec<RuleMatch> {
+    let files = mentioned_files(message);
+
+    rules
+        .iter()
+        .filter_map(|compiled| {
+            let mut reasons = Vec::new();
+
+            if let Some(pattern) = &compiled.message_pattern {
+                let found = pattern.find(message)?;
+                reasons.push(format!("message matched /{}/ at \"{}\"", pattern.as_str(), found.as_str()));
+            }
+            if let Some(globs) = &compiled.file_globs {
+                let file = files.iter().find(|file| globs.is_match(file.as_str()))?;
+                reasons.push(format!("mentioned file {} matched {}", file, compiled.rule.file_globs.join(", ")));
+            }
+            if !compiled.rule.session_tags.is_empty() {
+                let tag = compiled.rule.session_tags.iter().find(|tag| session_tags.contains(tag))?;
+                reasons.push(format!("session is tagged {}", tag));
+            }
+
+            Some(RuleMatch {
+                rule: compiled.rule.name.clone(),
+                source: compiled.source.to_string_lossy().to_string(),
+                reasons,
+                agent: compiled.rule.agent.clone(),
+                model: compiled.rule.model.clone(),
+                template: compiled.rule.template.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Combine what the matching rules pinned, earlier rules taking precedence.
+pub fn resolve(matches: &[RuleMatch]) -> Routing {
+    let first = |field: fn(&RuleMatch) -> &Option<String>| matches.iter().find_map(|m| field(m).clone());
+    Routing {
+        agent: first(|m| &m.agent),
+        model: first(|m| &m.model),
+        template: first(|m| &m.template),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_rules_match_and_resolve_in_order() {
+        let project = TempDir::new().unwrap();
+        let path = project_rules_path(project.path());
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(
+            &path,
+            serde_json::json!([
+                {"name": "docs", "file_globs": ["**/*.md"], "agent": "gemini_cli"},
+                {"name": "frontend", "session_tags": ["frontend"], "message_pattern": "(?i)component", "model": "openai/gpt-4o"},
+                {"name": "catch-all", "agent": "claude_code"},
+                {"name": "typo", "message_pattern": "bug", "agent": "copilot"},
+                {"name": "review", "message_pattern": "(?i)review", "template": "plan_review", "agent": "claude_code"}
+            ])
+            .to_string(),
+        )
+        .unwrap();
+
+        let infos = list_rules(Some(project.path()));
+        assert_eq!(infos.len(), 5);
+        assert!(infos[2].error.as_ref().unwrap().contains("no conditions"));
+        assert!(infos[3].error.as_ref().unwrap().contains("unknown agent"));
+
+        let rules = load_rules(Some(project.path()));
+        assert_eq!(rules.len(), 3);
+
+        let tags = vec!["frontend".to_string()];
+        let matches = evaluate(&rules, "Review the Button component and update docs/README.md.", &tags);
+        let names: Vec<&str> = matches.iter().map(|m| m.rule.as_str()).collect();
+        assert_eq!(names, vec!["docs", "frontend", "review"]);
+        assert_eq!(matches[0].reasons, vec!["mentioned file docs/README.md matched **/*.md"]);
+        assert_eq!(matches[1].reasons.len(), 2);
+        assert_eq!(
+            resolve(&matches),
+            Routing {
+                agent: Some("gemini_cli".to_string()),
+                model: Some("openai/gpt-4o".to_string()),
+                template: Some("plan_review".to_string()),
+            }
+        );
+
+        // Without the tag the frontend rule no longer applies
+        let matches = evaluate(&rules, "Fix the component", &[]);
+        assert!(matches.is_empty());
+    }
+}
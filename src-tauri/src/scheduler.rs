@@ -0,0 +1,347 @@
+use crate::dag_executor::priority_rank;
+use crate::middle_manager::SubTask;
+use crate::models::SessionPriority;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Slots an agent gets when the registry doesn't set `max_concurrent_tasks`.
+const DEFAULT_CAPACITY: usize = 1;
+
+/// Shares agent capacity between every session. When an agent is busy, subtasks wait in
+/// its queue and each freed slot goes to the waiter with the best effective priority: its
+/// own priority plus its session's, improving by one level for every `aging` spent waiting
+/// so that low-priority work is never starved.
+pub struct Scheduler {
+    state: Mutex<SchedulerState>,
+    aging: Duration,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    capacities: HashMap<String, usize>,
+    running: HashMap<String, usize>,
+    session_priorities: HashMap<String, SessionPriority>,
+    waiting: Vec<Waiter>,
+    next_sequence: u64,
+}
+
+struct Waiter {
+    subtask: QueuedSubtask,
+    sequence: u64,
+    grant: oneshot::Sender<()>,
+}
+
+/// A subtask asking for a slot on its agent.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueuedSubtask {
+    pub session_id: String,
+    pub subtask_id: String,
+    pub description: String,
+    pub agent: String,
+    pub priority: String,
+    pub enqueued_at: DateTime<Utc>,
+}
+
+impl QueuedSubtask {
+    /// A subtask of `session_id` asking for a slot on `agent`, which for ensemble
+    /// candidates isn't the subtask's own.
+    pub fn new(session_id: &str, subtask: &SubTask, agent: &str) -> Self {
+        Self {
+            session_id: session_id.to_string(),
+            subtask_id: subtask.id.clone(),
+            description: subtask.description.clone(),
+            agent: agent.to_string(),
+            priority: subtask.priority.clone(),
+            enqueued_at: Utc::now(),
+        }
+    }
+}
+
+/// A waiting subtask as shown to the user, in the order slots will be handed out.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueEntry {
+    #[serde(flatten)]
+    pub subtask: QueuedSubtask,
+    pub session_priority: SessionPriority,
+    /// Lower runs sooner.
+    pub effective_priority: f64,
+    /// Place in its agent's queue, starting at 1.
+    pub position: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentLoad {
+    pub agent: String,
+    pub capacity: usize,
+    pub running: usize,
+    pub waiting: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueSnapshot {
+    pub agents: Vec<AgentLoad>,
+    pub waiting: Vec<QueueEntry>,
+}
+
+/// A slot on an agent, given back when dropped.
+pub struct SchedulerPermit {
+    scheduler: Arc<Scheduler>,
+    agent: String,
+}
+
+impl Drop for SchedulerPermit {
+    fn drop(&mut self) {
+        self.scheduler.release(&self.agent);
+    }
+}
+
+/// Withdraws a waiter whose `acquire` was dropped, returning the slot if one had already
+/// been granted to it.
+struct PendingGrant {
+    scheduler: Arc<Scheduler>,
+    agent: String,
+    sequence: u64,
+    armed: bool,
+}
+
+impl Drop for PendingGrant {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let mut state = self.scheduler.state.lock().unwrap();
+        let before = state.waiting.len();
+        state.waiting.retain(|waiter| waiter.sequence != self.sequence);
+        if state.waiting.len() == before {
+            drop(state);
+            self.scheduler.release(&self.agent);
+        }
+    }
+}
+
+/// Priority of a waiting subtask, lower first. High, medium and low subtasks rank 0, 1
+/// and 2, sessions add 0, 1 or 2 on top, and every `aging` spent waiting takes off one.
+pub fn effective_priority(priority: &str, session_priority: &SessionPriority, waited: Duration, aging: Duration) -> f64 {
+    let session_rank = match session_priority {
+        SessionPriority::High => 0,
+        SessionPriority::Normal => 1,
+        SessionPriority::Low => 2,
+    };
+    let base = (priority_rank(priority) + session_rank) as f64;
+    base - waited.as_secs_f64() / aging.as_secs_f64().max(f64::EPSILON)
+}
+
+impl Scheduler {
+    pub fn new(aging: Duration) -> Self {
+        Self {
+            state: Mutex::new(SchedulerState::default()),
+            aging,
+        }
+    }
+
+    /// Replace the agents' capacities, handing out any slots this frees up.
+    pub fn set_capacities(&self, capacities: HashMap<String, usize>) {
+        let mut state = self.state.lock().unwrap();
+        state.capacities = capacities;
+        let agents: HashSet<String> = state.waiting.iter().map(|waiter| waiter.subtask.agent.clone()).collect();
+        for agent in agents {
+            self.grant_free_slots(&mut state, &agent);
+        }
+    }
+
+    pub fn set_session_priority(&self, session_id: &str, priority: SessionPriority) {
+        self.state.lock().unwrap().session_priorities.insert(session_id.to_string(), priority);
+    }
+
+    /// Wait for a slot on the subtask's agent.
+    pub async fn acquire(self: &Arc<Self>, subtask: QueuedSubtask) -> SchedulerPermit {
+        let agent = subtask.agent.clone();
+        let (grant, granted) = oneshot::channel();
+        let sequence = {
+            let mut state = self.state.lock().unwrap();
+            let queue_empty = !state.waiting.iter().any(|waiter| waiter.subtask.agent == agent);
+            if queue_empty && state.has_free_slot(&agent) {
+                *state.running.entry(agent.clone()).or_default() += 1;
+                return SchedulerPermit { scheduler: self.clone(), agent };
+            }
+
+            let sequence = state.next_sequence;
+            state.next_sequence += 1;
+            state.waiting.push(Waiter { subtask, sequence, grant });
+            sequence
+        };
+
+        let mut pending = PendingGrant { scheduler: self.clone(), agent: agent.clone(), sequence, armed: true };
+        // The sender lives in the queue until it grants, so this only returns once granted
+        let _ = granted.await;
+        pending.armed = false;
+        SchedulerPermit { scheduler: self.clone(), agent }
+    }
+
+    /// The agents' load and every waiting subtask, in the order they will be started.
+    pub fn snapshot(&self) -> QueueSnapshot {
+        let state = self.state.lock().unwrap();
+        let now = Utc::now();
+
+        let mut waiting: Vec<QueueEntry> = state
+            .waiting
+            .iter()
+            .map(|waiter| {
+                let session_priority = state.session_priority(&waiter.subtask.session_id);
+                QueueEntry {
+                    effective_priority: self.priority_of(&state, waiter, now),
+                    subtask: waiter.subtask.clone(),
+                    session_priority,
+                    position: 0,
+                }
+            })
+            .collect();
+        waiting.sort_by(|a, b| a.effective_priority.total_cmp(&b.effective_priority));
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for entry in &mut waiting {
+            let position = positions.entry(entry.subtask.agent.clone()).or_default();
+            *position += 1;
+            entry.position = *position;
+        }
+
+        let mut agents: Vec<String> = state
+            .capacities
+            .keys()
+            .chain(state.running.keys())
+            .chain(state.waiting.iter().map(|waiter| &waiter.subtask.agent))
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        agents.sort();
+
+        QueueSnapshot {
+            agents: agents
+                .into_iter()
+                .map(|agent| AgentLoad {
+                    capacity: state.capacity(&agent),
+                    running: state.running.get(&agent).copied().unwrap_or(0),
+                    waiting: positions.get(&agent).copied().unwrap_or(0),
+                    agent,
+                })
+                .collect(),
+            waiting,
+        }
+    }
+
+    fn release(&self, agent: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(running) = state.running.get_mut(agent) {
+            *running = running.saturating_sub(1);
+        }
+        self.grant_free_slots(&mut state, agent);
+    }
+
+    fn grant_free_slots(&self, state: &mut SchedulerState, agent: &str) {
+        let now = Utc::now();
+        while state.has_free_slot(agent) {
+            let next = state
+                .waiting
+                .iter()
+                .enumerate()
+                .filter(|(_, waiter)| waiter.subtask.agent == agent)
+                .min_by(|(_, a), (_, b)| {
+                    self.priority_of(state, a, now)
+                        .total_cmp(&self.priority_of(state, b, now))
+                        .then(a.sequence.cmp(&b.sequence))
+                })
+                .map(|(index, _)| index);
+            let Some(index) = next else {
+                break;
+            };
+
+            let waiter = state.waiting.remove(index);
+            if waiter.grant.send(()).is_ok() {
+                *state.running.entry(agent.to_string()).or_default() += 1;
+            }
+        }
+    }
+
+    fn priority_of(&self, state: &SchedulerState, waiter: &Waiter, now: DateTime<Utc>) -> f64 {
+        let waited = (now - waiter.subtask.enqueued_at).to_std().unwrap_or_default();
+        effective_priority(
+            &waiter.subtask.priority,
+            &state.session_priority(&waiter.subtask.session_id),
+            waited,
+            self.aging,
+        )
+    }
+}
+
+impl SchedulerState {
+    fn capacity(&self, agent: &str) -> usize {
+        self.capacities.get(agent).copied().unwrap_or(DEFAULT_CAPACITY).max(1)
+    }
+
+    fn has_free_slot(&self, agent: &str) -> bool {
+        self.running.get(agent).copied().unwrap_or(0) < self.capacity(agent)
+    }
+
+    fn session_priority(&self, session_id: &str) -> SessionPriority {
+        self.session_priorities.get(session_id).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued(session_id: &str, subtask_id: &str, priority: &str, waited_secs: i64) -> QueuedSubtask {
+        QueuedSubtask {
+            session_id: session_id.to_string(),
+            subtask_id: subtask_id.to_string(),
+            description: format!("task {}", subtask_id),
+            agent: "claude_code".to_string(),
+            priority: priority.to_string(),
+            enqueued_at: Utc::now() - chrono::Duration::seconds(waited_secs),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_free_slots_go_to_the_highest_priority() {
+        let scheduler = Arc::new(Scheduler::new(Duration::from_secs(60)));
+        scheduler.set_session_priority("urgent", SessionPriority::High);
+        let busy = scheduler.acquire(queued("background", "0", "high", 0)).await;
+
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let mut waiters = tokio::task::JoinSet::new();
+        for subtask in [
+            queued("background", "low", "low", 0),
+            // Waiting three minutes makes up for being low priority
+            queued("background", "aged", "low", 180),
+            queued("urgent", "urgent", "medium", 0),
+        ] {
+            let scheduler = scheduler.clone();
+            let started = started.clone();
+            waiters.spawn(async move {
+                let id = subtask.subtask_id.clone();
+                let _permit = scheduler.acquire(subtask).await;
+                started.lock().unwrap().push(id);
+            });
+            tokio::task::yield_now().await;
+        }
+        while scheduler.snapshot().waiting.len() < 3 {
+            tokio::task::yield_now().await;
+        }
+
+        let snapshot = scheduler.snapshot();
+        let queue: Vec<&str> = snapshot.waiting.iter().map(|entry| entry.subtask.subtask_id.as_str()).collect();
+        assert_eq!(queue, vec!["aged", "urgent", "low"]);
+        assert_eq!(snapshot.agents[0].running, 1);
+        assert_eq!(snapshot.agents[0].waiting, 3);
+
+        drop(busy);
+        while waiters.join_next().await.is_some() {}
+        assert_eq!(*started.lock().unwrap(), vec!["aged", "urgent", "low"]);
+        assert_eq!(scheduler.snapshot().agents[0].running, 0);
+    }
+}
@@ -2,26 +2,72 @@ use crate::models::*;
 use crate::database::get_database;
 use crate::claude_code_adapter::ClaudeCodeAdapter;
 use crate::gemini_cli_adapter::GeminiCliAdapter;
-use crate::middle_manager::MiddleManager;
-use crate::git_worktree_manager::GitWorktreeManager;
+use crate::middle_manager::{DependencyOutput, MiddleManager, PlanningContext, SubTask, TaskDecomposition, KNOWN_AGENTS};
+use crate::plan_preview::PlanPreview;
+use crate::plan_approval::{PendingPlan, PlanEdit};
+use crate::dag_executor::{DagExecutor, NodeState, NodeStatus};
+use crate::scheduler::{QueueSnapshot, QueuedSubtask, Scheduler};
+use crate::session_state::{self, StatusChange};
+use crate::request_runs::{RequestRun, RunProgress, RunRegistry, RunResults};
+use crate::verification::{verify, CommandPolicy};
+use crate::cost_tracker::{parse_agent_usage, sum_usage, with_estimated_cost};
+use crate::prompt_templates;
+use crate::llm_provider::LlmFailure;
+use crate::git_worktree_manager::{DiffStat, GitWorktreeManager};
+use crate::ensemble::{self, CandidateReport, EnsembleCandidate};
+use crate::planner_tools::{RepoTools, ToolCallRecord};
+use crate::repo_map::RepoMapper;
+use crate::context_window::{self, ConversationSummary};
 use std::collections::HashMap;
+use std::future::Future;
 use std::sync::{Arc, RwLock};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use anyhow::Result;
 use uuid::Uuid;
 
+#[derive(Clone)]
 pub struct SessionManager {
     active_sessions: Arc<RwLock<HashMap<String, SessionData>>>,
     claude_adapter: Arc<ClaudeCodeAdapter>,
     gemini_adapter: Arc<GeminiCliAdapter>,
     middle_manager: Arc<MiddleManager>,
     git_worktree_manager: Arc<GitWorktreeManager>,
+    repo_mapper: Arc<RepoMapper>,
+    scheduler: Arc<Scheduler>,
+    runs: Arc<RunRegistry>,
+    max_subtask_attempts: u32,
+    /// Acceptance check commands that may run without the plan being approved.
+    verify_commands: Vec<String>,
+    repo_map_tokens: usize,
+    context_tokens: usize,
+}
+
+/// Where every attempt of one ensemble starts from.
+struct EnsembleBase {
+    project_path: PathBuf,
+    /// The session worktree's checkpoint the attempts branch from.
+    commit: String,
+    run_id: String,
+}
+
+/// An ensemble candidate with what is needed to judge it and record its cost.
+struct EnsembleAttempt {
+    candidate: EnsembleCandidate,
+    diff: String,
+    result: Option<TaskResult>,
 }
 
 struct SessionData {
     session: Session,
     conversation_history: Vec<ConversationMessage>,
     active_tasks: HashMap<String, TaskResult>,
+    plan_status: Vec<NodeState>,
+    budget_warned: bool,
+    pending_plan: Option<PendingPlan>,
+    summary: Option<ConversationSummary>,
+    /// Agents writing to the session worktree share it; ensemble checkpoints and merges
+    /// wait until they have it to themselves.
+    worktree_lock: Arc<tokio::sync::RwLock<()>>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -31,9 +77,25 @@ pub struct ConversationMessage {
     pub role: MessageRole,
     pub content: String,
     pub agent_type: Option<String>,
+    /// Pinned messages are always given to the planner verbatim, however old they are.
+    #[serde(default)]
+    pub pinned: bool,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
+/// What deleting a session did.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SessionDeletion {
+    Deleted,
+    /// Nothing was deleted because work would be lost; delete again confirmed to go ahead.
+    NeedsConfirmation {
+        branch: Option<String>,
+        unmerged_commits: u64,
+        uncommitted_changes: bool,
+    },
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum MessageRole {
     User,
@@ -53,6 +115,37 @@ impl SessionManager {
                     std::env::temp_dir().join("agent-tool-worktrees").to_string_lossy().to_string()
                 }))
             )),
+            max_subtask_attempts: std::env::var("AGENT_TOOL_MAX_SUBTASK_ATTEMPTS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|attempts| *attempts > 0)
+                .unwrap_or(3),
+            verify_commands: std::env::var("AGENT_TOOL_VERIFY_COMMANDS")
+                .ok()
+                .and_then(|commands| {
+                    serde_json::from_str(&commands)
+                        .map_err(|e| eprintln!("Warning: Ignoring AGENT_TOOL_VERIFY_COMMANDS: {}", e))
+                        .ok()
+                })
+                .unwrap_or_default(),
+            repo_mapper: Arc::new(RepoMapper::new()),
+            scheduler: Arc::new(Scheduler::new(std::time::Duration::from_secs(
+                std::env::var("AGENT_TOOL_PRIORITY_AGING_SECS")
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+                    .filter(|secs| *secs > 0)
+                    .unwrap_or(60),
+            ))),
+            runs: Arc::new(RunRegistry::default()),
+            repo_map_tokens: std::env::var("AGENT_TOOL_REPO_MAP_TOKENS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(2000),
+            context_tokens: std::env::var("AGENT_TOOL_CONTEXT_TOKENS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|tokens| *tokens > 0)
+                .unwrap_or(6000),
         }
     }
 
@@ -61,6 +154,9 @@ impl SessionManager {
         name: String,
         project_path: String,
         description: Option<String>,
+        require_plan_approval: bool,
+        tags: Vec<String>,
+        priority: SessionPriority,
     ) -> Result<Session> {
         let session_id = Uuid::new_v4().to_string();
         let project_path_buf = PathBuf::from(&project_path);
@@ -96,16 +192,33 @@ impl SessionManager {
             updated_at: chrono::Utc::now(),
             worktree_path,
             branch_name,
+            require_plan_approval,
+            tags,
+            priority,
+            needs_repair: None,
         };
 
         // Store in database
         get_database().create_session(&session)?;
+        get_database().record_status_change(&StatusChange {
+            session_id: session.id.clone(),
+            from: None,
+            to: session.status.clone(),
+            reason: Some("Session created".to_string()),
+            created_at: session.created_at,
+        })?;
+        self.scheduler.set_session_priority(&session.id, session.priority.clone());
 
         // Add to active sessions
         let session_data = SessionData {
             session: session.clone(),
             conversation_history: Vec::new(),
             active_tasks: HashMap::new(),
+            plan_status: Vec::new(),
+            budget_warned: false,
+            pending_plan: None,
+            summary: None,
+            worktree_lock: Arc::default(),
         };
 
         {
@@ -138,6 +251,90 @@ impl SessionManager {
         sessions.get(session_id).map(|data| data.session.clone())
     }
 
+    /// Load the sessions stored in the database that aren't in memory, as after a restart,
+    /// with their conversation, pending plan and summary. Sessions whose worktree has gone
+    /// missing or moved to another branch are marked as needing repair. Returns how many
+    /// sessions were restored.
+    pub async fn restore_sessions(&self) -> Result<usize> {
+        let mut restored = 0;
+        for mut session in get_database().get_sessions()? {
+            if matches!(session.status, SessionStatus::Archived)
+                || self.active_sessions.read().unwrap().contains_key(&session.id)
+            {
+                continue;
+            }
+
+            let problem = self.worktree_problem(&session);
+            let newly_broken = problem.is_some() && problem != session.needs_repair;
+            if problem != session.needs_repair {
+                get_database().set_session_repair(&session.id, problem.as_deref())?;
+                session.needs_repair = problem;
+            }
+
+            let database = get_database();
+            let session_data = SessionData {
+                conversation_history: database.get_messages(&session.id)?,
+                active_tasks: HashMap::new(),
+                plan_status: Vec::new(),
+                budget_warned: false,
+                pending_plan: database.get_pending_plan(&session.id)?,
+                summary: database.get_conversation_summary(&session.id)?,
+                worktree_lock: Arc::default(),
+                session,
+            };
+            let session_id = session_data.session.id.clone();
+            let repair = session_data.session.needs_repair.clone();
+            self.scheduler.set_session_priority(&session_id, session_data.session.priority.clone());
+            self.active_sessions.write().unwrap().insert(session_id.clone(), session_data);
+            restored += 1;
+
+            if let (true, Some(reason)) = (newly_broken, repair) {
+                self.add_message(
+                    &session_id,
+                    MessageRole::System,
+                    format!("Session needs repair: {}", reason),
+                    None,
+                ).await?;
+            }
+        }
+
+        Ok(restored)
+    }
+
+    /// Why a session's worktree can't be used, if it can't.
+    fn worktree_problem(&self, session: &Session) -> Option<String> {
+        let worktree_path = session.worktree_path.as_ref()?;
+        let worktree = Path::new(worktree_path);
+        if !worktree.exists() {
+            return Some(format!("worktree {} no longer exists", worktree_path));
+        }
+
+        match (self.git_worktree_manager.get_worktree_branch(worktree), &session.branch_name) {
+            (Err(e), _) => Some(format!("worktree {} is not a usable git checkout: {}", worktree_path, e)),
+            (Ok(branch), Some(expected)) if &branch != expected => Some(format!(
+                "worktree {} is on branch '{}' instead of '{}'",
+                worktree_path, branch, expected
+            )),
+            _ => None,
+        }
+    }
+
+    /// The session, provided it can run work.
+    async fn usable_session(&self, session_id: &str) -> Result<Session> {
+        let session = self.get_session(session_id).await
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+        if let Some(reason) = &session.needs_repair {
+            return Err(anyhow::anyhow!("Session {} needs repair: {}", session_id, reason));
+        }
+        match session.status {
+            SessionStatus::Completed | SessionStatus::Archived => {
+                Err(anyhow::anyhow!("Session {} has ended", session_id))
+            }
+            SessionStatus::Paused => Err(anyhow::anyhow!("Session {} is paused; resume it first", session_id)),
+            _ => Ok(session),
+        }
+    }
+
     pub async fn list_sessions(&self) -> Result<Vec<Session>> {
         // Load from database to get all sessions (not just active ones)
         get_database().get_sessions()
@@ -156,6 +353,7 @@ impl SessionManager {
             role,
             content,
             agent_type,
+            pinned: false,
             created_at: chrono::Utc::now(),
         };
 
@@ -167,27 +365,70 @@ impl SessionManager {
                 
                 // Update session timestamp
                 session_data.session.updated_at = chrono::Utc::now();
-                session_data.session.status = SessionStatus::Active;
             }
         }
 
-        // TODO: Store message in database
-        
+        if let Err(e) = get_database().save_message(&message) {
+            eprintln!("Warning: Failed to store message {} for session {}: {}", message.id, session_id, e);
+        }
+
         Ok(message)
     }
 
+    /// Replace the content of a message already in the conversation.
+    pub fn update_message(&self, session_id: &str, message_id: &str, content: String) {
+        if let Err(e) = get_database().update_message_content(message_id, &content) {
+            eprintln!("Warning: Failed to store message {} for session {}: {}", message_id, session_id, e);
+        }
+        self.stream_message(session_id, message_id, content);
+    }
+
+    /// Show partial content of a message as it streams in. Only the conversation in memory
+    /// changes; `update_message` stores the final text.
+    pub fn stream_message(&self, session_id: &str, message_id: &str, content: String) {
+        let mut sessions = self.active_sessions.write().unwrap();
+        if let Some(session_data) = sessions.get_mut(session_id) {
+            if let Some(message) = session_data.conversation_history.iter_mut().find(|m| m.id == message_id) {
+                message.content = content;
+            }
+        }
+    }
+
+    /// The session's conversation. Sessions that aren't loaded, such as those from before
+    /// a restart, are read from the database.
     pub async fn get_conversation_history(&self, session_id: &str) -> Vec<ConversationMessage> {
-        let sessions = self.active_sessions.read().unwrap();
-        sessions
-            .get(session_id)
-            .map(|data| data.conversation_history.clone())
-            .unwrap_or_default()
+        {
+            let sessions = self.active_sessions.read().unwrap();
+            if let Some(data) = sessions.get(session_id) {
+                return data.conversation_history.clone();
+            }
+        }
+
+        get_database().get_messages(session_id).unwrap_or_else(|e| {
+            eprintln!("Warning: Failed to load conversation for session {}: {}", session_id, e);
+            Vec::new()
+        })
     }
 
+    /// Plan and run a user's request. A request that fails marks the session as failed
+    /// until the next one.
     pub async fn execute_user_request(
         &self,
         session_id: &str,
         user_message: String,
+    ) -> Result<Vec<ConversationMessage>> {
+        self.usable_session(session_id).await?;
+        self.transition(session_id, SessionStatus::Active, Some("Request received".to_string()))?;
+
+        let outcome = self.run_user_request(session_id, user_message).await;
+        self.fail_on_error(session_id, &outcome);
+        outcome
+    }
+
+    async fn run_user_request(
+        &self,
+        session_id: &str,
+        user_message: String,
     ) -> Result<Vec<ConversationMessage>> {
         // Add user message to conversation
         self.add_message(
@@ -197,224 +438,1576 @@ impl SessionManager {
             None,
         ).await?;
 
-        let mut responses = Vec::new();
-
-        // Get conversation context
-        let history = self.get_conversation_history(session_id).await;
-        let context = self.build_context_from_history(&history);
-
-        // Use Middle Manager to decompose the task
-        let decomposition = self.middle_manager
-            .process_task(&user_message, &context)
-            .await
-            .map_err(|e| anyhow::anyhow!(e))?;
-
-        // Add middle manager response
-        let reasoning_message = self.add_message(
+        // Use Middle Manager to decompose the task, showing its reasoning as it streams in
+        let first_response_index = self.get_conversation_history(session_id).await.len();
+        let context = self.build_context(session_id).await;
+        let planning_message = self.add_message(
             session_id,
             MessageRole::Assistant,
-            format!(
-                "Task decomposition: {} - {}",
-                decomposition.strategy, decomposition.reasoning
-            ),
+            "Planning...".to_string(),
             Some("middle_manager".to_string()),
         ).await?;
-        responses.push(reasoning_message);
-
-        // Execute subtasks through appropriate agents
-        for subtask in decomposition.subtasks {
-            let task_result = match subtask.agent.as_str() {
-                "claude_code" => {
-                    self.execute_claude_code_task(session_id, &subtask).await?
-                }
-                "gemini_cli" => {
-                    self.execute_gemini_cli_task(session_id, &subtask).await?
-                }
-                "middle_manager" => {
-                    // Handle coordination tasks
-                    TaskResult {
-                        id: Uuid::new_v4().to_string(),
-                        session_id: session_id.to_string(),
-                        task_description: subtask.description.clone(),
-                        agent_type: "middle_manager".to_string(),
-                        status: TaskStatus::Completed,
-                        result: Some("Coordination task handled by middle manager".to_string()),
-                        error: None,
-                        created_at: chrono::Utc::now(),
-                        completed_at: Some(chrono::Utc::now()),
-                    }
-                }
-                _ => {
-                    return Err(anyhow::anyhow!("Unknown agent type: {}", subtask.agent));
-                }
-            };
-
-            // Store task result
-            {
-                let mut sessions = self.active_sessions.write().unwrap();
-                if let Some(session_data) = sessions.get_mut(session_id) {
-                    session_data.active_tasks.insert(task_result.id.clone(), task_result.clone());
-                }
-            }
-
-            // Add agent response to conversation
-            let content = if let Some(result) = &task_result.result {
-                format!("Task completed: {}", result)
-            } else if let Some(error) = &task_result.error {
-                format!("Task failed: {}", error)
-            } else {
-                "Task completed with no output".to_string()
-            };
+        let decomposition = self
+            .plan_request(session_id, &user_message, context, &|partial| {
+                self.stream_message(session_id, &planning_message.id, partial.to_string());
+            })
+            .await?;
+        for rule_match in &decomposition.rule_matches {
+            self.add_message(
+                session_id,
+                MessageRole::System,
+                format!("Routing rule '{}' matched: {}", rule_match.rule, rule_match.reasons.join("; ")),
+                Some("middle_manager".to_string()),
+            ).await?;
+        }
+        self.record_llm_calls(session_id, &decomposition.llm_calls);
+        self.record_tool_calls(session_id, &decomposition.tool_calls);
+        for failure in &decomposition.llm_failures {
+            self.report_llm_failure(session_id, failure).await;
+        }
+        if decomposition.fallback {
+            self.add_message(
+                session_id,
+                MessageRole::System,
+                "No usable task plan was produced; delegating the request to a single agent as is".to_string(),
+                Some("middle_manager".to_string()),
+            ).await?;
+        }
 
-            let agent_message = self.add_message(
+        if !decomposition.repair_attempts.is_empty() {
+            let attempts = decomposition
+                .repair_attempts
+                .iter()
+                .map(|attempt| {
+                    format!(
+                        "- Attempt {} by {} ({}): {}",
+                        attempt.attempt,
+                        attempt.model,
+                        if attempt.succeeded { "passed" } else { "still invalid" },
+                        attempt.errors.join("; ")
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            self.add_message(
                 session_id,
-                MessageRole::Assistant,
-                content,
-                Some(task_result.agent_type.clone()),
+                MessageRole::System,
+                format!(
+                    "Task plan needed {} repair attempt(s):\n{}",
+                    decomposition.repair_attempts.len(),
+                    attempts
+                ),
+                Some("middle_manager".to_string()),
             ).await?;
-            responses.push(agent_message);
         }
 
-        Ok(responses)
+        // Replace the streamed output with the validated plan's summary
+        self.update_message(
+            session_id,
+            &planning_message.id,
+            format!(
+                "Task decomposition{}: {} - {}",
+                decomposition.planner_model.as_ref().map(|model| format!(" by {}", model)).unwrap_or_default(),
+                decomposition.strategy,
+                decomposition.reasoning
+            ),
+        );
+
+        // Sessions in approval mode stop here until the user approves the plan
+        let require_approval = self.get_session(session_id).await
+            .map(|session| session.require_plan_approval)
+            .unwrap_or(false);
+        if require_approval {
+            self.hold_plan_for_approval(session_id, user_message, decomposition).await?;
+        } else {
+            self.execute_plan(session_id, &user_message, decomposition, false).await?;
+        }
+
+        // Everything added to the conversation since the user's message is the response
+        let mut responses = self.get_conversation_history(session_id).await;
+        Ok(responses.split_off(first_response_index.min(responses.len())))
     }
 
-    async fn execute_claude_code_task(
+    /// Decompose a request given the session's conversation context, adding the agents'
+    /// track record, the repository map and the session's tags for routing rules.
+    async fn plan_request(
         &self,
         session_id: &str,
-        subtask: &crate::middle_manager::SubTask,
-    ) -> Result<TaskResult> {
-        // Get session to determine project path and permissions
+        user_message: &str,
+        mut context: String,
+        on_progress: &(dyn Fn(&str) + Send + Sync),
+    ) -> Result<TaskDecomposition> {
+        if let Ok(stats) = crate::agent_registry::get_agent_stats(None).await {
+            let track_record = crate::agent_registry::format_stats_for_planner(&stats);
+            if !track_record.is_empty() {
+                context.push_str("\n\nAgent track record:\n");
+                context.push_str(&track_record);
+            }
+        }
+
+        let session = self.get_session(session_id).await;
+        let project_path = session.as_ref().map(|session| PathBuf::from(&session.project_path));
+        let repo_map = match &session {
+            Some(session) => self.build_repo_map(session).await,
+            None => None,
+        };
+        let tools = match &session {
+            Some(session) => Self::planner_tools(session).await,
+            None => None,
+        };
+        let session_tags = session.map(|session| session.tags).unwrap_or_default();
+        let planning_context = PlanningContext {
+            conversation: &context,
+            repo_map: repo_map.as_deref(),
+            project_path: project_path.as_deref(),
+            session_tags: &session_tags,
+            tools: tools.as_ref(),
+        };
+        self.middle_manager
+            .process_task_streaming(user_message, &planning_context, on_progress)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Plan a request without running it. Nothing is added to the conversation and no agent
+    /// is started; the preview is stored so it can be compared with what later runs.
+    pub async fn preview_user_request(&self, session_id: &str, user_message: String) -> Result<PlanPreview> {
         let session = self.get_session(session_id).await
-            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
 
-        // Use worktree path if available, otherwise use project path
+        // The request is planned as if it had been sent, but the summary is left as it is
+        let mut history = self.get_conversation_history(session_id).await;
+        history.push(ConversationMessage {
+            id: Uuid::new_v4().to_string(),
+            session_id: session_id.to_string(),
+            role: MessageRole::User,
+            content: user_message.clone(),
+            agent_type: None,
+            pinned: false,
+            created_at: chrono::Utc::now(),
+        });
+        let summary = self.get_conversation_summary(session_id).await;
+        let context = context_window::build_context(&history, summary.as_ref(), self.context_tokens);
+
+        // Planning itself did cost money; the stored preview carries it into the usage summary
+        let decomposition = self.plan_request(session_id, &user_message, context, &|_| {}).await?;
+        let preview = self.build_preview(&session, &user_message, decomposition).await;
+        get_database().save_plan_preview(&preview)?;
+        Ok(preview)
+    }
+
+    /// Preview handing a task straight to one agent.
+    pub async fn preview_direct_task(&self, session_id: &str, task_description: &str, agent: &str) -> Result<PlanPreview> {
+        let session = self.get_session(session_id).await
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+        if !KNOWN_AGENTS.contains(&agent) {
+            return Err(anyhow::anyhow!("Unknown agent type: {}", agent));
+        }
+
+        let decomposition = TaskDecomposition {
+            strategy: "direct".to_string(),
+            reasoning: format!("Task sent directly to {}", agent),
+            subtasks: vec![SubTask {
+                id: Uuid::new_v4().to_string(),
+                description: task_description.to_string(),
+                agent: agent.to_string(),
+                priority: "high".to_string(),
+                category: None,
+                dependencies: vec![],
+                acceptance_criteria: vec![],
+                ensemble: vec![],
+            }],
+            repair_attempts: vec![],
+            llm_calls: vec![],
+            prompt_templates: vec![],
+            llm_failures: vec![],
+            fallback: false,
+            planner_model: None,
+            rule_matches: vec![],
+            tool_calls: vec![],
+        };
+
+        let preview = self.build_preview(&session, task_description, decomposition).await;
+        get_database().save_plan_preview(&preview)?;
+        Ok(preview)
+    }
+
+    async fn build_preview(&self, session: &Session, request: &str, decomposition: TaskDecomposition) -> PlanPreview {
+        let stats = crate::agent_registry::get_agent_stats(None).await.unwrap_or_default();
         let working_path = session.worktree_path.as_ref().unwrap_or(&session.project_path);
+        PlanPreview::new(&session.id, request, decomposition, &stats, |agent| {
+            Self::subtask_permissions(agent, working_path)
+        })
+    }
+
+    /// Permissions an agent is started with for a subtask in `working_path`. The middle
+    /// manager handles its subtasks itself and starts nothing.
+    fn subtask_permissions(agent: &str, working_path: &str) -> Option<AgentPermissions> {
+        match agent {
+            "claude_code" => Some(Self::claude_code_permissions(working_path)),
+            "gemini_cli" => Some(GeminiCliAdapter::quick_task_permissions(working_path)),
+            _ => None,
+        }
+    }
 
-        let permissions = AgentPermissions {
+    fn claude_code_permissions(working_path: &str) -> AgentPermissions {
+        AgentPermissions {
             file_read: true,
             file_write: true,
             network_access: true,
             process_spawn: true,
-            allowed_paths: vec![working_path.clone(), "**".to_string()],
+            allowed_paths: vec![working_path.to_string(), "**".to_string()],
+        }
+    }
+
+    /// Run a plan's subtasks and reply to the user with the results.
+    ///
+    /// `plan_approved` is set when a person approved the plan, which lets its acceptance
+    /// checks run commands that aren't on the allowlist.
+    async fn execute_plan(
+        &self,
+        session_id: &str,
+        user_message: &str,
+        decomposition: TaskDecomposition,
+        plan_approved: bool,
+    ) -> Result<()> {
+        // Execute subtasks through appropriate agents, running independent ones concurrently
+        self.set_plan_status(session_id, Vec::new());
+        self.scheduler.set_capacities(crate::agent_registry::get_agent_capacities().await);
+        let plan_metadata = TaskMetadata {
+            prompt_templates: decomposition.prompt_templates.clone(),
+            planner_model: decomposition.planner_model.clone(),
+            rule_matches: decomposition.rule_matches.clone(),
+            ..TaskMetadata::default()
         };
+        let outcome = DagExecutor::new(self.scheduler.clone(), session_id)
+            .run(
+                decomposition.subtasks.clone(),
+                |subtask, dependency_results| {
+                    let manager = self.clone();
+                    let session_id = session_id.to_string();
+                    let plan_metadata = plan_metadata.clone();
+                    async move {
+                        manager.run_subtask(&session_id, subtask, dependency_results, plan_metadata, plan_approved).await
+                    }
+                },
+                |node| self.record_node_status(session_id, node),
+            )
+            .await?;
+        self.set_plan_status(session_id, outcome.nodes.clone());
 
-        // Start Claude Code session if not already running
-        let claude_session_id = format!("{}-claude", session_id);
-        if self.claude_adapter.get_session_status(&claude_session_id).is_none() {
-            self.claude_adapter
-                .start_session(
-                    claude_session_id.clone(),
-                    working_path.clone(),
-                    permissions,
-                )
-                .await?;
+        // Answer the user's request from everything the subtasks produced
+        let summary_message = self.add_message(
+            session_id,
+            MessageRole::Assistant,
+            "Summarising results...".to_string(),
+            Some("middle_manager".to_string()),
+        ).await?;
+        let project_path = self.get_session(session_id).await.map(|session| PathBuf::from(session.project_path));
+        let synthesis = self.middle_manager
+            .synthesize_response(user_message, &decomposition, &outcome, project_path.as_deref(), &|partial| {
+                self.stream_message(session_id, &summary_message.id, partial.to_string());
+            })
+            .await;
+        self.update_message(session_id, &summary_message.id, synthesis.reply);
+        self.record_llm_calls(session_id, synthesis.llm_call.as_slice());
+        for failure in &synthesis.llm_failures {
+            self.report_llm_failure(session_id, failure).await;
         }
 
-        // Execute the task
-        self.claude_adapter
-            .execute_task(&claude_session_id, &subtask.description, None)
-            .await
+        Ok(())
     }
 
-    async fn execute_gemini_cli_task(
-        &self,
-        session_id: &str,
-        subtask: &crate::middle_manager::SubTask,
-    ) -> Result<TaskResult> {
-        // Get session to determine project path
-        let session = self.get_session(session_id).await
-            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+    /// Store a plan as the session's pending plan and show it to the user for review.
+    async fn hold_plan_for_approval(&self, session_id: &str, request: String, decomposition: TaskDecomposition) -> Result<()> {
+        let plan = PendingPlan {
+            session_id: session_id.to_string(),
+            request,
+            decomposition,
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        };
+        let summary = Self::describe_pending_plan(&plan);
+        let replaced = {
+            let mut sessions = self.active_sessions.write().unwrap();
+            let session_data = sessions
+                .get_mut(session_id)
+                .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+            session_data.pending_plan.replace(plan.clone()).is_some()
+        };
+        if let Err(e) = get_database().save_pending_plan(&plan) {
+            eprintln!("Warning: Failed to store pending plan for session {}: {}", session_id, e);
+        }
 
-        // Use worktree path if available, otherwise use project path
-        let working_path = session.worktree_path.as_ref().unwrap_or(&session.project_path);
+        self.add_message(
+            session_id,
+            MessageRole::System,
+            format!(
+                "{}Plan awaiting approval:\n{}",
+                if replaced { "The previous pending plan was discarded. " } else { "" },
+                summary
+            ),
+            Some("middle_manager".to_string()),
+        ).await?;
 
-        // Use quick task execution for Gemini CLI
-        self.gemini_adapter
-            .execute_quick_task(&subtask.description, working_path)
-            .await
+        Ok(())
     }
 
-    fn build_context_from_history(&self, history: &[ConversationMessage]) -> String {
-        let recent_messages: Vec<String> = history
+    fn describe_pending_plan(plan: &PendingPlan) -> String {
+        plan.decomposition
+            .subtasks
             .iter()
-            .rev()
-            .take(10) // Last 10 messages for context
-            .rev()
-            .map(|msg| {
-                let role = match msg.role {
-                    MessageRole::User => "User",
-                    MessageRole::Assistant => "Assistant",
-                    MessageRole::System => "System",
+            .map(|subtask| {
+                let dependencies = if subtask.dependencies.is_empty() {
+                    String::new()
+                } else {
+                    format!(", after {}", subtask.dependencies.join(", "))
                 };
-                let agent_info = msg.agent_type
-                    .as_ref()
-                    .map(|a| format!(" ({})", a))
-                    .unwrap_or_default();
-                format!("{}{}: {}", role, agent_info, msg.content)
+                format!(
+                    "- [{}] {} ({}, {} priority{})",
+                    subtask.id, subtask.description, subtask.agent, subtask.priority, dependencies
+                )
             })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub async fn get_pending_plan(&self, session_id: &str) -> Option<PendingPlan> {
+        let sessions = self.active_sessions.read().unwrap();
+        sessions.get(session_id).and_then(|data| data.pending_plan.clone())
+    }
+
+    /// Change a subtask of the pending plan. Edits that would leave the plan invalid are refused.
+    pub async fn edit_pending_plan(&self, session_id: &str, edit: PlanEdit) -> Result<PendingPlan> {
+        let plan = {
+            let mut sessions = self.active_sessions.write().unwrap();
+            let plan = sessions
+                .get_mut(session_id)
+                .and_then(|data| data.pending_plan.as_mut())
+                .ok_or_else(|| anyhow::anyhow!("Session {} has no plan awaiting approval", session_id))?;
+            plan.apply(edit).map_err(|e| anyhow::anyhow!(e))?;
+            plan.clone()
+        };
+
+        if let Err(e) = get_database().save_pending_plan(&plan) {
+            eprintln!("Warning: Failed to store pending plan for session {}: {}", session_id, e);
+        }
+        Ok(plan)
+    }
+
+    /// Execute the pending plan, returning the messages added while it ran.
+    pub async fn approve_plan(&self, session_id: &str) -> Result<Vec<ConversationMessage>> {
+        self.usable_session(session_id).await?;
+        let plan = self.take_pending_plan(session_id)?;
+        let first_response_index = self.get_conversation_history(session_id).await.len();
+
+        self.add_message(
+            session_id,
+            MessageRole::System,
+            format!("Plan approved; running {} subtask(s)", plan.decomposition.subtasks.len()),
+            None,
+        ).await?;
+        self.transition(session_id, SessionStatus::Active, Some("Plan approved".to_string()))?;
+        let outcome = self.execute_plan(session_id, &plan.request, plan.decomposition, true).await;
+        self.fail_on_error(session_id, &outcome);
+        outcome?;
+
+        let mut responses = self.get_conversation_history(session_id).await;
+        Ok(responses.split_off(first_response_index.min(responses.len())))
+    }
+
+    /// Start planning and running a user's request in the background, returning its run at once.
+    pub async fn start_user_request(&self, session_id: &str, user_message: String) -> Result<RequestRun> {
+        self.usable_session(session_id).await?;
+        let run = self.runs.start(session_id, &user_message).map_err(|e| anyhow::anyhow!(e))?;
+
+        let session_id = session_id.to_string();
+        self.spawn_run(&run, move |manager| async move {
+            manager.execute_user_request(&session_id, user_message).await
+        });
+        Ok(run)
+    }
+
+    /// Start executing the pending plan in the background, returning its run at once.
+    pub async fn start_plan_approval(&self, session_id: &str) -> Result<RequestRun> {
+        self.usable_session(session_id).await?;
+        let plan = self.get_pending_plan(session_id).await
+            .ok_or_else(|| anyhow::anyhow!("Session {} has no plan awaiting approval", session_id))?;
+        let run = self.runs.start(session_id, &plan.request).map_err(|e| anyhow::anyhow!(e))?;
+
+        let session_id = session_id.to_string();
+        self.spawn_run(&run, move |manager| async move {
+            manager.approve_plan(&session_id).await
+        });
+        Ok(run)
+    }
+
+    fn spawn_run<F, Fut>(&self, run: &RequestRun, work: F)
+    where
+        F: FnOnce(SessionManager) -> Fut,
+        Fut: Future<Output = Result<Vec<ConversationMessage>>> + Send + 'static,
+    {
+        let runs = self.runs.clone();
+        let run_id = run.id.clone();
+        let work = work(self.clone());
+        tokio::spawn(async move {
+            let outcome = work.await;
+            runs.finish(&run_id, outcome.err().map(|e| e.to_string()));
+        });
+    }
+
+    pub async fn get_run_progress(&self, run_id: &str) -> Result<RunProgress> {
+        let run = self.runs.get(run_id).ok_or_else(|| anyhow::anyhow!("Run not found: {}", run_id))?;
+        let plan = self.get_plan_status(&run.session_id).await;
+        let current_subtasks = plan
+            .iter()
+            .filter(|node| node.status == NodeStatus::Running)
+            .cloned()
             .collect();
 
-        recent_messages.join("\n")
+        Ok(RunProgress { run, current_subtasks, plan })
     }
 
-    pub async fn pause_session(&self, session_id: &str) -> Result<()> {
-        {
+    /// The messages and subtask results a run has produced so far.
+    pub async fn get_run_results(&self, run_id: &str) -> Result<RunResults> {
+        let run = self.runs.get(run_id).ok_or_else(|| anyhow::anyhow!("Run not found: {}", run_id))?;
+        let messages = self
+            .get_conversation_history(&run.session_id)
+            .await
+            .into_iter()
+            .filter(|message| message.created_at >= run.started_at)
+            .collect();
+
+        let mut task_results: Vec<TaskResult> = {
+            let sessions = self.active_sessions.read().unwrap();
+            sessions
+                .get(&run.session_id)
+                .map(|data| {
+                    data.active_tasks
+                        .values()
+                        .filter(|task| task.created_at >= run.started_at)
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        task_results.sort_by_key(|task| task.completed_at.unwrap_or(task.created_at));
+
+        Ok(RunResults {
+            run_id: run.id,
+            status: run.status,
+            messages,
+            task_results,
+        })
+    }
+
+    pub async fn list_runs(&self, session_id: &str) -> Vec<RequestRun> {
+        self.runs.for_session(session_id)
+    }
+
+    pub async fn reject_plan(&self, session_id: &str, reason: Option<String>) -> Result<()> {
+        self.take_pending_plan(session_id)?;
+
+        self.add_message(
+            session_id,
+            MessageRole::System,
+            match reason {
+                Some(reason) => format!("Plan rejected: {}", reason),
+                None => "Plan rejected".to_string(),
+            },
+            None,
+        ).await?;
+
+        Ok(())
+    }
+
+    fn take_pending_plan(&self, session_id: &str) -> Result<PendingPlan> {
+        let plan = {
             let mut sessions = self.active_sessions.write().unwrap();
-            if let Some(session_data) = sessions.get_mut(session_id) {
-                session_data.session.status = SessionStatus::Paused;
-                session_data.session.updated_at = chrono::Utc::now();
-            }
+            sessions
+                .get_mut(session_id)
+                .and_then(|data| data.pending_plan.take())
+                .ok_or_else(|| anyhow::anyhow!("Session {} has no plan awaiting approval", session_id))?
+        };
+
+        if let Err(e) = get_database().delete_pending_plan(session_id) {
+            eprintln!("Warning: Failed to remove pending plan for session {}: {}", session_id, e);
         }
+        Ok(plan)
+    }
 
-        // Stop any running agent processes for this session
-        let claude_session_id = format!("{}-claude", session_id);
-        let _ = self.claude_adapter.stop_session(&claude_session_id).await;
+    pub async fn set_plan_approval(&self, session_id: &str, required: bool) -> Result<()> {
+        {
+            let mut sessions = self.active_sessions.write().unwrap();
+            let session_data = sessions
+                .get_mut(session_id)
+                .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+            session_data.session.require_plan_approval = required;
+        }
 
+        get_database().set_plan_approval(session_id, required)?;
         Ok(())
     }
 
-    pub async fn resume_session(&self, session_id: &str) -> Result<()> {
+    /// Replace the tags routing rules see for a session.
+    pub async fn set_session_tags(&self, session_id: &str, tags: Vec<String>) -> Result<()> {
         {
             let mut sessions = self.active_sessions.write().unwrap();
-            if let Some(session_data) = sessions.get_mut(session_id) {
-                session_data.session.status = SessionStatus::Active;
-                session_data.session.updated_at = chrono::Utc::now();
-            }
+            let session_data = sessions
+                .get_mut(session_id)
+                .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+            session_data.session.tags = tags.clone();
         }
+
+        get_database().set_session_tags(session_id, &tags)?;
         Ok(())
     }
 
-    // Commented out unused methods to remove dead code warnings
-    // pub async fn complete_session(&self, session_id: &str) -> Result<()> {
-    //     let worktree_path = {
-    //         let mut sessions = self.active_sessions.write().unwrap();
-    //         if let Some(session_data) = sessions.get_mut(session_id) {
-    //             session_data.session.status = SessionStatus::Completed;
-    //             session_data.session.updated_at = chrono::Utc::now();
-    //             session_data.session.worktree_path.clone()
-    //         } else {
-    //             None
-    //         }
-    //     };
+    /// Set how a session's subtasks rank against other sessions' for busy agents. Takes
+    /// effect for subtasks already waiting too.
+    pub async fn set_session_priority(&self, session_id: &str, priority: SessionPriority) -> Result<()> {
+        {
+            let mut sessions = self.active_sessions.write().unwrap();
+            let session_data = sessions
+                .get_mut(session_id)
+                .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+            session_data.session.priority = priority.clone();
+        }
 
-    //     // Cleanup agent sessions
-    //     let claude_session_id = format!("{}-claude", session_id);
-    //     let _ = self.claude_adapter.stop_session(&claude_session_id).await;
-
-    //     // Clean up git worktree if it exists
-    //     if let Some(worktree_path_str) = worktree_path {
-    //         let worktree_path = PathBuf::from(&worktree_path_str);
-    //         if let Some(session_data) = self.get_session(session_id).await {
-    //             let project_path = PathBuf::from(&session_data.project_path);
-    //             if let Err(e) = self.git_worktree_manager.remove_worktree(&project_path, &worktree_path).await {
-    //                 eprintln!("Warning: Failed to remove git worktree for session {}: {}", session_id, e);
-    //             }
-    //         }
-    //     }
+        self.scheduler.set_session_priority(session_id, priority.clone());
+        get_database().set_session_priority(session_id, &priority)?;
+        Ok(())
+    }
 
-    //     Ok(())
-    // }
+    /// Agent load and the subtasks waiting for an agent, across all sessions.
+    pub async fn get_scheduler_queue(&self) -> QueueSnapshot {
+        self.scheduler.set_capacities(crate::agent_registry::get_agent_capacities().await);
+        self.scheduler.snapshot()
+    }
+
+    /// Execute a single subtask, record its result and post it to the conversation.
+    ///
+    /// When the subtask has acceptance criteria they are checked in the session worktree
+    /// after each attempt, and the subtask is re-delegated with the failing output until the
+    /// checks pass or the attempt limit is reached.
+    /// `plan_metadata` describes how the plan was produced (its prompt templates, model and
+    /// matching routing rules) and is recorded on the task alongside what the agent itself
+    /// reported.
+    async fn run_subtask(
+        &self,
+        session_id: &str,
+        subtask: SubTask,
+        dependency_results: Vec<TaskResult>,
+        plan_metadata: TaskMetadata,
+        plan_approved: bool,
+    ) -> TaskResult {
+        let started_at = chrono::Utc::now();
 
+        let (mut task_result, attempts) = match self.may_start_work(session_id).await {
+            Ok(()) => self.execute_with_verification(session_id, &subtask, &dependency_results, plan_approved).await,
+            Err(reason) => (
+                Self::unsuccessful_task_result(session_id, &subtask, TaskStatus::Cancelled, reason),
+                0,
+            ),
+        };
+
+        task_result.task_description = subtask.description.clone();
+        task_result.metadata.retries = attempts.saturating_sub(1);
+        task_result.metadata.prompt_templates.splice(0..0, plan_metadata.prompt_templates);
+        task_result.metadata.planner_model = plan_metadata.planner_model;
+        task_result.metadata.rule_matches = plan_metadata.rule_matches;
+        task_result.created_at = started_at;
+        task_result.metadata.category = Some(subtask.category());
+
+        // Store task result
+        if let Err(e) = get_database().create_task(&task_result) {
+            eprintln!("Warning: Failed to store task {}: {}", task_result.id, e);
+        }
+        {
+            let mut sessions = self.active_sessions.write().unwrap();
+            if let Some(session_data) = sessions.get_mut(session_id) {
+                session_data.active_tasks.insert(task_result.id.clone(), task_result.clone());
+            }
+        }
+
+        // Add agent response to conversation
+        let content = if let Some(result) = &task_result.result {
+            format!("Task completed: {}", result)
+        } else if let Some(error) = &task_result.error {
+            format!("Task failed: {}", error)
+        } else {
+            "Task completed with no output".to_string()
+        };
+
+        if let Err(e) = self.add_message(
+            session_id,
+            MessageRole::Assistant,
+            content,
+            Some(task_result.agent_type.clone()),
+        ).await {
+            eprintln!("Warning: Failed to record result of task {}: {}", task_result.id, e);
+        }
+
+        task_result
+    }
+
+    /// Run a subtask and check its acceptance criteria in the session worktree, re-delegating
+    /// it with the failing output until the checks pass, the attempt limit is reached or the
+    /// budget runs out. Returns the final result, carrying the usage of every attempt, and the
+    /// number of attempts made.
+    async fn execute_with_verification(
+        &self,
+        session_id: &str,
+        subtask: &SubTask,
+        dependency_results: &[TaskResult],
+        plan_approved: bool,
+    ) -> (TaskResult, u32) {
+        let session = self.get_session(session_id).await;
+        let project_path = session.as_ref().map(|session| PathBuf::from(&session.project_path));
+        let working_path = session.map(|session| session.worktree_path.unwrap_or(session.project_path));
+
+        let mut attempt_subtask = subtask.clone();
+        let mut retry_template = None;
+        let mut attempt = 1;
+        let mut usages: Vec<TokenUsage> = Vec::new();
+        loop {
+            let mut task_result = self
+                .dispatch_subtask(session_id, &attempt_subtask, dependency_results, plan_approved)
+                .await;
+            task_result.metadata.prompt_templates.extend(retry_template.clone());
+            if task_result.agent_type == "middle_manager" && task_result.metadata.model.is_none() {
+                task_result.metadata.model = Some(self.middle_manager.default_model().to_string());
+            }
+            usages.extend(Self::attempt_usage(&task_result));
+            task_result.metadata.usage = (!usages.is_empty()).then(|| sum_usage(&usages));
+
+            let failed = matches!(task_result.status, TaskStatus::Failed | TaskStatus::Cancelled);
+            let working_path = match &working_path {
+                Some(path) if !failed && !subtask.acceptance_criteria.is_empty() => path,
+                _ => return (task_result, attempt),
+            };
+
+            // Other subtasks must not write to the worktree while it is being checked
+            let policy = self.command_policy(&task_result.agent_type, plan_approved).await;
+            let report = {
+                let _worktree = self.worktree_lock(session_id).write_owned().await;
+                verify(&subtask.acceptance_criteria, Path::new(working_path), &policy).await
+            };
+            let skipped: Vec<String> = report
+                .skipped()
+                .map(|check| format!("- {} ({})", check.criterion, check.output))
+                .collect();
+            if attempt == 1 && !skipped.is_empty() {
+                let _ = self.add_message(
+                    session_id,
+                    MessageRole::System,
+                    format!(
+                        "Skipped acceptance check(s) for '{}':\n{}",
+                        subtask.description,
+                        skipped.join("\n")
+                    ),
+                    Some(subtask.agent.clone()),
+                ).await;
+            }
+            if report.passed() {
+                return (task_result, attempt);
+            }
+
+            let failures = report.failure_summary();
+            if attempt >= self.max_subtask_attempts {
+                task_result.status = TaskStatus::Failed;
+                task_result.error = Some(format!(
+                    "Acceptance checks still failing after {} attempt(s):\n{}",
+                    attempt, failures
+                ));
+                return (task_result, attempt);
+            }
+            if let Err(reason) = self.may_start_work(session_id).await {
+                task_result.status = TaskStatus::Failed;
+                task_result.error = Some(format!(
+                    "Acceptance checks still failing after {} attempt(s), not retrying: {}\n{}",
+                    attempt, reason, failures
+                ));
+                return (task_result, attempt);
+            }
+
+            let _ = self.add_message(
+                session_id,
+                MessageRole::System,
+                format!(
+                    "Subtask '{}' did not pass its acceptance checks (attempt {} of {}), retrying:\n{}",
+                    subtask.description, attempt, self.max_subtask_attempts, failures
+                ),
+                Some(subtask.agent.clone()),
+            ).await;
+
+            attempt += 1;
+            let retry_prompt = prompt_templates::render(
+                "subtask_retry",
+                project_path.as_deref(),
+                minijinja::context! { description => &subtask.description, failures => &failures },
+            );
+            attempt_subtask.description = retry_prompt.text;
+            retry_template = Some(retry_prompt.template);
+        }
+    }
+
+    /// Tokens and cost of one attempt at a subtask, as reported by its agent.
+    fn attempt_usage(task_result: &TaskResult) -> Option<TokenUsage> {
+        let usage = task_result
+            .metadata
+            .usage
+            .clone()
+            .or_else(|| task_result.result.as_deref().and_then(parse_agent_usage))?;
+        Some(match &task_result.metadata.model {
+            Some(model) => with_estimated_cost(model, usage),
+            None => usage,
+        })
+    }
+
+    /// Refuse to start more work for a request that has been told to stop or once the
+    /// budget is spent.
+    async fn may_start_work(&self, session_id: &str) -> std::result::Result<(), String> {
+        if let Some(reason) = self.runs.cancellation(session_id) {
+            return Err(format!("Request stopped ({}); not starting new subtasks", reason));
+        }
+        self.enforce_budget(session_id).await
+    }
+
+    /// Refuse to start new work once the session or project budget is spent, warning the
+    /// user once when spending first crosses the warning threshold.
+    async fn enforce_budget(&self, session_id: &str) -> std::result::Result<(), String> {
+        let summary = match get_database().get_usage_summary(session_id) {
+            Ok(Some(summary)) => summary,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                eprintln!("Warning: Failed to check budget for session {}: {}", session_id, e);
+                return Ok(());
+            }
+        };
+
+        let spent = format!(
+            "session ${:.4}, project ${:.4}",
+            summary.session_usage.cost_usd.unwrap_or(0.0),
+            summary.project_usage.cost_usd.unwrap_or(0.0)
+        );
+        match summary.status {
+            BudgetStatus::Ok => Ok(()),
+            BudgetStatus::Exceeded => Err(format!("Budget exceeded ({}); not starting new subtasks", spent)),
+            BudgetStatus::Warning => {
+                let first_warning = {
+                    let mut sessions = self.active_sessions.write().unwrap();
+                    sessions
+                        .get_mut(session_id)
+                        .map(|data| !std::mem::replace(&mut data.budget_warned, true))
+                        .unwrap_or(false)
+                };
+                if first_warning {
+                    let _ = self.add_message(
+                        session_id,
+                        MessageRole::System,
+                        format!("Approaching budget limit ({})", spent),
+                        None,
+                    ).await;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn get_usage_summary(&self, session_id: &str) -> Result<UsageSummary> {
+        get_database()
+            .get_usage_summary(session_id)?
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))
+    }
+
+    async fn report_llm_failure(&self, session_id: &str, failure: &LlmFailure) {
+        let _ = self.add_message(
+            session_id,
+            MessageRole::System,
+            format!(
+                "LLM call for {} with {} failed ({}): {}",
+                failure.purpose, failure.model, failure.kind, failure.message
+            ),
+            Some("middle_manager".to_string()),
+        ).await;
+    }
+
+    fn record_llm_calls(&self, session_id: &str, calls: &[LlmCall]) {
+        for call in calls {
+            if let Err(e) = get_database().record_llm_call(session_id, call) {
+                eprintln!("Warning: Failed to record {} call for session {}: {}", call.purpose, session_id, e);
+            }
+        }
+    }
+
+    fn record_tool_calls(&self, session_id: &str, calls: &[ToolCallRecord]) {
+        for call in calls {
+            if let Err(e) = get_database().record_tool_call(session_id, call) {
+                eprintln!("Warning: Failed to record {} tool call for session {}: {}", call.name, session_id, e);
+            }
+        }
+    }
+
+    fn unsuccessful_task_result(session_id: &str, subtask: &SubTask, status: TaskStatus, error: String) -> TaskResult {
+        TaskResult {
+            id: Uuid::new_v4().to_string(),
+            session_id: session_id.to_string(),
+            task_description: subtask.description.clone(),
+            agent_type: subtask.agent.clone(),
+            status,
+            result: None,
+            error: Some(error),
+            created_at: chrono::Utc::now(),
+            completed_at: Some(chrono::Utc::now()),
+            metadata: TaskMetadata::default(),
+        }
+    }
+
+    /// Hand a subtask to its agent, turning any execution error into a failed result.
+    async fn dispatch_subtask(
+        &self,
+        session_id: &str,
+        subtask: &SubTask,
+        dependency_results: &[TaskResult],
+        plan_approved: bool,
+    ) -> TaskResult {
+        let outcome = if subtask.ensemble.len() > 1 {
+            self.execute_ensemble_task(session_id, subtask, dependency_results, plan_approved).await
+        } else {
+            let _worktree = self.worktree_lock(session_id).read_owned().await;
+            self.dispatch_to_agent(session_id, subtask, dependency_results).await
+        };
+
+        outcome.unwrap_or_else(|e| {
+            Self::unsuccessful_task_result(session_id, subtask, TaskStatus::Failed, e.to_string())
+        })
+    }
+
+    /// Which of the acceptance check commands for `agent`'s work may run.
+    async fn command_policy(&self, agent: &str, plan_approved: bool) -> CommandPolicy {
+        CommandPolicy {
+            process_spawn: crate::agent_registry::get_agent_permissions(agent)
+                .await
+                .map(|permissions| permissions.process_spawn)
+                .unwrap_or(false),
+            plan_approved,
+            allowlist: self.verify_commands.clone(),
+        }
+    }
+
+    fn worktree_lock(&self, session_id: &str) -> Arc<tokio::sync::RwLock<()>> {
+        let sessions = self.active_sessions.read().unwrap();
+        sessions
+            .get(session_id)
+            .map(|data| data.worktree_lock.clone())
+            .unwrap_or_default()
+    }
+
+    async fn dispatch_to_agent(&self, session_id: &str, subtask: &SubTask, dependency_results: &[TaskResult]) -> Result<TaskResult> {
+        match subtask.agent.as_str() {
+            "claude_code" => self.execute_claude_code_task(session_id, subtask).await,
+            "gemini_cli" => self.execute_gemini_cli_task(session_id, subtask).await,
+            "middle_manager" => self.execute_middle_manager_task(session_id, subtask, dependency_results).await,
+            _ => Err(anyhow::anyhow!("Unknown agent type: {}", subtask.agent)),
+        }
+    }
+
+    /// Run a subtask on every agent in its ensemble at once, each in a throwaway worktree
+    /// branched from the session's, then apply the best attempt to the session worktree.
+    /// The other attempts keep their branches and worktrees so they can be inspected.
+    async fn execute_ensemble_task(
+        &self,
+        session_id: &str,
+        subtask: &SubTask,
+        dependency_results: &[TaskResult],
+        plan_approved: bool,
+    ) -> Result<TaskResult> {
+        let session = self.get_session(session_id).await
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+        let Some(worktree_path) = session.worktree_path.clone() else {
+            self.add_message(
+                session_id,
+                MessageRole::System,
+                format!(
+                    "Session has no worktree, so '{}' runs on {} alone rather than as an ensemble",
+                    subtask.description, subtask.agent
+                ),
+                Some("middle_manager".to_string()),
+            ).await?;
+            let _permit = self.scheduler.acquire(QueuedSubtask::new(session_id, subtask, &subtask.agent)).await;
+            return self.dispatch_to_agent(session_id, subtask, dependency_results).await;
+        };
+        let project_path = PathBuf::from(&session.project_path);
+        let worktree = PathBuf::from(&worktree_path);
+
+        // Attempts start from everything the session has finished so far, so the checkpoint
+        // waits for agents still writing to the worktree
+        let commit = {
+            let _worktree = self.worktree_lock(session_id).write_owned().await;
+            self.git_worktree_manager.commit_all(&worktree, "Checkpoint before ensemble subtask")?;
+            self.git_worktree_manager.head_commit(&worktree)?
+        };
+        let base = Arc::new(EnsembleBase {
+            project_path: project_path.clone(),
+            commit,
+            run_id: Uuid::new_v4().simple().to_string()[..8].to_string(),
+        });
+        let mut agents: Vec<String> = Vec::new();
+        for agent in &subtask.ensemble {
+            if !agents.contains(agent) {
+                agents.push(agent.clone());
+            }
+        }
+
+        let mut runs = tokio::task::JoinSet::new();
+        for agent in agents.clone() {
+            let manager = self.clone();
+            let session_id = session_id.to_string();
+            let subtask = subtask.clone();
+            let base = base.clone();
+            let policy = self.command_policy(&agent, plan_approved).await;
+            runs.spawn(async move {
+                manager.run_ensemble_candidate(&session_id, &subtask, &agent, &base, &policy).await
+            });
+        }
+        let mut attempts = Vec::new();
+        while let Some(joined) = runs.join_next().await {
+            match joined {
+                Ok(attempt) => attempts.push(attempt),
+                Err(e) => eprintln!("Warning: Ensemble attempt for '{}' did not finish: {}", subtask.description, e),
+            }
+        }
+        attempts.sort_by_key(|attempt| agents.iter().position(|agent| *agent == attempt.candidate.agent));
+
+        let mut candidates: Vec<EnsembleCandidate> = attempts.iter().map(|attempt| attempt.candidate.clone()).collect();
+        let contenders = ensemble::contenders(&candidates);
+        let judgement = if contenders.len() > 1 {
+            let reports: Vec<CandidateReport> = contenders
+                .iter()
+                .map(|index| CandidateReport::new(&candidates[*index], &attempts[*index].diff))
+                .collect();
+            let judgement = self.middle_manager
+                .judge_ensemble(&subtask.description, &reports, Some(&project_path))
+                .await;
+            self.record_llm_calls(session_id, &judgement.llm_calls);
+            for failure in &judgement.llm_failures {
+                self.report_llm_failure(session_id, failure).await;
+            }
+            Some(judgement)
+        } else {
+            None
+        };
+        let judged = judgement.as_ref().and_then(|judgement| judgement.winner.as_deref());
+        let winner = ensemble::pick_winner(&candidates, judged);
+
+        // Apply the winner, then drop its worktree; its commit now lives on the session branch
+        let applied = match winner {
+            Some(winner) => match &candidates[winner].commit {
+                Some(commit) => {
+                    let _worktree = self.worktree_lock(session_id).write_owned().await;
+                    self.git_worktree_manager.cherry_pick(&worktree, commit)
+                }
+                None => Ok(()),
+            },
+            None => Err(anyhow::anyhow!("No ensemble attempt completed the subtask")),
+        };
+        if let (Some(winner), Ok(())) = (winner, &applied) {
+            candidates[winner].winner = true;
+            if let Some(path) = candidates[winner].worktree_path.take() {
+                if let Err(e) = self.git_worktree_manager.remove_worktree(&project_path, Path::new(&path), false).await {
+                    eprintln!("Warning: Failed to remove ensemble worktree {}: {}", path, e);
+                    candidates[winner].worktree_path = Some(path);
+                }
+            }
+        }
+
+        for (candidate, attempt) in candidates.iter().zip(&attempts) {
+            if let Err(e) = get_database().save_ensemble_candidate(candidate) {
+                eprintln!("Warning: Failed to store ensemble candidate {}: {}", candidate.id, e);
+            }
+            // Losing attempts still cost something, so they are recorded as tasks too
+            if let Some(result) = attempt.result.as_ref().filter(|_| !candidate.winner) {
+                if let Err(e) = get_database().create_task(result) {
+                    eprintln!("Warning: Failed to store task {}: {}", result.id, e);
+                }
+            }
+        }
+
+        let alternates: Vec<&str> = candidates
+            .iter()
+            .filter(|candidate| !candidate.winner && candidate.commit.is_some())
+            .map(|candidate| candidate.branch.as_str())
+            .collect();
+        let verdict = match (winner, &applied) {
+            (Some(winner), Ok(())) => {
+                let reason = judgement
+                    .as_ref()
+                    .and_then(|judgement| judgement.reasoning.clone())
+                    .unwrap_or_else(|| "smallest change that completed and passed its checks".to_string());
+                format!("kept {}'s attempt ({})", candidates[winner].agent, reason)
+            }
+            (_, Err(e)) => format!("no attempt was applied: {}", e),
+            (None, Ok(())) => unreachable!("a missing winner is an error"),
+        };
+        let alternates = if alternates.is_empty() {
+            String::new()
+        } else {
+            format!(". Other attempts remain on branches: {}", alternates.join(", "))
+        };
+        self.add_message(
+            session_id,
+            MessageRole::System,
+            format!("Ensemble for '{}': {}{}", subtask.description, verdict, alternates),
+            Some("middle_manager".to_string()),
+        ).await?;
+
+        applied?;
+        let winner = winner.expect("applied implies a winner");
+        attempts
+            .swap_remove(winner)
+            .result
+            .ok_or_else(|| anyhow::anyhow!("Ensemble winner produced no result"))
+    }
+
+    /// One agent's attempt at an ensemble subtask in its own worktree, committed so it can be
+    /// compared with and applied to the session. The attempt waits for a slot on its agent.
+    async fn run_ensemble_candidate(
+        &self,
+        session_id: &str,
+        subtask: &SubTask,
+        agent: &str,
+        base: &EnsembleBase,
+        policy: &CommandPolicy,
+    ) -> EnsembleAttempt {
+        let _permit = self.scheduler.acquire(QueuedSubtask::new(session_id, subtask, agent)).await;
+        let branch = format!("ensemble/{}/{}-{}", session_id, base.run_id, agent);
+        let mut candidate = EnsembleCandidate {
+            id: Uuid::new_v4().to_string(),
+            session_id: session_id.to_string(),
+            subtask_id: subtask.id.clone(),
+            subtask_description: subtask.description.clone(),
+            agent: agent.to_string(),
+            branch: branch.clone(),
+            worktree_path: None,
+            status: TaskStatus::Failed,
+            output: None,
+            error: None,
+            checks_passed: None,
+            commit: None,
+            diff: DiffStat::default(),
+            winner: false,
+            created_at: chrono::Utc::now(),
+        };
+
+        let worktree = match self.git_worktree_manager
+            .create_scratch_worktree(
+                &base.project_path,
+                &format!("ensemble-{}-{}", base.run_id, agent),
+                &branch,
+                &base.commit,
+            )
+            .await
+        {
+            Ok(worktree) => worktree,
+            Err(e) => {
+                candidate.error = Some(e.to_string());
+                return EnsembleAttempt { candidate, diff: String::new(), result: None };
+            }
+        };
+        let working_path = worktree.to_string_lossy().to_string();
+        candidate.worktree_path = Some(working_path.clone());
+
+        let result = match self.execute_agent_in(agent, &subtask.description, &working_path).await {
+            Ok(result) => result,
+            Err(e) => Self::unsuccessful_task_result(session_id, subtask, TaskStatus::Failed, e.to_string()),
+        };
+        candidate.status = result.status.clone();
+        candidate.output = result.result.clone();
+        candidate.error = result.error.clone();
+        if matches!(result.status, TaskStatus::Completed) && !subtask.acceptance_criteria.is_empty() {
+            candidate.checks_passed = Some(verify(&subtask.acceptance_criteria, &worktree, policy).await.passed());
+        }
+
+        let summary = subtask.description.lines().next().unwrap_or_default();
+        let git = &self.git_worktree_manager;
+        let diff = git
+            .commit_all(&worktree, &format!("{} attempt: {}", agent, summary))
+            .and_then(|commit| Ok((commit, git.diff_stat(&worktree, &base.commit)?, git.diff(&worktree, &base.commit)?)));
+        let diff = match diff {
+            Ok((commit, stat, diff)) => {
+                candidate.commit = commit;
+                candidate.diff = stat;
+                diff
+            }
+            Err(e) => {
+                eprintln!("Warning: Failed to record ensemble attempt by {}: {}", agent, e);
+                String::new()
+            }
+        };
+
+        EnsembleAttempt { candidate, diff, result: Some(result) }
+    }
+
+    /// Run a task on a coding agent in `working_path`, apart from the session's own agent
+    /// processes.
+    async fn execute_agent_in(&self, agent: &str, description: &str, working_path: &str) -> Result<TaskResult> {
+        match agent {
+            "claude_code" => {
+                let claude_session_id = format!("ensemble-{}", Uuid::new_v4());
+                self.claude_adapter
+                    .start_session(
+                        claude_session_id.clone(),
+                        working_path.to_string(),
+                        Self::claude_code_permissions(working_path),
+                    )
+                    .await?;
+                let result = self.claude_adapter.execute_task(&claude_session_id, description, None).await;
+                let _ = self.claude_adapter.stop_session(&claude_session_id).await;
+                result
+            }
+            "gemini_cli" => self.gemini_adapter.execute_quick_task(description, working_path).await,
+            _ => Err(anyhow::anyhow!("{} cannot take part in an ensemble", agent)),
+        }
+    }
+
+    pub async fn get_ensemble_candidates(&self, session_id: &str) -> Result<Vec<EnsembleCandidate>> {
+        get_database().get_ensemble_candidates(session_id)
+    }
+
+    /// Have the middle manager's own LLM do a subtask, given what its dependencies produced.
+    async fn execute_middle_manager_task(
+        &self,
+        session_id: &str,
+        subtask: &SubTask,
+        dependency_results: &[TaskResult],
+    ) -> Result<TaskResult> {
+        let project_path = self.get_session(session_id).await.map(|session| PathBuf::from(session.project_path));
+        let dependencies: Vec<DependencyOutput> = dependency_results.iter().map(DependencyOutput::from).collect();
+        let execution = self.middle_manager
+            .execute_subtask(&subtask.description, &dependencies, project_path.as_deref())
+            .await;
+        for failure in &execution.llm_failures {
+            self.report_llm_failure(session_id, failure).await;
+        }
+
+        let output = execution.output
+            .ok_or_else(|| anyhow::anyhow!("No model could complete the middle manager subtask"))?;
+        Ok(TaskResult {
+            id: Uuid::new_v4().to_string(),
+            session_id: session_id.to_string(),
+            task_description: subtask.description.clone(),
+            agent_type: "middle_manager".to_string(),
+            status: TaskStatus::Completed,
+            result: Some(output),
+            error: None,
+            created_at: chrono::Utc::now(),
+            completed_at: Some(chrono::Utc::now()),
+            metadata: TaskMetadata {
+                model: execution.llm_call.as_ref().map(|call| call.model.clone()),
+                usage: execution.llm_call.map(|call| call.usage),
+                prompt_templates: vec![execution.prompt_template],
+                ..TaskMetadata::default()
+            },
+        })
+    }
+
+    async fn record_node_status(&self, session_id: &str, node: NodeState) {
+        if node.status == NodeStatus::Skipped {
+            let _ = self.add_message(
+                session_id,
+                MessageRole::System,
+                format!("Skipped subtask '{}': a dependency did not complete", node.description),
+                Some(node.agent.clone()),
+            ).await;
+        }
+
+        let mut sessions = self.active_sessions.write().unwrap();
+        if let Some(session_data) = sessions.get_mut(session_id) {
+            match session_data.plan_status.iter_mut().find(|n| n.subtask_id == node.subtask_id) {
+                Some(existing) => *existing = node,
+                None => session_data.plan_status.push(node),
+            }
+        }
+    }
+
+    fn set_plan_status(&self, session_id: &str, nodes: Vec<NodeState>) {
+        let mut sessions = self.active_sessions.write().unwrap();
+        if let Some(session_data) = sessions.get_mut(session_id) {
+            session_data.plan_status = nodes;
+        }
+    }
+
+    pub async fn get_plan_status(&self, session_id: &str) -> Vec<NodeState> {
+        let sessions = self.active_sessions.read().unwrap();
+        sessions
+            .get(session_id)
+            .map(|data| data.plan_status.clone())
+            .unwrap_or_default()
+    }
+
+    async fn execute_claude_code_task(
+        &self,
+        session_id: &str,
+        subtask: &SubTask,
+    ) -> Result<TaskResult> {
+        // Get session to determine project path and permissions
+        let session = self.get_session(session_id).await
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+
+        // Use worktree path if available, otherwise use project path
+        let working_path = session.worktree_path.as_ref().unwrap_or(&session.project_path);
+
+        let permissions = Self::claude_code_permissions(working_path);
+
+        // Start Claude Code session if not already running. Concurrent subtasks may race
+        // to start it, so only fail if no session exists afterwards.
+        let claude_session_id = format!("{}-claude", session_id);
+        if self.claude_adapter.get_session_status(&claude_session_id).is_none() {
+            if let Err(e) = self.claude_adapter
+                .start_session(
+                    claude_session_id.clone(),
+                    working_path.clone(),
+                    permissions,
+                )
+                .await
+            {
+                if self.claude_adapter.get_session_status(&claude_session_id).is_none() {
+                    return Err(e);
+                }
+            }
+        }
+
+        // Execute the task
+        self.claude_adapter
+            .execute_task(&claude_session_id, &subtask.description, None)
+            .await
+    }
+
+    async fn execute_gemini_cli_task(
+        &self,
+        session_id: &str,
+        subtask: &SubTask,
+    ) -> Result<TaskResult> {
+        // Get session to determine project path
+        let session = self.get_session(session_id).await
+            .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
+
+        // Use worktree path if available, otherwise use project path
+        let working_path = session.worktree_path.as_ref().unwrap_or(&session.project_path);
+
+        // Use quick task execution for Gemini CLI
+        self.gemini_adapter
+            .execute_quick_task(&subtask.description, working_path)
+            .await
+    }
+
+    /// Map the session's worktree (or project, without one) for the planner. A zero token
+    /// budget turns the map off.
+    async fn build_repo_map(&self, session: &Session) -> Option<String> {
+        if self.repo_map_tokens == 0 {
+            return None;
+        }
+        let working_path = session.worktree_path.as_ref().unwrap_or(&session.project_path);
+        match self.repo_mapper.repo_map(Path::new(working_path), self.repo_map_tokens).await {
+            Ok(map) if !map.is_empty() => Some(map),
+            Ok(_) => None,
+            Err(e) => {
+                eprintln!("Warning: Failed to build repository map for {}: {}", working_path, e);
+                None
+            }
+        }
+    }
+
+    /// Read-only tools for the planner over the session's working tree, limited by the middle
+    /// manager's permissions. Without a registry the planner may read the working tree only.
+    async fn planner_tools(session: &Session) -> Option<RepoTools> {
+        let working_path = session.worktree_path.as_ref().unwrap_or(&session.project_path);
+        let permissions = crate::agent_registry::get_agent_permissions("middle_manager")
+            .await
+            .unwrap_or_else(|| AgentPermissions {
+                file_read: true,
+                file_write: false,
+                network_access: false,
+                process_spawn: false,
+                allowed_paths: vec![working_path.to_string()],
+            });
+        RepoTools::new(Path::new(working_path), &permissions)
+    }
+
+    /// Build the planner's conversation context within the token budget, first folding any
+    /// turns that no longer fit into the session's rolling summary.
+    async fn build_context(&self, session_id: &str) -> String {
+        let history = self.get_conversation_history(session_id).await;
+        let mut summary = self.get_conversation_summary(session_id).await;
+
+        let range = context_window::messages_to_summarize(&history, summary.as_ref(), self.context_tokens);
+        if !range.is_empty() {
+            let transcript = context_window::summary_transcript(&history, range.clone());
+            let project_path = self.get_session(session_id).await.map(|session| PathBuf::from(session.project_path));
+            let summarization = self.middle_manager
+                .summarize_conversation(
+                    summary.as_ref().map(|summary| summary.summary.as_str()),
+                    &transcript,
+                    context_window::summary_budget(self.context_tokens),
+                    project_path.as_deref(),
+                )
+                .await;
+            self.record_llm_calls(session_id, summarization.llm_call.as_slice());
+            for failure in &summarization.llm_failures {
+                self.report_llm_failure(session_id, failure).await;
+            }
+
+            // Without a new summary the dropped turns are simply left out
+            if let Some(text) = summarization.summary {
+                let updated = ConversationSummary {
+                    session_id: session_id.to_string(),
+                    summary: text,
+                    covered_messages: range.end,
+                    updated_at: chrono::Utc::now(),
+                };
+                if let Err(e) = get_database().save_conversation_summary(&updated) {
+                    eprintln!("Warning: Failed to store conversation summary for session {}: {}", session_id, e);
+                }
+                if let Some(session_data) = self.active_sessions.write().unwrap().get_mut(session_id) {
+                    session_data.summary = Some(updated.clone());
+                }
+                summary = Some(updated);
+            }
+        }
+
+        context_window::build_context(&history, summary.as_ref(), self.context_tokens)
+    }
+
+    pub async fn get_conversation_summary(&self, session_id: &str) -> Option<ConversationSummary> {
+        let sessions = self.active_sessions.read().unwrap();
+        sessions.get(session_id).and_then(|data| data.summary.clone())
+    }
+
+    /// Pin or unpin a message so it always reaches the planner verbatim.
+    pub async fn pin_message(&self, session_id: &str, message_id: &str, pinned: bool) -> Result<()> {
+        let mut sessions = self.active_sessions.write().unwrap();
+        let session_data = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+        let message = session_data
+            .conversation_history
+            .iter_mut()
+            .find(|message| message.id == message_id)
+            .ok_or_else(|| anyhow::anyhow!("Message not found: {}", message_id))?;
+        message.pinned = pinned;
+        drop(sessions);
+
+        get_database().set_message_pinned(message_id, pinned)?;
+        Ok(())
+    }
+
+    /// Pause a session. A request it is running starts no more subtasks; those already
+    /// running finish.
+    pub async fn pause_session(&self, session_id: &str) -> Result<()> {
+        self.transition(session_id, SessionStatus::Paused, Some("Paused by the user".to_string()))?;
+
+        if let Some(run) = self.runs.cancel(session_id, "Session paused") {
+            self.add_message(
+                session_id,
+                MessageRole::System,
+                format!("Stopping request '{}': no further subtasks will start", run.request),
+                None,
+            ).await?;
+        }
+
+        // Stop any running agent processes for this session
+        let claude_session_id = format!("{}-claude", session_id);
+        let _ = self.claude_adapter.stop_session(&claude_session_id).await;
+
+        Ok(())
+    }
+
+    pub async fn resume_session(&self, session_id: &str) -> Result<()> {
+        self.transition(session_id, SessionStatus::Active, Some("Resumed by the user".to_string()))?;
+        Ok(())
+    }
+
+    /// Mark a session as done. Its agents are stopped and its worktree, and those of any
+    /// ensemble attempts, removed; branches are deleted unless `keep_branch` is set.
+    pub async fn complete_session(&self, session_id: &str, keep_branch: bool) -> Result<Session> {
+        self.check_transition(session_id, SessionStatus::Completed)?;
+        self.end_session(session_id, keep_branch).await?;
+        self.add_message(session_id, MessageRole::System, "Session completed".to_string(), None).await?;
+        self.transition(session_id, SessionStatus::Completed, Some("Completed by the user".to_string()))
+    }
+
+    /// Set a session aside: it is cleaned up as on completion and no longer loaded, but its
+    /// conversation can still be read.
+    pub async fn archive_session(&self, session_id: &str, keep_branch: bool) -> Result<Session> {
+        self.check_transition(session_id, SessionStatus::Archived)?;
+        self.end_session(session_id, keep_branch).await?;
+        let session = self.transition(session_id, SessionStatus::Archived, Some("Archived by the user".to_string()))?;
+        self.active_sessions.write().unwrap().remove(session_id);
+        Ok(session)
+    }
+
+    /// Delete a session, its worktree and branches, and everything stored for it. Work that
+    /// would be lost, commits not merged into the main branch or uncommitted changes, is only
+    /// thrown away with `confirmed`.
+    pub async fn delete_session(&self, session_id: &str, confirmed: bool) -> Result<SessionDeletion> {
+        self.ensure_no_active_run(session_id)?;
+        let session = self.get_session(session_id).await
+            .or_else(|| get_database().get_sessions().ok()?.into_iter().find(|session| session.id == session_id))
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+
+        if !confirmed {
+            let project_path = Path::new(&session.project_path);
+            let unmerged_commits = session
+                .branch_name
+                .as_ref()
+                .and_then(|branch| self.git_worktree_manager.unmerged_commits(project_path, branch).ok())
+                .unwrap_or(0);
+            let uncommitted_changes = session
+                .worktree_path
+                .as_ref()
+                .and_then(|path| self.git_worktree_manager.has_uncommitted_changes(Path::new(path)).ok())
+                .unwrap_or(false);
+            if unmerged_commits > 0 || uncommitted_changes {
+                return Ok(SessionDeletion::NeedsConfirmation {
+                    branch: session.branch_name,
+                    unmerged_commits,
+                    uncommitted_changes,
+                });
+            }
+        }
+
+        self.end_session_worktrees(&session, false).await?;
+
+        // Branches kept when the session ended, or left behind by a missing worktree
+        let project_path = Path::new(&session.project_path);
+        let ensemble_branches = get_database()
+            .get_ensemble_candidates(session_id)?
+            .into_iter()
+            .map(|candidate| candidate.branch);
+        for branch in session.branch_name.clone().into_iter().chain(ensemble_branches) {
+            if self.git_worktree_manager.branch_exists(project_path, &branch) {
+                self.git_worktree_manager.delete_branch(project_path, &branch)?;
+            }
+        }
+
+        get_database().delete_session(session_id)?;
+        self.active_sessions.write().unwrap().remove(session_id);
+        Ok(SessionDeletion::Deleted)
+    }
+
+    /// Sessions can't be ended or deleted while a request is still writing to them.
+    fn ensure_no_active_run(&self, session_id: &str) -> Result<()> {
+        match self.runs.active(session_id) {
+            Some(run) => Err(anyhow::anyhow!(
+                "Session {} is still running request {}; pause it and wait for the run to stop first",
+                session_id,
+                run.id
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Stop a session's agents and remove its worktrees, keeping its work on its branch if
+    /// `keep_branch` is set.
+    async fn end_session(&self, session_id: &str, keep_branch: bool) -> Result<()> {
+        self.ensure_no_active_run(session_id)?;
+        let session = self.get_session(session_id).await
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+        self.end_session_worktrees(&session, keep_branch).await?;
+
+        let branch_name = session.branch_name.filter(|_| keep_branch);
+        {
+            let mut sessions = self.active_sessions.write().unwrap();
+            if let Some(session_data) = sessions.get_mut(session_id) {
+                session_data.session.worktree_path = None;
+                session_data.session.branch_name = branch_name.clone();
+                session_data.pending_plan = None;
+            }
+        }
+        get_database().set_session_worktree(session_id, None, branch_name.as_deref())?;
+        if let Err(e) = get_database().delete_pending_plan(session_id) {
+            eprintln!("Warning: Failed to remove pending plan for session {}: {}", session_id, e);
+        }
+
+        Ok(())
+    }
+
+    async fn end_session_worktrees(&self, session: &Session, keep_branch: bool) -> Result<()> {
+        let claude_session_id = format!("{}-claude", session.id);
+        let _ = self.claude_adapter.stop_session(&claude_session_id).await;
+
+        let project_path = PathBuf::from(&session.project_path);
+        if let Some(worktree_path) = &session.worktree_path {
+            let worktree = Path::new(worktree_path);
+            if worktree.exists() {
+                if keep_branch {
+                    self.git_worktree_manager.commit_all(worktree, "Save work before the session ended")?;
+                }
+                self.git_worktree_manager.remove_worktree(&project_path, worktree, keep_branch).await?;
+            }
+        }
+
+        // Ensemble attempts that lost keep their worktrees until the session ends
+        for mut candidate in get_database().get_ensemble_candidates(&session.id)? {
+            let Some(worktree_path) = candidate.worktree_path.take() else {
+                continue;
+            };
+            if let Err(e) = self.git_worktree_manager
+                .remove_worktree(&project_path, Path::new(&worktree_path), keep_branch)
+                .await
+            {
+                eprintln!("Warning: Failed to remove ensemble worktree {}: {}", worktree_path, e);
+                continue;
+            }
+            if let Err(e) = get_database().save_ensemble_candidate(&candidate) {
+                eprintln!("Warning: Failed to store ensemble candidate {}: {}", candidate.id, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Move a session to `status` if its transition table allows it, recording the move in
+    /// its status history. Moving to the status it is already in does nothing.
+    fn transition(&self, session_id: &str, status: SessionStatus, reason: Option<String>) -> Result<Session> {
+        let mut sessions = self.active_sessions.write().unwrap();
+        let session_data = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+        if let Some(change) = session_state::transition(session_id, &session_data.session.status, status, reason)? {
+            get_database().record_status_change(&change)?;
+            session_data.session.status = change.to;
+            session_data.session.updated_at = change.created_at;
+        }
+
+        Ok(session_data.session.clone())
+    }
+
+    /// Fail early, before anything is cleaned up, when a session can't move to `status`.
+    fn check_transition(&self, session_id: &str, status: SessionStatus) -> Result<()> {
+        let sessions = self.active_sessions.read().unwrap();
+        let session_data = sessions
+            .get(session_id)
+            .ok_or_else(|| anyhow::anyhow!("Session not found: {}", session_id))?;
+        session_state::transition(session_id, &session_data.session.status, status, None)?;
+        Ok(())
+    }
+
+    fn fail_on_error<T>(&self, session_id: &str, outcome: &Result<T>) {
+        if let Err(e) = outcome {
+            if let Err(transition_error) = self.transition(session_id, SessionStatus::Failed, Some(e.to_string())) {
+                eprintln!("Warning: Failed to mark session {} as failed: {}", session_id, transition_error);
+            }
+        }
+    }
+
+    /// Every status the session has been in, oldest first.
+    pub async fn get_status_history(&self, session_id: &str) -> Result<Vec<StatusChange>> {
+        get_database().get_status_history(session_id)
+    }
+
+    // Commented out unused methods to remove dead code warnings
     // pub async fn get_session_tasks(&self, session_id: &str) -> Vec<TaskResult> {
     //     let sessions = self.active_sessions.read().unwrap();
     //     sessions
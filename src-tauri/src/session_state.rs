@@ -0,0 +1,107 @@
+use crate::models::SessionStatus;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Every move a session may make between statuses. Staying in the same status is always
+/// allowed and isn't a transition.
+const TRANSITIONS: &[(SessionStatus, &[SessionStatus])] = &[
+    (
+        SessionStatus::Created,
+        &[SessionStatus::Active, SessionStatus::Paused, SessionStatus::Completed, SessionStatus::Failed, SessionStatus::Archived],
+    ),
+    (
+        SessionStatus::Active,
+        &[SessionStatus::Paused, SessionStatus::Completed, SessionStatus::Failed, SessionStatus::Archived],
+    ),
+    (
+        SessionStatus::Paused,
+        &[SessionStatus::Active, SessionStatus::Completed, SessionStatus::Failed, SessionStatus::Archived],
+    ),
+    (SessionStatus::Failed, &[SessionStatus::Active, SessionStatus::Completed, SessionStatus::Archived]),
+    (SessionStatus::Completed, &[SessionStatus::Archived]),
+    (SessionStatus::Archived, &[]),
+];
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum SessionTransitionError {
+    #[error("session {session_id} cannot go from {from:?} to {to:?}")]
+    NotAllowed {
+        session_id: String,
+        from: SessionStatus,
+        to: SessionStatus,
+    },
+}
+
+/// One entry in a session's status history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusChange {
+    pub session_id: String,
+    /// `None` for the status a session was created with.
+    pub from: Option<SessionStatus>,
+    pub to: SessionStatus,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Statuses a session may move to from `from`.
+pub fn allowed_transitions(from: &SessionStatus) -> &'static [SessionStatus] {
+    TRANSITIONS
+        .iter()
+        .find(|(status, _)| status == from)
+        .map(|(_, allowed)| *allowed)
+        .unwrap_or(&[])
+}
+
+/// Check a move between statuses, returning the change to record, or `None` when the
+/// session is already in `to`.
+pub fn transition(
+    session_id: &str,
+    from: &SessionStatus,
+    to: SessionStatus,
+    reason: Option<String>,
+) -> Result<Option<StatusChange>, SessionTransitionError> {
+    if *from == to {
+        return Ok(None);
+    }
+    if !allowed_transitions(from).contains(&to) {
+        return Err(SessionTransitionError::NotAllowed {
+            session_id: session_id.to_string(),
+            from: from.clone(),
+            to,
+        });
+    }
+
+    Ok(Some(StatusChange {
+        session_id: session_id.to_string(),
+        from: Some(from.clone()),
+        to,
+        reason,
+        created_at: Utc::now(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions() {
+        let change = transition("s", &SessionStatus::Created, SessionStatus::Active, None).unwrap().unwrap();
+        assert_eq!(change.from, Some(SessionStatus::Created));
+        assert_eq!(change.to, SessionStatus::Active);
+
+        assert!(transition("s", &SessionStatus::Paused, SessionStatus::Paused, None).unwrap().is_none());
+        assert!(transition("s", &SessionStatus::Paused, SessionStatus::Active, None).is_ok());
+        assert!(transition("s", &SessionStatus::Completed, SessionStatus::Archived, None).is_ok());
+
+        assert_eq!(
+            transition("s", &SessionStatus::Completed, SessionStatus::Active, None).unwrap_err(),
+            SessionTransitionError::NotAllowed {
+                session_id: "s".to_string(),
+                from: SessionStatus::Completed,
+                to: SessionStatus::Active,
+            }
+        );
+        assert!(transition("s", &SessionStatus::Archived, SessionStatus::Active, None).is_err());
+    }
+}
@@ -0,0 +1,285 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+/// A check that must pass in the session worktree before a subtask counts as done.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AcceptanceCriterion {
+    /// A shell command that must exit successfully, e.g. `cargo test -p foo`.
+    Command { command: String },
+    /// A file, relative to the worktree, that must contain the given text.
+    FileContains { path: String, pattern: String },
+    /// A file, relative to the worktree, that must exist.
+    FileExists { path: String },
+}
+
+impl std::fmt::Display for AcceptanceCriterion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AcceptanceCriterion::Command { command } => write!(f, "`{}` succeeds", command),
+            AcceptanceCriterion::FileContains { path, pattern } => write!(f, "{} contains \"{}\"", path, pattern),
+            AcceptanceCriterion::FileExists { path } => write!(f, "{} exists", path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckResult {
+    pub criterion: AcceptanceCriterion,
+    pub passed: bool,
+    /// Set for commands that weren't allowed to run. They don't fail the subtask.
+    #[serde(default)]
+    pub skipped: bool,
+    pub output: String,
+}
+
+/// Whether the shell commands among a subtask's acceptance criteria may run. The planner
+/// writes them, so they only run for agents allowed to spawn processes, and only when a
+/// person approved the plan or the command is on the allowlist.
+#[derive(Debug, Clone, Default)]
+pub struct CommandPolicy {
+    pub process_spawn: bool,
+    pub plan_approved: bool,
+    /// Commands that may run without approval. An entry also allows the command followed by
+    /// further arguments, as long as they contain no shell syntax.
+    pub allowlist: Vec<String>,
+}
+
+impl CommandPolicy {
+    /// Why `command` may not run, if it may not.
+    pub fn refusal(&self, command: &str) -> Option<String> {
+        if !self.process_spawn {
+            return Some("the agent is not allowed to spawn processes".to_string());
+        }
+        if self.plan_approved || self.allowlist.iter().any(|allowed| allowlist_matches(allowed, command)) {
+            return None;
+        }
+        Some("the plan was not approved and the command is not on the allowlist".to_string())
+    }
+}
+
+fn allowlist_matches(allowed: &str, command: &str) -> bool {
+    let command = command.trim();
+    let allowed = allowed.trim();
+    if command == allowed {
+        return true;
+    }
+    let Some(arguments) = command.strip_prefix(allowed).and_then(|rest| rest.strip_prefix(' ')) else {
+        return false;
+    };
+    !allowed.is_empty() && !arguments.contains(|c: char| SHELL_SYNTAX.contains(&c))
+}
+
+/// Characters that would let arguments run something other than the allowed command.
+const SHELL_SYNTAX: &[char] = &[';', '&', '|', '`', '$', '<', '>', '(', ')', '\n', '\r', '\\'];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl VerificationReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    pub fn skipped(&self) -> impl Iterator<Item = &CheckResult> {
+        self.checks.iter().filter(|check| check.skipped)
+    }
+
+    /// Describe each failing check with its output, for feeding back to the agent.
+    pub fn failure_summary(&self) -> String {
+        self.checks
+            .iter()
+            .filter(|check| !check.passed)
+            .map(|check| format!("- {}\n{}", check.criterion, check.output))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Longest stretch of command output kept per check.
+const MAX_OUTPUT_CHARS: usize = 4000;
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Run every criterion against the worktree, in order. Commands `policy` doesn't allow
+/// are skipped.
+pub async fn verify(criteria: &[AcceptanceCriterion], working_dir: &Path, policy: &CommandPolicy) -> VerificationReport {
+    let mut checks = Vec::with_capacity(criteria.len());
+    for criterion in criteria {
+        if let AcceptanceCriterion::Command { command } = criterion {
+            if let Some(reason) = policy.refusal(command) {
+                checks.push(CheckResult {
+                    criterion: criterion.clone(),
+                    passed: true,
+                    skipped: true,
+                    output: format!("Not run: {}", reason),
+                });
+                continue;
+            }
+        }
+
+        let (passed, output) = match criterion {
+            AcceptanceCriterion::Command { command } => run_command(command, working_dir).await,
+            AcceptanceCriterion::FileContains { path, pattern } => match resolve_in_worktree(working_dir, path) {
+                Some(file) => match tokio::fs::read_to_string(&file).await {
+                    Ok(contents) if contents.contains(pattern.as_str()) => (true, String::new()),
+                    Ok(_) => (false, format!("{} does not contain \"{}\"", path, pattern)),
+                    Err(e) => (false, format!("Failed to read {}: {}", path, e)),
+                },
+                None => (false, format!("{} is outside the session worktree", path)),
+            },
+            AcceptanceCriterion::FileExists { path } => match resolve_in_worktree(working_dir, path) {
+                Some(file) if file.exists() => (true, String::new()),
+                Some(_) => (false, format!("{} does not exist", path)),
+                None => (false, format!("{} is outside the session worktree", path)),
+            },
+        };
+
+        checks.push(CheckResult {
+            criterion: criterion.clone(),
+            passed,
+            skipped: false,
+            output,
+        });
+    }
+
+    VerificationReport { checks }
+}
+
+async fn run_command(command: &str, working_dir: &Path) -> (bool, String) {
+    let mut cmd = if cfg!(target_os = "windows") {
+        let mut cmd = tokio::process::Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    } else {
+        let mut cmd = tokio::process::Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    };
+    cmd.current_dir(working_dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    let output = match tokio::time::timeout(COMMAND_TIMEOUT, cmd.output()).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => return (false, format!("Failed to run `{}`: {}", command, e)),
+        Err(_) => return (false, format!("`{}` timed out after {}s", command, COMMAND_TIMEOUT.as_secs())),
+    };
+
+    let mut combined = String::from_utf8_lossy(&output.stdout).to_string();
+    combined.push_str(&String::from_utf8_lossy(&output.stderr));
+    (output.status.success(), tail(&combined, MAX_OUTPUT_CHARS))
+}
+
+/// Resolve a worktree-relative path, refusing anything that would escape the worktree.
+fn resolve_in_worktree(working_dir: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path);
+    let escapes = relative
+        .components()
+        .any(|c| matches!(c, Component::ParentDir | Component::RootDir | Component::Prefix(_)));
+    if escapes {
+        None
+    } else {
+        Some(working_dir.join(relative))
+    }
+}
+
+fn tail(text: &str, max_chars: usize) -> String {
+    let count = text.chars().count();
+    if count <= max_chars {
+        text.to_string()
+    } else {
+        format!("[...]{}", text.chars().skip(count - max_chars).collect::<String>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_verify_criteria() {
+        let approved = CommandPolicy {
+            process_spawn: true,
+            plan_approved: true,
+            allowlist: vec![],
+        };
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "status: done\n").unwrap();
+
+        let report = verify(
+            &[
+                AcceptanceCriterion::FileContains {
+                    path: "notes.txt".to_string(),
+                    pattern: "status: done".to_string(),
+                },
+                AcceptanceCriterion::FileExists { path: "notes.txt".to_string() },
+                AcceptanceCriterion::Command { command: "echo checked".to_string() },
+            ],
+            dir.path(),
+            &approved,
+        )
+        .await;
+        assert!(report.passed(), "{}", report.failure_summary());
+
+        let report = verify(
+            &[
+                AcceptanceCriterion::FileContains {
+                    path: "../notes.txt".to_string(),
+                    pattern: "status".to_string(),
+                },
+                AcceptanceCriterion::Command { command: "echo broken >&2; exit 3".to_string() },
+            ],
+            dir.path(),
+            &approved,
+        )
+        .await;
+        assert!(!report.passed());
+        assert!(report.checks.iter().all(|check| !check.passed));
+        assert!(report.failure_summary().contains("broken"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unpermitted_commands_are_skipped() {
+        let dir = TempDir::new().unwrap();
+        let criteria = [
+            AcceptanceCriterion::Command { command: "touch ran".to_string() },
+            AcceptanceCriterion::Command { command: "true --quiet; touch ran".to_string() },
+        ];
+        let unapproved = CommandPolicy {
+            process_spawn: true,
+            plan_approved: false,
+            allowlist: vec!["true".to_string()],
+        };
+
+        let report = verify(&criteria, dir.path(), &unapproved).await;
+        assert!(report.passed());
+        assert_eq!(report.skipped().count(), 2);
+        assert!(!dir.path().join("ran").exists());
+
+        let allowed = verify(
+            &[AcceptanceCriterion::Command { command: "true --quiet".to_string() }],
+            dir.path(),
+            &unapproved,
+        )
+        .await;
+        assert_eq!(allowed.skipped().count(), 0);
+
+        let no_spawn = CommandPolicy {
+            process_spawn: false,
+            plan_approved: true,
+            allowlist: vec!["touch ran".to_string()],
+        };
+        let report = verify(&criteria[..1], dir.path(), &no_spawn).await;
+        assert_eq!(report.skipped().count(), 1);
+        assert!(!dir.path().join("ran").exists());
+    }
+}